@@ -0,0 +1,72 @@
+//! Detection of commands which block the connection they are sent on until data arrives.
+//!
+//! A node connection is multiplexed, so such a command would delay every command queued behind
+//! it. They are sent on a connection of their own instead. `WAIT` and `WAITAOF` also block but
+//! only make sense on the connection which sent the writes they wait for, so they are left alone.
+
+use redis::{Arg, Cmd};
+
+use crate::command;
+
+// Commands which always block (at most for the timeout they are given)
+const BLOCKING: &[&str] = &[
+    "BLMOVE",
+    "BLMPOP",
+    "BLPOP",
+    "BRPOP",
+    "BRPOPLPUSH",
+    "BZMPOP",
+    "BZPOPMAX",
+    "BZPOPMIN",
+];
+
+// Commands which block when given the `BLOCK` option
+const BLOCKING_WITH_OPTION: &[&str] = &["XREAD", "XREADGROUP"];
+
+pub(crate) fn is_blocking(cmd: &Cmd) -> bool {
+    let mut args = cmd.args_iter().filter_map(|arg| match arg {
+        Arg::Simple(arg) => Some(arg),
+        Arg::Cursor => None,
+    });
+    let name = match args.next() {
+        Some(name) => name,
+        None => return false,
+    };
+    if command::is_one_of(name, BLOCKING) {
+        return true;
+    }
+    command::is_one_of(name, BLOCKING_WITH_OPTION)
+        && args
+            .take_while(|arg| !arg.eq_ignore_ascii_case(b"STREAMS"))
+            .any(|arg| arg.eq_ignore_ascii_case(b"BLOCK"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use redis::cmd;
+
+    #[test]
+    fn blocking_commands() {
+        assert!(is_blocking(cmd("BLPOP").arg("list").arg(0)));
+        assert!(is_blocking(cmd("bzpopmin").arg("zset").arg(1)));
+        assert!(is_blocking(
+            cmd("XREAD")
+                .arg("COUNT")
+                .arg(10)
+                .arg("block")
+                .arg(0)
+                .arg("STREAMS")
+                .arg("stream")
+                .arg("$")
+        ));
+
+        assert!(!is_blocking(cmd("LPOP").arg("list")));
+        assert!(!is_blocking(cmd("WAIT").arg(1).arg(0)));
+        // A stream named BLOCK does not make the read block
+        assert!(!is_blocking(
+            cmd("XREAD").arg("STREAMS").arg("BLOCK").arg("$")
+        ));
+    }
+}
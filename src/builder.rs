@@ -0,0 +1,226 @@
+//! A builder with the options and names of `redis::cluster::ClusterClientBuilder`, for code
+//! moving from the synchronous cluster client of redis-rs. Its parameters can not be read back,
+//! so the builder is mirrored rather than converted.
+
+use std::time::Duration;
+
+use redis::{ConnectionInfo, ErrorKind, IntoConnectionInfo, RedisError, RedisResult};
+
+use crate::{Client, ReadFromReplicaStrategy};
+
+/// Whether certificates are verified, like `redis::cluster::TlsMode`.
+#[cfg(feature = "tls")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TlsMode {
+    /// Verify the certificates of the nodes.
+    Secure,
+    /// Accept any certificate.
+    Insecure,
+}
+
+/// Builds a [`Client`] like `redis::cluster::ClusterClientBuilder` builds a `ClusterClient`,
+/// see [`Client::builder`].
+///
+/// ```rust
+/// use redis_cluster_async::Client;
+///
+/// # fn run() -> redis::RedisResult<()> {
+/// let client = Client::builder(vec!["redis://127.0.0.1:7000/"])
+///     .username("user".to_string())
+///     .password("secret".to_string())
+///     .retries(3)
+///     .read_from_replicas()
+///     .build()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct ClientBuilder {
+    initial_nodes: RedisResult<Vec<ConnectionInfo>>,
+    username: Option<String>,
+    password: Option<String>,
+    retries: Option<u32>,
+    #[cfg(feature = "tls")]
+    tls: Option<TlsMode>,
+    read_from_replicas: bool,
+    client_name: Option<String>,
+    connection_timeout: Option<Duration>,
+}
+
+impl ClientBuilder {
+    /// Create a builder for a client of the cluster reachable through `initial_nodes`.
+    pub fn new<T: IntoConnectionInfo>(initial_nodes: Vec<T>) -> ClientBuilder {
+        ClientBuilder {
+            initial_nodes: initial_nodes
+                .into_iter()
+                .map(IntoConnectionInfo::into_connection_info)
+                .collect(),
+            username: None,
+            password: None,
+            retries: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+            read_from_replicas: false,
+            client_name: None,
+            connection_timeout: None,
+        }
+    }
+
+    /// Build the client.
+    ///
+    /// # Errors
+    ///
+    /// Like [`Client::open`], and if the initial nodes use different credentials while none
+    /// are set on the builder.
+    pub fn build(self) -> RedisResult<Client> {
+        let mut nodes = self.initial_nodes?;
+        // Like the synchronous client, the nodes otherwise use the credentials of the first node
+        if let Some(first) = nodes.first() {
+            let differ = |field: fn(&ConnectionInfo) -> &Option<String>| {
+                nodes.iter().any(|node| field(node) != field(first))
+            };
+            if (self.username.is_none() && differ(|node| &node.redis.username))
+                || (self.password.is_none() && differ(|node| &node.redis.password))
+            {
+                return Err(RedisError::from((
+                    ErrorKind::InvalidClientConfig,
+                    "Cannot use different credentials among initial nodes.",
+                )));
+            }
+        }
+        for node in &mut nodes {
+            if let Some(username) = &self.username {
+                node.redis.username = Some(username.clone());
+            }
+            if let Some(password) = &self.password {
+                node.redis.password = Some(password.clone());
+            }
+        }
+
+        let mut client = Client::open(nodes)?;
+        #[cfg(feature = "tls")]
+        if let Some(tls) = self.tls {
+            client.set_tls(tls);
+        }
+        if let Some(retries) = self.retries {
+            client.set_retries(Some(retries));
+        }
+        if self.read_from_replicas {
+            client.set_read_from_replicas(ReadFromReplicaStrategy::PreferReplica);
+        }
+        client.params.client_name = self.client_name;
+        client.set_connection_timeout(self.connection_timeout);
+        Ok(client)
+    }
+
+    /// Authenticate with `password` on every node, instead of the password of the URLs.
+    pub fn password(mut self, password: String) -> ClientBuilder {
+        self.password = Some(password);
+        self
+    }
+
+    /// Authenticate as `username` on every node, instead of the user of the URLs.
+    pub fn username(mut self, username: String) -> ClientBuilder {
+        self.username = Some(username);
+        self
+    }
+
+    /// Retry a command at most `retries` times. Default: 16
+    pub fn retries(mut self, retries: u32) -> ClientBuilder {
+        self.retries = Some(retries);
+        self
+    }
+
+    /// Connect to every node over TLS even if the URLs are `redis://`, see [`Client::set_tls`].
+    /// Default: the scheme of the URLs
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, tls: TlsMode) -> ClientBuilder {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Send read-only commands to replicas, see
+    /// [`ConnectionConfig::read_from_replicas`](crate::ConnectionConfig::read_from_replicas).
+    pub fn read_from_replicas(mut self) -> ClientBuilder {
+        self.read_from_replicas = true;
+        self
+    }
+
+    /// Name every node connection with `CLIENT SETNAME`, see
+    /// [`ConnectionConfig::client_name`](crate::ConnectionConfig::client_name).
+    pub fn client_name(mut self, name: String) -> ClientBuilder {
+        self.client_name = Some(name);
+        self
+    }
+
+    /// Give up connecting to a node after `timeout`, see
+    /// [`Client::set_connection_timeout`].
+    pub fn connection_timeout(mut self, timeout: Duration) -> ClientBuilder {
+        self.connection_timeout = Some(timeout);
+        self
+    }
+
+    /// Use `build()`.
+    #[deprecated(note = "Use build()")]
+    pub fn open(self) -> RedisResult<Client> {
+        self.build()
+    }
+
+    /// Use `read_from_replicas()`.
+    #[deprecated(note = "Use read_from_replicas()")]
+    pub fn readonly(mut self, read_from_replicas: bool) -> ClientBuilder {
+        self.read_from_replicas = read_from_replicas;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "tls")]
+    use redis::ConnectionAddr;
+
+    use super::*;
+
+    #[test]
+    fn options_are_applied() {
+        let client = ClientBuilder::new(vec!["redis://a:7000", "redis://b:7000"])
+            .username("user".into())
+            .password("secret".into())
+            .retries(3)
+            .read_from_replicas()
+            .client_name("worker".into())
+            .connection_timeout(Duration::from_secs(1))
+            .build()
+            .unwrap();
+        for node in &client.initial_nodes {
+            assert_eq!(node.redis.username.as_deref(), Some("user"));
+            assert_eq!(node.redis.password.as_deref(), Some("secret"));
+        }
+        assert_eq!(client.params.retry.max_retries, Some(3));
+        assert!(client.params.read_from_replicas());
+        assert_eq!(client.params.client_name.as_deref(), Some("worker"));
+        assert_eq!(
+            client.params.connection_timeout,
+            Some(Duration::from_secs(1))
+        );
+
+        let err = ClientBuilder::new(vec!["redis://:one@a:7000", "redis://:two@b:7000"])
+            .build()
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidClientConfig);
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn tls_is_applied() {
+        let client = ClientBuilder::new(vec!["redis://a:7000", "redis://b:7000"])
+            .tls(TlsMode::Insecure)
+            .build()
+            .unwrap();
+        for node in &client.initial_nodes {
+            assert!(matches!(
+                node.addr,
+                ConnectionAddr::TcpTls { insecure: true, .. }
+            ));
+        }
+    }
+}
@@ -0,0 +1,213 @@
+//! Values too large to be held in one buffer, written and read a chunk at a time through
+//! [`AsyncWrite`] and [`AsyncRead`].
+//!
+//! A value is a single string key, built with `APPEND` and read back with `GETRANGE`, so it can
+//! also be read or written with the usual commands and is limited to the 512MB of a string.
+
+use std::{
+    io, mem,
+    pin::Pin,
+    task::{self, Poll},
+};
+
+use futures::{
+    future::{BoxFuture, FutureExt},
+    io::{AsyncRead, AsyncWrite},
+};
+use redis::{aio::ConnectionLike, RedisError, RedisResult};
+
+use crate::Connection;
+
+const DEFAULT_CHUNK_SIZE: usize = 1024 * 1024;
+
+impl<C> Connection<C>
+where
+    C: ConnectionLike + Send + 'static,
+{
+    /// Returns a writer replacing the value of `key` with what is written to it, sent in chunks
+    /// of 1MB by default (see [`ValueWriter::chunk_size`]). The value is only complete once the
+    /// writer is closed, readers of the key see the part written so far until then.
+    ///
+    /// ```rust,no_run
+    /// use futures::io::AsyncWriteExt;
+    ///
+    /// # async fn run(connection: redis_cluster_async::Connection) -> std::io::Result<()> {
+    /// let mut file = futures::io::Cursor::new(vec![0; 300 * 1024 * 1024]);
+    /// let mut writer = connection.value_writer("backup");
+    /// futures::io::copy(&mut file, &mut writer).await?;
+    /// writer.close().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn value_writer(&self, key: impl AsRef<[u8]>) -> ValueWriter<C> {
+        ValueWriter {
+            connection: self.clone(),
+            key: key.as_ref().to_vec(),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            buffer: Vec::new(),
+            started: false,
+            pending: None,
+        }
+    }
+
+    /// Returns a reader of the value of `key`, fetched in chunks of 1MB by default (see
+    /// [`ValueReader::chunk_size`]). A missing key reads as an empty value.
+    pub fn value_reader(&self, key: impl AsRef<[u8]>) -> ValueReader<C> {
+        ValueReader {
+            connection: self.clone(),
+            key: key.as_ref().to_vec(),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            offset: 0,
+            chunk: Vec::new(),
+            read: 0,
+            done: false,
+            pending: None,
+        }
+    }
+}
+
+/// Writes a value in chunks, see [`Connection::value_writer`].
+pub struct ValueWriter<C = redis::aio::MultiplexedConnection> {
+    connection: Connection<C>,
+    key: Vec<u8>,
+    chunk_size: usize,
+    // What was written since the last chunk was sent
+    buffer: Vec<u8>,
+    // Whether the first chunk, replacing the previous value, was sent
+    started: bool,
+    pending: Option<BoxFuture<'static, RedisResult<()>>>,
+}
+
+impl<C> ValueWriter<C> {
+    /// Number of bytes sent with each command. Default: 1MB
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+}
+
+impl<C> ValueWriter<C>
+where
+    C: ConnectionLike + Send + 'static,
+{
+    // Sends the buffered bytes, replacing the value with the first chunk
+    fn send(&mut self) {
+        let chunk = mem::take(&mut self.buffer);
+        let mut cmd = redis::cmd(if self.started { "APPEND" } else { "SET" });
+        cmd.arg(&self.key[..]).arg(chunk);
+        self.started = true;
+        let mut connection = self.connection.clone();
+        self.pending = Some(async move { cmd.query_async::<_, ()>(&mut connection).await }.boxed());
+    }
+
+    // Waits for the chunk being sent, if any
+    fn poll_pending(&mut self, cx: &mut task::Context) -> Poll<io::Result<()>> {
+        if let Some(pending) = &mut self.pending {
+            let result = futures::ready!(pending.as_mut().poll(cx));
+            self.pending = None;
+            result.map_err(to_io_error)?;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<C> AsyncWrite for ValueWriter<C>
+where
+    C: ConnectionLike + Send + 'static,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        futures::ready!(this.poll_pending(cx))?;
+        if this.buffer.len() >= this.chunk_size {
+            this.send();
+            futures::ready!(this.poll_pending(cx))?;
+        }
+        let written = buf.len().min(this.chunk_size - this.buffer.len());
+        this.buffer.extend_from_slice(&buf[..written]);
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut task::Context) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        futures::ready!(this.poll_pending(cx))?;
+        if !this.buffer.is_empty() {
+            this.send();
+        }
+        this.poll_pending(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut task::Context) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        futures::ready!(this.poll_pending(cx))?;
+        // An empty value still replaces the previous one
+        if !this.buffer.is_empty() || !this.started {
+            this.send();
+        }
+        this.poll_pending(cx)
+    }
+}
+
+/// Reads a value in chunks, see [`Connection::value_reader`].
+pub struct ValueReader<C = redis::aio::MultiplexedConnection> {
+    connection: Connection<C>,
+    key: Vec<u8>,
+    chunk_size: usize,
+    // Offset in the value of the next chunk
+    offset: usize,
+    chunk: Vec<u8>,
+    // How much of `chunk` was read
+    read: usize,
+    // Whether the last chunk was fetched
+    done: bool,
+    pending: Option<BoxFuture<'static, RedisResult<Vec<u8>>>>,
+}
+
+impl<C> ValueReader<C> {
+    /// Number of bytes fetched with each command. Default: 1MB
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+}
+
+impl<C> AsyncRead for ValueReader<C>
+where
+    C: ConnectionLike + Send + 'static,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        while this.read == this.chunk.len() && !this.done {
+            if this.pending.is_none() {
+                let mut cmd = redis::cmd("GETRANGE");
+                cmd.arg(&this.key[..])
+                    .arg(this.offset)
+                    .arg(this.offset + this.chunk_size - 1);
+                let mut connection = this.connection.clone();
+                this.pending = Some(async move { cmd.query_async(&mut connection).await }.boxed());
+            }
+            let pending = this.pending.as_mut().unwrap();
+            let chunk = futures::ready!(pending.as_mut().poll(cx));
+            this.pending = None;
+            this.chunk = chunk.map_err(to_io_error)?;
+            this.read = 0;
+            this.offset += this.chunk.len();
+            this.done = this.chunk.len() < this.chunk_size;
+        }
+        let read = buf.len().min(this.chunk.len() - this.read);
+        buf[..read].copy_from_slice(&this.chunk[this.read..this.read + read]);
+        this.read += read;
+        Poll::Ready(Ok(read))
+    }
+}
+
+fn to_io_error(err: RedisError) -> io::Error {
+    io::Error::other(err)
+}
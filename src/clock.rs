@@ -0,0 +1,31 @@
+use std::time::{Duration, Instant};
+
+use futures::future::{BoxFuture, FutureExt};
+
+/// Source of time for every time-based decision made by a cluster connection (retry backoff,
+/// timeouts, refresh intervals).
+///
+/// The default, [`TokioClock`], uses the tokio timer. Tests can install their own implementation
+/// with [`Client::set_clock`](crate::Client::set_clock) to drive retry schedules with simulated
+/// time instead of waiting for real sleeps.
+pub trait Clock: Send + Sync + 'static {
+    /// Returns the current instant.
+    fn now(&self) -> Instant;
+
+    /// Returns a future which completes once `duration` has elapsed.
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+}
+
+/// A [`Clock`] backed by the tokio runtime.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TokioClock;
+
+impl Clock for TokioClock {
+    fn now(&self) -> Instant {
+        tokio::time::Instant::now().into_std()
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        tokio::time::sleep(duration).boxed()
+    }
+}
@@ -0,0 +1,44 @@
+//! Lookups of the name of a command. They run for every command sent, so they compare the name
+//! in place rather than allocating an uppercased copy of it.
+
+use redis::{Arg, Cmd};
+
+// The first argument of `cmd`, `None` if it has none
+pub(crate) fn name(cmd: &Cmd) -> Option<&[u8]> {
+    match cmd.args_iter().next() {
+        Some(Arg::Simple(name)) => Some(name),
+        _ => None,
+    }
+}
+
+// Whether `name` is one of `names`, ignoring case
+pub(crate) fn is_one_of(name: &[u8], names: &[&str]) -> bool {
+    names
+        .iter()
+        .any(|candidate| candidate.as_bytes().eq_ignore_ascii_case(name))
+}
+
+// `name` in upper case, for messages and logs
+pub(crate) fn display(name: &[u8]) -> String {
+    String::from_utf8_lossy(name).to_ascii_uppercase()
+}
+
+// The name of `cmd` in upper case, empty if it has none
+pub(crate) fn display_name(cmd: &Cmd) -> String {
+    name(cmd).map(display).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        let cmd = redis::cmd("mGet").arg("a").clone();
+        assert_eq!(name(&cmd), Some(&b"mGet"[..]));
+        assert!(is_one_of(b"mGet", &["GET", "MGET"]));
+        assert!(!is_one_of(b"mGet", &["MSET"]));
+        assert_eq!(display_name(&cmd), "MGET");
+        assert_eq!(display_name(&redis::Cmd::new()), "");
+    }
+}
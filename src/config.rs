@@ -0,0 +1,128 @@
+use std::time::Duration;
+
+use crate::{ClusterParams, ReadFromReplicaStrategy};
+
+/// Options of a single connection, see [`Client::get_connection_with`](crate::Client::get_connection_with).
+///
+/// Options which are not set keep the value configured on the [`Client`](crate::Client), so one
+/// client can hand out connections which behave differently, e.g. one reading from replicas next
+/// to one which only talks to masters.
+///
+/// ```rust,no_run
+/// use std::time::Duration;
+///
+/// use redis_cluster_async::{Client, ConnectionConfig};
+///
+/// # async fn run() -> redis::RedisResult<()> {
+/// let client = Client::open(vec!["redis://127.0.0.1:7000/"])?;
+/// let writes = client.get_connection().await?;
+/// let reports = client
+///     .get_connection_with(
+///         ConnectionConfig::new()
+///             .client_name("reports")
+///             .read_from_replicas(true)
+///             .response_timeout(Duration::from_secs(5)),
+///     )
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConnectionConfig {
+    client_name: Option<String>,
+    read_strategy: Option<ReadFromReplicaStrategy>,
+    read_only: Option<bool>,
+    ordered: Option<bool>,
+    response_timeout: Option<Duration>,
+}
+
+impl ConnectionConfig {
+    /// Create a config which keeps every option of the client.
+    pub fn new() -> Self {
+        ConnectionConfig::default()
+    }
+
+    /// Name the node connections with `CLIENT SETNAME`, so they can be told apart in
+    /// `CLIENT LIST`.
+    pub fn client_name(mut self, name: impl Into<String>) -> Self {
+        self.client_name = Some(name.into());
+        self
+    }
+
+    /// Send read-only commands to a replica of their slot, when it has one. Node connections
+    /// are put in `READONLY` mode so replicas serve the reads instead of redirecting them. The
+    /// replica may lag behind its master, so a read may not see a write which just succeeded.
+    ///
+    /// Transactions (atomic pipelines) made of read-only commands and the read-only scripting
+    /// commands (`EVAL_RO`, `EVALSHA_RO`, `FCALL_RO`) are reads as well, as long as their keys
+    /// share a slot.
+    ///
+    /// Same as `read_strategy(ReadFromReplicaStrategy::PreferReplica)`, or
+    /// `read_strategy(ReadFromReplicaStrategy::MasterOnly)` when `false`.
+    pub fn read_from_replicas(mut self, read_from_replicas: bool) -> Self {
+        self.read_strategy = Some(if read_from_replicas {
+            ReadFromReplicaStrategy::PreferReplica
+        } else {
+            ReadFromReplicaStrategy::MasterOnly
+        });
+        self
+    }
+
+    /// Send read-only commands to the nodes picked by `strategy`, like
+    /// [`ConnectionConfig::read_from_replicas`]. See [`ReadFromReplicaStrategy`].
+    pub fn read_strategy(mut self, strategy: ReadFromReplicaStrategy) -> Self {
+        self.read_strategy = Some(strategy);
+        self
+    }
+
+    /// Reject every command which is not known to only read, without sending it, and send the
+    /// others to replicas like [`ConnectionConfig::read_from_replicas`].
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = Some(read_only);
+        self
+    }
+
+    /// Keep the commands for a slot in the order they were sent in even when some of them are
+    /// retried: a command is only sent once every command sent before it for the same slot got
+    /// its response. Commands for different slots are still sent concurrently.
+    ///
+    /// Without it commands for the same slot are sent in order too, but one which is redirected
+    /// or retried is sent again after the commands which followed it.
+    pub fn ordered(mut self, ordered: bool) -> Self {
+        self.ordered = Some(ordered);
+        self
+    }
+
+    /// Fail attempts to send a command which do not get a response within `timeout` with a timed
+    /// out I/O error. As the command may still have been executed, a timed out attempt is only
+    /// retried if the command is safe to send twice: reads, blind writes with
+    /// [`Client::set_retry_blind_writes`](crate::Client::set_retry_blind_writes) and commands
+    /// sent through [`Connection::retryable`](crate::Connection::retryable).
+    pub fn response_timeout(mut self, timeout: Duration) -> Self {
+        self.response_timeout = Some(timeout);
+        self
+    }
+
+    pub(crate) fn apply(&self, params: &ClusterParams) -> ClusterParams {
+        let mut params = params.clone();
+        if let Some(name) = &self.client_name {
+            params.client_name = Some(name.clone());
+        }
+        if let Some(strategy) = self.read_strategy {
+            params.read_strategy = strategy;
+        }
+        if let Some(read_only) = self.read_only {
+            params.read_only = read_only;
+            if read_only && params.read_strategy == ReadFromReplicaStrategy::MasterOnly {
+                params.read_strategy = ReadFromReplicaStrategy::PreferReplica;
+            }
+        }
+        if let Some(ordered) = self.ordered {
+            params.ordered = ordered;
+        }
+        if let Some(timeout) = self.response_timeout {
+            params.response_timeout = Some(timeout);
+        }
+        params
+    }
+}
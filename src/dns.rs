@@ -0,0 +1,328 @@
+use std::{
+    io,
+    net::IpAddr,
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, Instant},
+};
+
+use ahash::HashMap;
+use redis::{ConnectionAddr, ConnectionInfo};
+
+use crate::{Clock, Runtime};
+
+struct Entry {
+    addrs: Vec<IpAddr>,
+    resolved_at: Instant,
+    refreshing: bool,
+}
+
+/// Caches the resolved addresses of node hostnames.
+///
+/// Fresh entries are returned directly. Once an entry is older than the TTL it is still returned,
+/// but a lookup is started in the background so the next connection sees the new addresses. DNS
+/// changes are therefore picked up at most one TTL (plus one lookup) after they happen.
+pub(crate) struct DnsCache {
+    ttl: Duration,
+    entries: Arc<Mutex<HashMap<(String, u16), Entry>>>,
+}
+
+impl DnsCache {
+    pub(crate) fn new(ttl: Duration) -> Self {
+        DnsCache {
+            ttl,
+            entries: Default::default(),
+        }
+    }
+
+    pub(crate) async fn resolve(
+        &self,
+        host: &str,
+        port: u16,
+        clock: &Arc<dyn Clock>,
+        runtime: &Arc<dyn Runtime>,
+    ) -> io::Result<IpAddr> {
+        let key = (host.to_string(), port);
+        let now = clock.now();
+        let stale = {
+            let mut entries = self.entries.lock().unwrap();
+            match entries.get_mut(&key) {
+                Some(entry) if now.saturating_duration_since(entry.resolved_at) < self.ttl => {
+                    return Ok(entry.addrs[0]);
+                }
+                Some(entry) => {
+                    let addr = entry.addrs[0];
+                    let refresh = !entry.refreshing;
+                    entry.refreshing = true;
+                    Some((addr, refresh))
+                }
+                None => None,
+            }
+        };
+        match stale {
+            Some((addr, refresh)) => {
+                if refresh {
+                    let entries = self.entries.clone();
+                    let clock = clock.clone();
+                    let lookup = lookup(runtime, &key.0, key.1);
+                    runtime.spawn(Box::pin(async move {
+                        let result = lookup.await;
+                        let mut entries = entries.lock().unwrap();
+                        match result {
+                            Ok(addrs) => {
+                                entries.insert(
+                                    key,
+                                    Entry {
+                                        addrs,
+                                        resolved_at: clock.now(),
+                                        refreshing: false,
+                                    },
+                                );
+                            }
+                            // Keep serving the stale addresses and try again on the next use
+                            Err(_) => {
+                                if let Some(entry) = entries.get_mut(&key) {
+                                    entry.refreshing = false;
+                                }
+                            }
+                        }
+                    }));
+                }
+                Ok(addr)
+            }
+            None => {
+                let addrs = lookup(runtime, host, port).await?;
+                let addr = addrs[0];
+                self.entries.lock().unwrap().insert(
+                    key,
+                    Entry {
+                        addrs,
+                        resolved_at: clock.now(),
+                        refreshing: false,
+                    },
+                );
+                Ok(addr)
+            }
+        }
+    }
+}
+
+/// Replaces every plain TCP seed whose hostname resolves to several addresses by one seed per
+/// address, so that each of them is tried during discovery. Seeds which fail to resolve are kept
+/// as they are, so connecting to them reports the error.
+pub(crate) async fn expand_seeds(
+    seeds: &[ConnectionInfo],
+    runtime: &Arc<dyn Runtime>,
+) -> Vec<ConnectionInfo> {
+    let mut expanded = Vec::with_capacity(seeds.len());
+    for seed in seeds {
+        let (host, port) = match &seed.addr {
+            ConnectionAddr::Tcp(host, port) if host.parse::<IpAddr>().is_err() => (host, *port),
+            _ => {
+                expanded.push(seed.clone());
+                continue;
+            }
+        };
+        let mut addrs = lookup(runtime, host, port).await.unwrap_or_default();
+        let mut seen = Vec::new();
+        addrs.retain(|addr| {
+            let new = !seen.contains(addr);
+            seen.push(*addr);
+            new
+        });
+        if addrs.len() < 2 {
+            expanded.push(seed.clone());
+            continue;
+        }
+        expanded.extend(addrs.into_iter().map(|addr| ConnectionInfo {
+            addr: ConnectionAddr::Tcp(addr.to_string(), port),
+            redis: seed.redis.clone(),
+        }));
+    }
+    expanded
+}
+
+/// Resolves the hostnames of `originals` every `interval` and replaces `seeds` with `originals`
+/// followed by one seed per resolved address. A hostname which fails to resolve keeps the
+/// addresses it resolved to last. Stops once `seeds` is dropped.
+pub(crate) fn spawn_seed_resolution(
+    originals: Vec<ConnectionInfo>,
+    seeds: Weak<Mutex<Vec<ConnectionInfo>>>,
+    interval: Duration,
+    clock: Arc<dyn Clock>,
+    runtime: Arc<dyn Runtime>,
+) {
+    let lookups = runtime.clone();
+    runtime.spawn(Box::pin(async move {
+        let mut resolved = HashMap::<(String, u16), Vec<IpAddr>>::default();
+        loop {
+            clock.sleep(interval).await;
+            if seeds.strong_count() == 0 {
+                break;
+            }
+            for seed in &originals {
+                if let ConnectionAddr::Tcp(host, port) = &seed.addr {
+                    if host.parse::<IpAddr>().is_err() {
+                        if let Ok(addrs) = lookup(&lookups, host, *port).await {
+                            resolved.insert((host.clone(), *port), addrs);
+                        }
+                    }
+                }
+            }
+            let mut merged = Vec::with_capacity(originals.len());
+            for seed in &originals {
+                merged.push(seed.clone());
+                if let ConnectionAddr::Tcp(host, port) = &seed.addr {
+                    for addr in resolved.get(&(host.clone(), *port)).into_iter().flatten() {
+                        merged.push(ConnectionInfo {
+                            addr: ConnectionAddr::Tcp(addr.to_string(), *port),
+                            redis: seed.redis.clone(),
+                        });
+                    }
+                }
+            }
+            match seeds.upgrade() {
+                Some(seeds) => *seeds.lock().unwrap() = merged,
+                None => break,
+            }
+        }
+    }));
+}
+
+fn lookup(
+    runtime: &Arc<dyn Runtime>,
+    host: &str,
+    port: u16,
+) -> impl std::future::Future<Output = io::Result<Vec<IpAddr>>> + Send + 'static {
+    let host = host.to_string();
+    let lookup = runtime.lookup_host(host.clone(), port);
+    async move {
+        let addrs = lookup.await?;
+        if addrs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No addresses found for `{}`", host),
+            ));
+        }
+        Ok(addrs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::future::BoxFuture;
+
+    use crate::{TokioClock, TokioRuntime};
+
+    // Resolves every hostname to 10.0.0.1, counting the lookups
+    #[derive(Default)]
+    struct StaticRuntime {
+        lookups: std::sync::atomic::AtomicUsize,
+    }
+
+    impl Runtime for StaticRuntime {
+        fn spawn(&self, task: BoxFuture<'static, ()>) {
+            tokio::spawn(task);
+        }
+
+        fn lookup_host(
+            &self,
+            _host: String,
+            _port: u16,
+        ) -> BoxFuture<'static, io::Result<Vec<IpAddr>>> {
+            self.lookups
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Box::pin(async { Ok(vec![IpAddr::from([10, 0, 0, 1])]) })
+        }
+    }
+
+    #[tokio::test]
+    async fn caches_resolved_addresses() {
+        let clock: Arc<dyn Clock> = Arc::new(TokioClock);
+        let runtime: Arc<dyn Runtime> = Arc::new(TokioRuntime);
+        let cache = DnsCache::new(Duration::from_secs(60));
+        let addr = cache
+            .resolve("127.0.0.1", 7000, &clock, &runtime)
+            .await
+            .unwrap();
+        assert_eq!(addr, IpAddr::from([127, 0, 0, 1]));
+
+        let entries = cache.entries.lock().unwrap();
+        let entry = &entries[&("127.0.0.1".to_string(), 7000)];
+        assert_eq!(entry.addrs, vec![addr]);
+    }
+
+    #[tokio::test]
+    async fn seed_resolution_merges_addresses() {
+        let original = ConnectionInfo {
+            addr: ConnectionAddr::Tcp("cluster.example.com".into(), 7000),
+            redis: Default::default(),
+        };
+        let seeds = Arc::new(Mutex::new(vec![original.clone()]));
+        spawn_seed_resolution(
+            vec![original.clone()],
+            Arc::downgrade(&seeds),
+            Duration::from_millis(1),
+            Arc::new(TokioClock),
+            Arc::new(StaticRuntime::default()),
+        );
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while seeds.lock().unwrap().len() == 1 {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        let seeds = seeds.lock().unwrap();
+        assert_eq!(seeds[0].addr, original.addr);
+        assert!(seeds[1..]
+            .iter()
+            .any(|seed| seed.addr == ConnectionAddr::Tcp("10.0.0.1".into(), 7000)));
+    }
+
+    #[tokio::test]
+    async fn expand_seeds_keeps_addresses_and_tls_hosts() {
+        let seeds = vec![
+            ConnectionInfo {
+                addr: ConnectionAddr::Tcp("127.0.0.1".into(), 7000),
+                redis: Default::default(),
+            },
+            ConnectionInfo {
+                addr: ConnectionAddr::TcpTls {
+                    host: "cluster.example.com".into(),
+                    port: 7001,
+                    insecure: false,
+                },
+                redis: Default::default(),
+            },
+        ];
+        let expanded = expand_seeds(&seeds, &(Arc::new(TokioRuntime) as Arc<dyn Runtime>)).await;
+        assert_eq!(
+            expanded.iter().map(|seed| &seed.addr).collect::<Vec<_>>(),
+            seeds.iter().map(|seed| &seed.addr).collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn lookups_go_through_the_runtime() {
+        let clock: Arc<dyn Clock> = Arc::new(TokioClock);
+        let static_runtime = Arc::new(StaticRuntime::default());
+        let runtime: Arc<dyn Runtime> = static_runtime.clone();
+        let cache = DnsCache::new(Duration::from_secs(60));
+        for _ in 0..2 {
+            let addr = cache
+                .resolve("node.example.com", 7000, &clock, &runtime)
+                .await
+                .unwrap();
+            assert_eq!(addr, IpAddr::from([10, 0, 0, 1]));
+        }
+        assert_eq!(
+            static_runtime
+                .lookups
+                .load(std::sync::atomic::Ordering::SeqCst),
+            1
+        );
+    }
+}
@@ -0,0 +1,89 @@
+//! Context added to the errors of commands, so that an error names the node, slot and command
+//! which failed.
+
+use std::io;
+
+use redis::{ErrorKind, RedisError};
+
+// Appends `context` to an I/O or server error, keeping everything the caller (and the retry logic)
+// may look at: the error kind, the server error code and the kind of the I/O error. Errors raised
+// by the client itself already describe what went wrong and are returned unchanged.
+pub(crate) fn with_context(err: RedisError, context: &str) -> RedisError {
+    if err.is_io_error() {
+        // The I/O error itself can not be reached, but callers can only tell I/O errors apart by
+        // these predicates anyway
+        let kind = if err.is_connection_refusal() {
+            io::ErrorKind::ConnectionRefused
+        } else if err.is_connection_dropped() {
+            io::ErrorKind::ConnectionReset
+        } else if err.is_timeout() {
+            io::ErrorKind::TimedOut
+        } else {
+            io::ErrorKind::Other
+        };
+        return io::Error::new(kind, format!("{} ({})", err, context)).into();
+    }
+
+    let code = match err.code() {
+        Some(code) if is_server_error(err.kind()) => code,
+        _ => return err,
+    };
+    // Parse the error again like it was received, which is the only way to build extension errors
+    let line = match err.detail() {
+        Some(detail) => format!("-{} {} ({})\r\n", code, detail, context),
+        None => format!("-{} ({})\r\n", code, context),
+    };
+    match redis::parse_redis_value(line.as_bytes()) {
+        Err(enriched) => enriched,
+        Ok(_) => err,
+    }
+}
+
+fn is_server_error(kind: ErrorKind) -> bool {
+    !matches!(
+        kind,
+        ErrorKind::AuthenticationFailed
+            | ErrorKind::TypeError
+            | ErrorKind::InvalidClientConfig
+            | ErrorKind::IoError
+            | ErrorKind::ClientError
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn context_keeps_the_error_kind() {
+        let context = "node a:6379, slot 1, command GET";
+
+        let err = with_context(
+            io::Error::from(io::ErrorKind::ConnectionRefused).into(),
+            context,
+        );
+        assert!(err.is_connection_refusal());
+        assert!(err
+            .to_string()
+            .ends_with("(node a:6379, slot 1, command GET)"));
+
+        let err = redis::parse_redis_value(b"-MOVED 1 b:6379\r\n").unwrap_err();
+        let err = with_context(err, context);
+        assert_eq!(err.kind(), ErrorKind::Moved);
+        assert_eq!(
+            err.detail(),
+            Some("1 b:6379 (node a:6379, slot 1, command GET)")
+        );
+
+        let err = redis::parse_redis_value(b"-WRONGTYPE Operation against a key\r\n").unwrap_err();
+        let err = with_context(err, context);
+        assert_eq!(err.code(), Some("WRONGTYPE"));
+        assert_eq!(
+            err.detail(),
+            Some("Operation against a key (node a:6379, slot 1, command GET)")
+        );
+
+        let err = RedisError::from((ErrorKind::ClientError, "client"));
+        assert_eq!(with_context(err, context).to_string(), "client");
+    }
+}
@@ -0,0 +1,36 @@
+//! Failpoints in the driver, for forcing rare failures in tests. Enabled by the `failpoints`
+//! feature and configured with the [`fail`](https://docs.rs/fail) crate, e.g.
+//! `fail::cfg("redis_cluster_async::connect", "return")`.
+//!
+//! The failpoints only react to the `return` action (`sleep`, `pause`, ... behave as usual). An
+//! argument given to it, as in `return(10.0.0.1:7000)`, restricts the failpoint to the nodes whose
+//! `host:port` contains it, so tests running in parallel against different nodes do not affect
+//! each other.
+//!
+//! | Name | Fails |
+//! |------|-------|
+//! | [`CONNECT`] | connecting to a node, with a connection refused error |
+//! | [`SLOTS`] | querying a node for the slot map, on startup and on refreshes |
+//! | [`REDIRECT`] | a successful command, with a `MOVED` to the same node |
+//! | [`RECONNECT`] | the check of an existing connection while refreshing, so it is replaced |
+
+/// Connecting to a node fails.
+pub const CONNECT: &str = "redis_cluster_async::connect";
+
+/// `CLUSTER SLOTS` fails.
+pub const SLOTS: &str = "redis_cluster_async::slots";
+
+/// A successful command is answered with a `MOVED` redirection instead.
+pub const REDIRECT: &str = "redis_cluster_async::redirect";
+
+/// An existing connection is treated as dead while refreshing the slots.
+pub const RECONNECT: &str = "redis_cluster_async::reconnect";
+
+// Whether the failpoint `name` returns for the node `node` (`host:port`)
+pub(crate) fn fires(name: &str, node: &str) -> bool {
+    fail::eval(
+        name,
+        |arg| !matches!(arg, Some(arg) if !node.contains(&arg)),
+    )
+    .unwrap_or(false)
+}
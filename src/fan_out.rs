@@ -0,0 +1,594 @@
+//! Commands which have to be sent to every node of the cluster.
+
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    iter::Sum,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use futures::future;
+use log::warn;
+use redis::{
+    aio::ConnectionLike, from_redis_value, Cmd, ErrorKind, FromRedisValue, RedisError, RedisResult,
+    Script, Value,
+};
+
+use crate::{Connection, Feature, NodeRole, Route};
+
+impl<C> Connection<C>
+where
+    C: ConnectionLike + Send + 'static,
+{
+    /// Sends `cmd` to every node, masters and replicas, and returns the reply of each node keyed by
+    /// `host:port`. The nodes are those of the last discovered topology.
+    pub async fn execute_on_all_nodes(&self, cmd: &Cmd) -> HashMap<String, RedisResult<Value>> {
+        self.fan_out(None, cmd).await
+    }
+
+    /// Sends `cmd` to every master, see [`Connection::execute_on_all_nodes`].
+    pub async fn execute_on_all_masters(&self, cmd: &Cmd) -> HashMap<String, RedisResult<Value>> {
+        self.fan_out(Some(NodeRole::Master), cmd).await
+    }
+
+    /// Sends `cmd` to every replica, see [`Connection::execute_on_all_nodes`]. Note that replicas
+    /// redirect commands accessing keys unless `READONLY` was sent on the connection first.
+    pub async fn execute_on_all_replicas(&self, cmd: &Cmd) -> HashMap<String, RedisResult<Value>> {
+        self.fan_out(Some(NodeRole::Replica), cmd).await
+    }
+
+    /// Runs `DBSIZE` on every master and returns the number of keys of the cluster.
+    ///
+    /// Fails with the error of the first failed master (ordered by `host:port`) if any master
+    /// failed.
+    pub async fn dbsize(&self) -> RedisResult<u64> {
+        self.fan_out(Some(NodeRole::Master), &redis::cmd("DBSIZE"))
+            .await
+            .sum()
+    }
+
+    /// Runs `FLUSHDB` on every master, deleting every key of the cluster, and returns the outcome
+    /// for each master, keyed by `host:port`. The replicas follow their master.
+    pub async fn flushdb(&self) -> HashMap<String, RedisResult<()>> {
+        self.fan_out(Some(NodeRole::Master), &redis::cmd("FLUSHDB"))
+            .await
+    }
+
+    /// Loads `script` on every node, masters and replicas, so that `EVALSHA` finds it wherever it
+    /// is routed, and returns the outcome for each node keyed by `host:port`. Nodes restarted or
+    /// added later do not have it, it is sent to them with `EVAL` when `EVALSHA` misses it.
+    pub async fn script_load(&self, script: &Script) -> HashMap<String, RedisResult<()>> {
+        let requests = self.node_names(None).into_iter().map(|node| async move {
+            let mut connection = self.with_route(Route::Node(node.clone()));
+            let result = script
+                .prepare_invoke()
+                .load_async(&mut connection)
+                .await
+                .map(drop);
+            (node, result)
+        });
+        future::join_all(requests).await.into_iter().collect()
+    }
+
+    /// Runs `CONFIG GET pattern` on every node and returns the matching parameters of each node,
+    /// keyed by `host:port`.
+    pub async fn config_get(
+        &self,
+        pattern: &str,
+    ) -> HashMap<String, RedisResult<HashMap<String, String>>> {
+        let mut cmd = Cmd::new();
+        cmd.arg("CONFIG").arg("GET").arg(pattern);
+        self.fan_out(None, &cmd).await
+    }
+
+    /// Runs `CONFIG SET key value` on every node and returns the outcome for each node, keyed by
+    /// `host:port`. Nodes which failed keep their previous value.
+    pub async fn config_set(&self, key: &str, value: &str) -> HashMap<String, RedisResult<()>> {
+        let mut cmd = Cmd::new();
+        cmd.arg("CONFIG").arg("SET").arg(key).arg(value);
+        self.fan_out(None, &cmd).await
+    }
+
+    /// Runs `LATENCY LATEST` on every node.
+    pub async fn latency_latest(&self) -> HashMap<String, RedisResult<Vec<LatencyEvent>>> {
+        let mut cmd = Cmd::new();
+        cmd.arg("LATENCY").arg("LATEST");
+        let results = self.fan_out(None, &cmd).await;
+        label(results, |node, events| {
+            Ok(rows::<(String, u64, u64, u64)>(events)?
+                .into_iter()
+                .map(|(event, timestamp, latest, max)| LatencyEvent {
+                    node: node.to_string(),
+                    event,
+                    timestamp: UNIX_EPOCH + Duration::from_secs(timestamp),
+                    latest: Duration::from_millis(latest),
+                    max: Duration::from_millis(max),
+                })
+                .collect())
+        })
+    }
+
+    /// Runs `LATENCY HISTORY event` on every node.
+    pub async fn latency_history(
+        &self,
+        event: &str,
+    ) -> HashMap<String, RedisResult<Vec<LatencySample>>> {
+        let mut cmd = Cmd::new();
+        cmd.arg("LATENCY").arg("HISTORY").arg(event);
+        let results = self.fan_out(None, &cmd).await;
+        label(results, |node, samples| {
+            Ok(rows::<(u64, u64)>(samples)?
+                .into_iter()
+                .map(|(timestamp, latency)| LatencySample {
+                    node: node.to_string(),
+                    timestamp: UNIX_EPOCH + Duration::from_secs(timestamp),
+                    latency: Duration::from_millis(latency),
+                })
+                .collect())
+        })
+    }
+
+    /// Runs `SLOWLOG GET n` on every node and returns the entries of all nodes, newest first.
+    ///
+    /// Fails with the error of the first failed node (ordered by `host:port`) if any node failed.
+    pub async fn slowlog_get(&self, n: usize) -> RedisResult<Vec<SlowlogEntry>> {
+        let mut cmd = Cmd::new();
+        cmd.arg("SLOWLOG").arg("GET").arg(n);
+        let results = self.fan_out(None, &cmd).await;
+        let mut entries = label(results, |node, entries: Vec<Value>| {
+            entries
+                .iter()
+                .map(|entry| SlowlogEntry::parse(node, entry))
+                .collect::<RedisResult<Vec<_>>>()
+        })
+        .concat()?;
+        entries.sort_by_key(|entry| Reverse(entry.timestamp));
+        Ok(entries)
+    }
+
+    /// Runs `SLOWLOG RESET` on every node and returns the outcome for each node, keyed by
+    /// `host:port`.
+    pub async fn slowlog_reset(&self) -> HashMap<String, RedisResult<()>> {
+        let mut cmd = Cmd::new();
+        cmd.arg("SLOWLOG").arg("RESET");
+        self.fan_out(None, &cmd).await
+    }
+
+    /// Runs `TIME` on every node concurrently and compares the reply with the local clock, keyed by
+    /// `host:port`.
+    ///
+    /// The reply is assumed to have been produced halfway through the round trip, so the reported
+    /// offsets are only accurate to within half of [`ClockSkew::round_trip`].
+    pub async fn clock_skew(&self) -> HashMap<String, RedisResult<ClockSkew>> {
+        let requests = self.node_names(None).into_iter().map(|node| async move {
+            let mut connection = self.with_route(Route::Node(node.clone()));
+            let sent = SystemTime::now();
+            let result = redis::cmd("TIME")
+                .query_async::<_, (u64, u64)>(&mut connection)
+                .await
+                .map(|(secs, micros)| {
+                    let round_trip = sent.elapsed().unwrap_or_default();
+                    ClockSkew {
+                        node: node.clone(),
+                        node_time: UNIX_EPOCH
+                            + Duration::from_secs(secs)
+                            + Duration::from_micros(micros),
+                        local_time: sent + round_trip / 2,
+                        round_trip,
+                    }
+                });
+            (node, result)
+        });
+        future::join_all(requests).await.into_iter().collect()
+    }
+
+    /// Sends `PING` to the node at `node` (`host:port`, see [`NodeHealth::addr`]) and returns
+    /// how long it took to answer, for readiness and liveness probes. Nodes which are not
+    /// connected yet are connected first, which counts towards the round trip.
+    ///
+    /// [`NodeHealth::addr`]: crate::NodeHealth::addr
+    pub async fn ping(&self, node: &str) -> RedisResult<Duration> {
+        let mut connection = self.with_route(Route::Node(node.to_string()));
+        let clock = &self.shared.clock;
+        let sent = clock.now();
+        redis::cmd("PING")
+            .query_async::<_, Value>(&mut connection)
+            .await?;
+        Ok(clock.now().saturating_duration_since(sent))
+    }
+
+    /// Sends `PING` to every master concurrently, see [`Connection::ping`], and returns the round
+    /// trip of each master keyed by `host:port`.
+    pub async fn ping_masters(&self) -> HashMap<String, RedisResult<Duration>> {
+        self.ping_nodes(Some(NodeRole::Master)).await
+    }
+
+    /// Sends `PING` to every node, masters and replicas, see [`Connection::ping_masters`].
+    pub async fn ping_all_nodes(&self) -> HashMap<String, RedisResult<Duration>> {
+        self.ping_nodes(None).await
+    }
+
+    /// Runs `CLUSTER MYID` on the node at `node` (`host:port`) and returns its node id, as shown
+    /// by `CLUSTER NODES` and `redis-cli --cluster`.
+    pub async fn my_id(&self, node: &str) -> RedisResult<String> {
+        let mut connection = self.with_route(Route::Node(node.to_string()));
+        redis::cmd("CLUSTER")
+            .arg("MYID")
+            .query_async(&mut connection)
+            .await
+    }
+
+    /// Runs `CLUSTER MYID` on every node and returns the id of each node keyed by `host:port`.
+    pub async fn node_ids(&self) -> HashMap<String, RedisResult<String>> {
+        let mut cmd = Cmd::new();
+        cmd.arg("CLUSTER").arg("MYID");
+        self.fan_out(None, &cmd).await
+    }
+
+    /// Returns the `host:port` of the node whose id is `id`, `None` if no node of the last
+    /// discovered topology has it. Asks every node for its id, see [`Connection::node_ids`].
+    ///
+    /// Fails with the error of the first failed node (ordered by `host:port`) if the id was not
+    /// found and some node failed.
+    pub async fn node_by_id(&self, id: &str) -> RedisResult<Option<String>> {
+        let mut ids = self.node_ids().await.into_iter().collect::<Vec<_>>();
+        ids.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let mut error = None;
+        for (node, result) in ids {
+            match result {
+                Ok(node_id) if node_id == id => return Ok(Some(node)),
+                Ok(_) => (),
+                Err(err) => {
+                    error.get_or_insert(err);
+                }
+            }
+        }
+        error.map_or(Ok(None), Err)
+    }
+
+    async fn ping_nodes(&self, role: Option<NodeRole>) -> HashMap<String, RedisResult<Duration>> {
+        let requests = self.node_names(role).into_iter().map(|node| async move {
+            let result = self.ping(&node).await;
+            (node, result)
+        });
+        future::join_all(requests).await.into_iter().collect()
+    }
+
+    /// Runs `CLIENT PAUSE` on every master, holding the commands of their clients (writes only
+    /// with [`PauseMode::Write`]) for at most `timeout`, after which the masters resume on their
+    /// own even if [`Connection::client_unpause`] is never called. Commands sent on this
+    /// connection are held as well.
+    ///
+    /// Either every master is paused or none is: if any master failed, those which were paused
+    /// are unpaused again and the error of the first failed master (ordered by `host:port`) is
+    /// returned. [`PauseMode::Write`] requires [`Feature::ClientPauseWrite`].
+    ///
+    /// ```rust,no_run
+    /// use std::time::Duration;
+    /// use redis_cluster_async::PauseMode;
+    ///
+    /// # async fn run(connection: redis_cluster_async::Connection) -> redis::RedisResult<()> {
+    /// connection.client_pause(PauseMode::Write, Duration::from_secs(10)).await?;
+    /// // Maintenance which needs the writes to stop
+    /// connection.client_unpause().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn client_pause(&self, mode: PauseMode, timeout: Duration) -> RedisResult<()> {
+        if mode == PauseMode::Write {
+            self.check_feature(Feature::ClientPauseWrite)?;
+        }
+        let mut cmd = Cmd::new();
+        cmd.arg("CLIENT")
+            .arg("PAUSE")
+            .arg(timeout.as_millis() as u64)
+            .arg(match mode {
+                PauseMode::Write => "WRITE",
+                PauseMode::All => "ALL",
+            });
+        let results = self.fan_out::<()>(Some(NodeRole::Master), &cmd).await;
+        if results.values().all(|result| result.is_ok()) {
+            return Ok(());
+        }
+        let mut unpause = Cmd::new();
+        unpause.arg("CLIENT").arg("UNPAUSE");
+        let paused = results
+            .iter()
+            .filter(|(_, result)| result.is_ok())
+            .map(|(node, _)| {
+                let unpause = &unpause;
+                async move {
+                    let mut connection = self.with_route(Route::Node(node.clone()));
+                    if let Err(err) = unpause.query_async::<_, ()>(&mut connection).await {
+                        warn!("Could not unpause {}: {}", node, err);
+                    }
+                }
+            });
+        future::join_all(paused).await;
+        results.all_ok().map(|_| ())
+    }
+
+    /// Runs `CLIENT UNPAUSE` on every master, ending a [`Connection::client_pause`] before its
+    /// timeout. Fails with the error of the first failed master (ordered by `host:port`) if any
+    /// master failed.
+    pub async fn client_unpause(&self) -> RedisResult<()> {
+        let mut cmd = Cmd::new();
+        cmd.arg("CLIENT").arg("UNPAUSE");
+        self.fan_out::<()>(Some(NodeRole::Master), &cmd)
+            .await
+            .all_ok()
+            .map(|_| ())
+    }
+
+    // Sends `cmd` to every node with `role` concurrently
+    pub(crate) async fn fan_out<T>(
+        &self,
+        role: Option<NodeRole>,
+        cmd: &Cmd,
+    ) -> HashMap<String, RedisResult<T>>
+    where
+        T: FromRedisValue,
+    {
+        let requests = self.node_names(role).into_iter().map(|node| async move {
+            let mut connection = self.with_route(Route::Node(node.clone()));
+            let result = cmd.query_async(&mut connection).await;
+            (node, result)
+        });
+        future::join_all(requests).await.into_iter().collect()
+    }
+}
+
+// Converts the successful results of every node, passing along the node name
+fn label<T, U>(
+    results: HashMap<String, RedisResult<T>>,
+    f: impl Fn(&str, T) -> RedisResult<U>,
+) -> HashMap<String, RedisResult<U>> {
+    results
+        .into_iter()
+        .map(|(node, result)| {
+            let result = result.and_then(|value| f(&node, value));
+            (node, result)
+        })
+        .collect()
+}
+
+// Parses a reply made of nested arrays, which `Vec<(..)>` would expect to be flattened
+fn rows<T: FromRedisValue>(rows: Vec<Value>) -> RedisResult<Vec<T>> {
+    rows.iter().map(from_redis_value).collect()
+}
+
+/// Which commands [`Connection::client_pause`] holds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PauseMode {
+    /// Hold the commands which may write, reads keep being served.
+    Write,
+    /// Hold every command.
+    All,
+}
+
+/// The latest spike of a latency event on a node, see [`Connection::latency_latest`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LatencyEvent {
+    /// `host:port` of the node.
+    pub node: String,
+    /// Name of the event, e.g. `command` or `fork`.
+    pub event: String,
+    /// When the latest spike happened.
+    pub timestamp: SystemTime,
+    /// Latency of the latest spike.
+    pub latest: Duration,
+    /// Largest latency of the event since the node started.
+    pub max: Duration,
+}
+
+/// A latency spike of an event on a node, see [`Connection::latency_history`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LatencySample {
+    /// `host:port` of the node.
+    pub node: String,
+    /// When the spike happened.
+    pub timestamp: SystemTime,
+    /// Latency of the spike.
+    pub latency: Duration,
+}
+
+/// An entry of the slow log of a node, see [`Connection::slowlog_get`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SlowlogEntry {
+    /// `host:port` of the node.
+    pub node: String,
+    /// Id of the entry, unique per node.
+    pub id: u64,
+    /// When the command was processed.
+    pub timestamp: SystemTime,
+    /// How long the command took to execute.
+    pub duration: Duration,
+    /// The command and its arguments, possibly truncated by the node.
+    pub args: Vec<String>,
+    /// Address of the client which sent the command (Redis 4.0 and later).
+    pub client_addr: Option<String>,
+    /// Name of the client which sent the command, if it set one (Redis 4.0 and later).
+    pub client_name: Option<String>,
+}
+
+impl SlowlogEntry {
+    fn parse(node: &str, value: &Value) -> RedisResult<Self> {
+        let items = match value {
+            Value::Bulk(items) if items.len() >= 4 => items,
+            _ => {
+                return Err(RedisError::from((
+                    ErrorKind::TypeError,
+                    "Invalid slow log entry",
+                    format!("{:?}", value),
+                )))
+            }
+        };
+        let args: Vec<Vec<u8>> = from_redis_value(&items[3])?;
+        let client = |index: usize| -> RedisResult<Option<String>> {
+            match items.get(index) {
+                Some(value) => {
+                    let value: String = from_redis_value(value)?;
+                    Ok(Some(value).filter(|value| !value.is_empty()))
+                }
+                None => Ok(None),
+            }
+        };
+        Ok(SlowlogEntry {
+            node: node.to_string(),
+            id: from_redis_value(&items[0])?,
+            timestamp: UNIX_EPOCH + Duration::from_secs(from_redis_value(&items[1])?),
+            duration: Duration::from_micros(from_redis_value(&items[2])?),
+            args: args
+                .iter()
+                .map(|arg| String::from_utf8_lossy(arg).into_owned())
+                .collect(),
+            client_addr: client(4)?,
+            client_name: client(5)?,
+        })
+    }
+}
+
+/// The clock of a node compared with the local clock, see [`Connection::clock_skew`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClockSkew {
+    /// `host:port` of the node.
+    pub node: String,
+    /// The time reported by the node.
+    pub node_time: SystemTime,
+    /// The local time at which the node is assumed to have replied.
+    pub local_time: SystemTime,
+    /// How long the `TIME` command took, as measured locally.
+    pub round_trip: Duration,
+}
+
+impl ClockSkew {
+    /// Returns how far the clock of the node is ahead of the local clock in microseconds,
+    /// negative if it is behind.
+    pub fn offset_micros(&self) -> i64 {
+        match self.node_time.duration_since(self.local_time) {
+            Ok(ahead) => ahead.as_micros() as i64,
+            Err(err) => -(err.duration().as_micros() as i64),
+        }
+    }
+}
+
+/// Combinators over the per-node results returned by the fan-out methods of [`Connection`].
+///
+/// Every combinator fails with the error of the first failed node (ordered by `host:port`) if
+/// any node failed.
+///
+/// ```rust,no_run
+/// use redis_cluster_async::{redis::cmd, NodeResults};
+///
+/// # async fn run(connection: redis_cluster_async::Connection) -> redis::RedisResult<()> {
+/// let keys: i64 = connection
+///     .execute_on_all_masters(&cmd("DBSIZE"))
+///     .await
+///     .parse()
+///     .sum()?;
+/// connection.config_set("maxmemory", "1gb").await.all_ok()?;
+/// # Ok(())
+/// # }
+/// ```
+pub trait NodeResults<T>: Sized {
+    /// Returns the value of every node.
+    fn all_ok(self) -> RedisResult<HashMap<String, T>>;
+
+    /// Converts the replies to `U`.
+    fn parse<U>(self) -> HashMap<String, RedisResult<U>>
+    where
+        T: Into<Value>,
+        U: FromRedisValue;
+
+    /// Returns the sum of the values of all nodes.
+    fn sum(self) -> RedisResult<T>
+    where
+        T: Sum,
+    {
+        Ok(self.all_ok()?.into_values().sum())
+    }
+
+    /// Returns the largest value, `None` if there were no nodes.
+    fn max(self) -> RedisResult<Option<T>>
+    where
+        T: Ord,
+    {
+        Ok(self.all_ok()?.into_values().max())
+    }
+
+    /// Returns the items of the values of all nodes, in node order.
+    fn concat<U>(self) -> RedisResult<Vec<U>>
+    where
+        T: IntoIterator<Item = U>,
+    {
+        let mut values = self.all_ok()?.into_iter().collect::<Vec<_>>();
+        values.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(values.into_iter().flat_map(|(_, value)| value).collect())
+    }
+}
+
+impl<T> NodeResults<T> for HashMap<String, RedisResult<T>> {
+    fn all_ok(self) -> RedisResult<HashMap<String, T>> {
+        let mut results = self.into_iter().collect::<Vec<_>>();
+        results.sort_by(|(a, _), (b, _)| a.cmp(b));
+        results
+            .into_iter()
+            .map(|(node, result)| result.map(|value| (node, value)))
+            .collect()
+    }
+
+    fn parse<U>(self) -> HashMap<String, RedisResult<U>>
+    where
+        T: Into<Value>,
+        U: FromRedisValue,
+    {
+        self.into_iter()
+            .map(|(node, result)| {
+                let result = result.and_then(|value| from_redis_value(&value.into()));
+                (node, result)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use redis::{ErrorKind, RedisError};
+
+    fn results<T>(values: Vec<(&str, RedisResult<T>)>) -> HashMap<String, RedisResult<T>> {
+        values
+            .into_iter()
+            .map(|(node, result)| (node.to_string(), result))
+            .collect()
+    }
+
+    fn error(detail: &'static str) -> RedisError {
+        (ErrorKind::IoError, detail).into()
+    }
+
+    #[test]
+    fn combinators() {
+        let ok = || results(vec![("a:1", Ok(1)), ("b:1", Ok(5)), ("c:1", Ok(3))]);
+        assert_eq!(ok().sum(), Ok(9));
+        assert_eq!(ok().max(), Ok(Some(5)));
+        assert_eq!(ok().all_ok().unwrap().len(), 3);
+
+        let failed = results(vec![
+            ("a:1", Ok(1)),
+            ("c:1", Err(error("c"))),
+            ("b:1", Err(error("b"))),
+        ]);
+        assert_eq!(
+            failed.sum().unwrap_err().to_string(),
+            error("b").to_string()
+        );
+
+        let lists = results(vec![("b:1", Ok(vec![3, 4])), ("a:1", Ok(vec![1, 2]))]);
+        assert_eq!(lists.concat(), Ok(vec![1, 2, 3, 4]));
+
+        let values = results(vec![("a:1", Ok(Value::Int(2)))]);
+        assert_eq!(values.parse::<i64>().sum(), Ok(2));
+    }
+}
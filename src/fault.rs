@@ -0,0 +1,225 @@
+//! Fault injection for testing how applications behave when the cluster misbehaves.
+//!
+//! Use [`FaultyConnection`] as the connection type of
+//! [`Client::get_generic_connection`](crate::Client::get_generic_connection) and register faults
+//! with [`inject`]. Every command sent to a node is checked against the registered rules and the
+//! first matching rule decides what happens to it instead of (or before) reaching the node.
+//!
+//! ```rust,no_run
+//! use redis_cluster_async::{fault::{self, Fault, FaultRule, FaultyConnection}, Client};
+//!
+//! # async fn run() -> redis::RedisResult<()> {
+//! let client = Client::open(vec!["redis://127.0.0.1:7000/"])?;
+//! let mut connection = client.get_generic_connection::<FaultyConnection>().await?;
+//!
+//! // The next GET sent to 127.0.0.1:7000 is answered with a MOVED redirect
+//! fault::inject(
+//!     FaultRule::new(Fault::Moved { slot: 0, node: "127.0.0.1:7001".into() })
+//!         .node("127.0.0.1:7000")
+//!         .command("GET")
+//!         .times(1),
+//! );
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    io,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use redis::{
+    aio::{ConnectionLike, MultiplexedConnection},
+    Cmd, ConnectionAddr, ErrorKind, IntoConnectionInfo, RedisError, RedisFuture, RedisResult,
+    Value,
+};
+
+use crate::{Clock, Connect, TokioClock};
+
+static RULES: Mutex<Vec<FaultRule>> = Mutex::new(Vec::new());
+
+/// A fault which can be applied to a command.
+#[derive(Clone, Debug)]
+pub enum Fault {
+    /// Reply with `-MOVED <slot> <node>`.
+    Moved { slot: u16, node: String },
+    /// Reply with `-ASK <slot> <node>`.
+    Ask { slot: u16, node: String },
+    /// Fail the command with a timed out I/O error.
+    Timeout,
+    /// Fail the command as if the node had dropped the connection.
+    ConnectionDropped,
+    /// Wait before sending the command, simulating a slow node.
+    Delay(Duration),
+}
+
+/// Describes when a [`Fault`] should be applied.
+#[derive(Clone, Debug)]
+pub struct FaultRule {
+    fault: Fault,
+    node: Option<String>,
+    command: Option<String>,
+    remaining: Option<usize>,
+}
+
+impl FaultRule {
+    /// Create a rule which applies `fault` to every command sent to any node.
+    pub fn new(fault: Fault) -> Self {
+        FaultRule {
+            fault,
+            node: None,
+            command: None,
+            remaining: None,
+        }
+    }
+
+    /// Only apply the fault to commands sent to `node` (given as `host:port`).
+    pub fn node(mut self, node: impl Into<String>) -> Self {
+        self.node = Some(node.into());
+        self
+    }
+
+    /// Only apply the fault to commands named `command` (case-insensitive).
+    pub fn command(mut self, command: impl Into<String>) -> Self {
+        self.command = Some(command.into());
+        self
+    }
+
+    /// Only apply the fault `times` times, after which the rule is removed.
+    pub fn times(mut self, times: usize) -> Self {
+        self.remaining = Some(times);
+        self
+    }
+
+    fn matches(&self, node: &str, cmds: &[&Cmd]) -> bool {
+        if matches!(&self.node, Some(n) if n != node) {
+            return false;
+        }
+        match &self.command {
+            Some(command) => cmds.iter().any(|cmd| {
+                crate::command::name(cmd)
+                    .is_some_and(|name| name.eq_ignore_ascii_case(command.as_bytes()))
+            }),
+            None => true,
+        }
+    }
+}
+
+/// Register a fault rule. Rules are checked in the order they were registered.
+pub fn inject(rule: FaultRule) {
+    RULES.lock().unwrap().push(rule);
+}
+
+/// Remove all registered fault rules.
+pub fn clear() {
+    RULES.lock().unwrap().clear();
+}
+
+fn take_fault(node: &str, cmds: &[&Cmd]) -> Option<Fault> {
+    let mut rules = RULES.lock().unwrap();
+    let index = rules.iter().position(|rule| rule.matches(node, cmds))?;
+    let rule = &mut rules[index];
+    let fault = rule.fault.clone();
+    match &mut rule.remaining {
+        Some(1) => {
+            rules.remove(index);
+        }
+        Some(remaining) => *remaining -= 1,
+        None => (),
+    }
+    Some(fault)
+}
+
+async fn apply(fault: Option<Fault>, clock: &dyn Clock) -> RedisResult<()> {
+    const DESC: &str = "An error was signalled by the server";
+    match fault {
+        None => Ok(()),
+        Some(Fault::Moved { slot, node }) => Err(RedisError::from((
+            ErrorKind::Moved,
+            DESC,
+            format!("{} {}", slot, node),
+        ))),
+        Some(Fault::Ask { slot, node }) => Err(RedisError::from((
+            ErrorKind::Ask,
+            DESC,
+            format!("{} {}", slot, node),
+        ))),
+        Some(Fault::Timeout) => Err(io::Error::from(io::ErrorKind::TimedOut).into()),
+        Some(Fault::ConnectionDropped) => Err(io::Error::from(io::ErrorKind::BrokenPipe).into()),
+        Some(Fault::Delay(duration)) => {
+            clock.sleep(duration).await;
+            Ok(())
+        }
+    }
+}
+
+/// A node connection which applies the registered fault rules before forwarding commands to
+/// the wrapped connection. Delays are waited for with the [`Clock`] of the cluster connection.
+#[derive(Clone)]
+pub struct FaultyConnection<C = MultiplexedConnection> {
+    inner: C,
+    node: String,
+    clock: Arc<dyn Clock>,
+}
+
+impl<C> Connect for FaultyConnection<C>
+where
+    C: Connect + Send + 'static,
+{
+    fn connect<'a, T>(info: T) -> RedisFuture<'a, Self>
+    where
+        T: IntoConnectionInfo + Send + 'a,
+    {
+        Self::connect_with_clock(info, Arc::new(TokioClock))
+    }
+
+    fn connect_with_clock<'a, T>(info: T, clock: Arc<dyn Clock>) -> RedisFuture<'a, Self>
+    where
+        T: IntoConnectionInfo + Send + 'a,
+    {
+        Box::pin(async move {
+            let info = info.into_connection_info()?;
+            let node = match &info.addr {
+                ConnectionAddr::Tcp(host, port) | ConnectionAddr::TcpTls { host, port, .. } => {
+                    format!("{}:{}", host, port)
+                }
+                ConnectionAddr::Unix(path) => path.display().to_string(),
+            };
+            let inner = C::connect_with_clock(info, clock.clone()).await?;
+            Ok(FaultyConnection { inner, node, clock })
+        })
+    }
+}
+
+impl<C> ConnectionLike for FaultyConnection<C>
+where
+    C: ConnectionLike + Send,
+{
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        let fault = take_fault(&self.node, &[cmd]);
+        Box::pin(async move {
+            apply(fault, &*self.clock).await?;
+            self.inner.req_packed_command(cmd).await
+        })
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        pipeline: &'a redis::Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        let fault = take_fault(&self.node, &pipeline.cmd_iter().collect::<Vec<_>>());
+        Box::pin(async move {
+            apply(fault, &*self.clock).await?;
+            self.inner
+                .req_packed_commands(pipeline, offset, count)
+                .await
+        })
+    }
+
+    fn get_db(&self) -> i64 {
+        self.inner.get_db()
+    }
+}
@@ -0,0 +1,96 @@
+use redis::{Arg, Cmd, ErrorKind, RedisError, RedisResult};
+
+use crate::command;
+
+/// Commands which connections reject without sending them, as a safety net against stray admin
+/// commands on a shared cluster. Installed with
+/// [`Client::set_command_filter`](crate::Client::set_command_filter).
+///
+/// Commands are named case insensitively, either alone (`CONFIG`) or together with a subcommand
+/// (`CONFIG SET`) to only match that subcommand. A pipeline is rejected as a whole if any of its
+/// commands is. The commands the client sends on its own (`CLUSTER SLOTS`, `READONLY`, ...) are
+/// not filtered.
+///
+/// ```rust
+/// use redis_cluster_async::CommandFilter;
+///
+/// let filter = CommandFilter::Deny(vec![
+///     "FLUSHALL".into(),
+///     "FLUSHDB".into(),
+///     "KEYS".into(),
+///     "DEBUG".into(),
+///     "CONFIG SET".into(),
+/// ]);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CommandFilter {
+    /// Reject the listed commands.
+    Deny(Vec<String>),
+    /// Reject every command but the listed ones.
+    Allow(Vec<String>),
+}
+
+impl CommandFilter {
+    pub(crate) fn check(&self, cmd: &Cmd) -> RedisResult<()> {
+        let mut args = cmd.args_iter().filter_map(|arg| match arg {
+            Arg::Simple(arg) => Some(arg),
+            Arg::Cursor => None,
+        });
+        let name = args.next().unwrap_or_default();
+        let subcommand = args.next();
+        let matches = |commands: &[String]| {
+            commands
+                .iter()
+                .any(|command| matches(command, name, subcommand))
+        };
+        let allowed = match self {
+            CommandFilter::Deny(commands) => !matches(commands),
+            CommandFilter::Allow(commands) => matches(commands),
+        };
+        if allowed {
+            return Ok(());
+        }
+        Err(RedisError::from((
+            ErrorKind::ClientError,
+            "Command is not allowed on this connection",
+            command::display(name),
+        )))
+    }
+}
+
+// Whether `command` (`NAME` or `NAME SUBCOMMAND`) names the command `name subcommand`
+fn matches(command: &str, name: &[u8], subcommand: Option<&[u8]>) -> bool {
+    let mut words = command.split_whitespace();
+    let expected = |word: Option<&str>, arg: Option<&[u8]>| match (word, arg) {
+        (None, _) => true,
+        (Some(word), Some(arg)) => word.as_bytes().eq_ignore_ascii_case(arg),
+        (Some(_), None) => false,
+    };
+    expected(words.next(), Some(name)) && expected(words.next(), subcommand)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use redis::cmd;
+
+    #[test]
+    fn commands_are_filtered() {
+        let deny = CommandFilter::Deny(vec!["flushall".into(), "CONFIG SET".into()]);
+        assert!(deny.check(&cmd("FLUSHALL")).is_err());
+        assert!(deny
+            .check(cmd("config").arg("set").arg("a").arg("b"))
+            .is_err());
+        assert!(deny.check(cmd("CONFIG").arg("GET").arg("a")).is_ok());
+        assert!(deny.check(cmd("GET").arg("key")).is_ok());
+
+        let allow = CommandFilter::Allow(vec!["GET".into(), "CLUSTER SLOTS".into()]);
+        assert!(allow.check(cmd("get").arg("key")).is_ok());
+        assert!(allow.check(cmd("CLUSTER").arg("SLOTS")).is_ok());
+        let err = allow.check(cmd("set").arg("key").arg(1)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ClientError);
+        assert_eq!(err.detail(), Some("SET"));
+        assert!(allow.check(&cmd("CLUSTER")).is_err());
+    }
+}
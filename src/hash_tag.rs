@@ -0,0 +1,117 @@
+use crate::slot::hash_tag;
+
+/// Wraps the leading segments of the keys matching a pattern in a hash tag, so that related keys
+/// share a slot and can be used together in multi-key commands and transactions.
+///
+/// Rules are installed with [`Client::set_hash_tag_rules`](crate::Client::set_hash_tag_rules) and
+/// the first rule whose pattern matches a key decides its hash tag. With the default of one
+/// segment separated by `:`, `user:42:profile` becomes `{user}:42:profile`. Keys which already
+/// hold a hash tag, and keys with no more segments than those tagged, are left as they are.
+///
+/// Patterns are globs over the whole key, `*` matching any number of bytes and `?` a single one.
+///
+/// ```rust
+/// use redis_cluster_async::HashTagRule;
+///
+/// let rules = vec![
+///     // `user:42:profile` and `user:42:sessions` become `{user:42}:profile` and
+///     // `{user:42}:sessions`
+///     HashTagRule::new("user:*").segments(2),
+///     // `orders/7/items` becomes `{orders}/7/items`
+///     HashTagRule::new("orders/*").separator(b'/'),
+/// ];
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HashTagRule {
+    pattern: Vec<u8>,
+    separator: u8,
+    segments: usize,
+}
+
+impl HashTagRule {
+    /// Create a rule tagging the first `:` separated segment of the keys matching `pattern`.
+    pub fn new(pattern: impl AsRef<[u8]>) -> Self {
+        HashTagRule {
+            pattern: pattern.as_ref().to_vec(),
+            separator: b':',
+            segments: 1,
+        }
+    }
+
+    /// Byte separating the segments of a key. Default: `:`
+    pub fn separator(mut self, separator: u8) -> Self {
+        self.separator = separator;
+        self
+    }
+
+    /// Number of leading segments put in the hash tag. Default: 1
+    pub fn segments(mut self, segments: usize) -> Self {
+        self.segments = segments;
+        self
+    }
+
+    // `key` with its tagged segments in braces, `None` if it is left as it is
+    fn tag(&self, key: &[u8]) -> Option<Vec<u8>> {
+        if self.segments == 0 || hash_tag(key).is_some() {
+            return None;
+        }
+        let end = key
+            .iter()
+            .enumerate()
+            .filter(|(_, &byte)| byte == self.separator)
+            .nth(self.segments - 1)
+            .map(|(end, _)| end)?;
+        let mut tagged = Vec::with_capacity(key.len() + 2);
+        tagged.push(b'{');
+        tagged.extend_from_slice(&key[..end]);
+        tagged.push(b'}');
+        tagged.extend_from_slice(&key[end..]);
+        Some(tagged)
+    }
+}
+
+// `key` tagged by the first rule matching it, `None` if no rule matches or it is left as it is
+pub(crate) fn apply(rules: &[HashTagRule], key: &[u8]) -> Option<Vec<u8>> {
+    rules
+        .iter()
+        .find(|rule| glob_matches(&rule.pattern, key))?
+        .tag(key)
+}
+
+fn glob_matches(pattern: &[u8], key: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => key.is_empty(),
+        Some((b'*', rest)) => (0..=key.len()).any(|skip| glob_matches(rest, &key[skip..])),
+        Some((b'?', rest)) => !key.is_empty() && glob_matches(rest, &key[1..]),
+        Some((byte, rest)) => key.first() == Some(byte) && glob_matches(rest, &key[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tagged(rules: &[HashTagRule], key: &str) -> String {
+        apply(rules, key.as_bytes())
+            .map(|key| String::from_utf8(key).unwrap())
+            .unwrap_or_else(|| key.to_string())
+    }
+
+    #[test]
+    fn keys_are_tagged_by_the_first_matching_rule() {
+        let rules = vec![
+            HashTagRule::new("user:*").segments(2),
+            HashTagRule::new("orders/*").separator(b'/'),
+            HashTagRule::new("*"),
+        ];
+        assert_eq!(tagged(&rules, "user:42:profile"), "{user:42}:profile");
+        assert_eq!(tagged(&rules, "user:42"), "user:42");
+        assert_eq!(tagged(&rules, "orders/7/items"), "{orders}/7/items");
+        assert_eq!(tagged(&rules, "cart:1"), "{cart}:1");
+        assert_eq!(tagged(&rules, "{cart}:1"), "{cart}:1");
+        assert_eq!(tagged(&rules, "cart"), "cart");
+
+        assert!(glob_matches(b"a?c*", b"abcdef"));
+        assert!(!glob_matches(b"a?c", b"ac"));
+    }
+}
@@ -0,0 +1,176 @@
+//! Classification of commands by whether sending them more than once has the same effect as
+//! sending them once.
+
+use redis::{Arg, Cmd};
+
+use crate::command;
+
+// Commands which only read, so a copy which already reached the node changed nothing
+const READ_ONLY: &[&str] = &[
+    "BITCOUNT",
+    "BITPOS",
+    "DBSIZE",
+    "DUMP",
+    "ECHO",
+    "EVALSHA_RO",
+    "EVAL_RO",
+    "EXISTS",
+    "FCALL_RO",
+    "GEODIST",
+    "GEOHASH",
+    "GEOPOS",
+    "GEOSEARCH",
+    "GET",
+    "GETBIT",
+    "GETRANGE",
+    "HEXISTS",
+    "HGET",
+    "HGETALL",
+    "HKEYS",
+    "HLEN",
+    "HMGET",
+    "HRANDFIELD",
+    "HSCAN",
+    "HSTRLEN",
+    "HVALS",
+    "KEYS",
+    "LCS",
+    "LINDEX",
+    "LLEN",
+    "LPOS",
+    "LRANGE",
+    "MGET",
+    "PFCOUNT",
+    "PING",
+    "PTTL",
+    "RANDOMKEY",
+    "READONLY",
+    "SCAN",
+    "SCARD",
+    "SDIFF",
+    "SINTER",
+    "SINTERCARD",
+    "SISMEMBER",
+    "SMEMBERS",
+    "SMISMEMBER",
+    "SRANDMEMBER",
+    "SSCAN",
+    "STRLEN",
+    "SUBSTR",
+    "SUNION",
+    "TIME",
+    "TTL",
+    "TYPE",
+    "XLEN",
+    "XPENDING",
+    "XRANGE",
+    "XREVRANGE",
+    "ZCARD",
+    "ZCOUNT",
+    "ZDIFF",
+    "ZINTER",
+    "ZINTERCARD",
+    "ZLEXCOUNT",
+    "ZMSCORE",
+    "ZRANDMEMBER",
+    "ZRANGE",
+    "ZRANGEBYLEX",
+    "ZRANGEBYSCORE",
+    "ZRANK",
+    "ZREVRANGE",
+    "ZREVRANGEBYLEX",
+    "ZREVRANGEBYSCORE",
+    "ZREVRANK",
+    "ZSCAN",
+    "ZSCORE",
+    "ZUNION",
+];
+
+// Commands which overwrite keys with the given values, executing them again replies the same
+// and leaves the same data behind unless another client wrote the keys in between
+const BLIND_WRITES: &[&str] = &["HMSET", "MSET", "PSETEX", "SET", "SETEX"];
+
+// Options which make the reply or the effect of `SET` depend on the previous value
+const SET_CONDITIONS: &[&str] = &["GET", "NX", "XX"];
+
+/// How safe it is to execute a command again when it is unknown whether it was executed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Idempotency {
+    /// The command only reads.
+    ReadOnly,
+    /// The command overwrites keys with the given values.
+    BlindWrite,
+    /// Executing the command again may change the data or the reply (`INCR`, `LPUSH`, ...).
+    Unsafe,
+}
+
+pub(crate) fn classify(cmd: &Cmd) -> Idempotency {
+    let mut args = cmd.args_iter().map(|arg| match arg {
+        Arg::Simple(arg) => arg,
+        Arg::Cursor => &[],
+    });
+    let name = match args.next() {
+        Some(name) => name,
+        None => return Idempotency::Unsafe,
+    };
+    if command::is_one_of(name, READ_ONLY) {
+        Idempotency::ReadOnly
+    } else if command::is_one_of(name, BLIND_WRITES) {
+        // Skip the key and the value so that they are never mistaken for an option
+        if name.eq_ignore_ascii_case(b"SET")
+            && args
+                .skip(2)
+                .any(|arg| command::is_one_of(arg, SET_CONDITIONS))
+        {
+            Idempotency::Unsafe
+        } else {
+            Idempotency::BlindWrite
+        }
+    } else {
+        Idempotency::Unsafe
+    }
+}
+
+/// Returns whether `cmd` can be sent again after a copy of it may already have been executed.
+pub(crate) fn is_idempotent(cmd: &Cmd, blind_writes: bool) -> bool {
+    match classify(cmd) {
+        Idempotency::ReadOnly => true,
+        Idempotency::BlindWrite => blind_writes,
+        Idempotency::Unsafe => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use redis::cmd;
+
+    #[test]
+    fn classify_commands() {
+        assert_eq!(classify(cmd("GET").arg("key")), Idempotency::ReadOnly);
+        assert_eq!(classify(cmd("hgetall").arg("key")), Idempotency::ReadOnly);
+        assert_eq!(classify(cmd("INCR").arg("key")), Idempotency::Unsafe);
+        assert_eq!(
+            classify(cmd("SET").arg("key").arg("value").arg("EX").arg(10)),
+            Idempotency::BlindWrite
+        );
+        assert_eq!(
+            classify(cmd("SET").arg("key").arg("value").arg("nx")),
+            Idempotency::Unsafe
+        );
+        // Keys and values are not options
+        assert_eq!(
+            classify(cmd("SET").arg("NX").arg("GET")),
+            Idempotency::BlindWrite
+        );
+    }
+
+    #[test]
+    fn blind_writes_are_opt_in() {
+        let set = cmd("MSET").arg("key").arg("value").clone();
+        assert!(!is_idempotent(&set, false));
+        assert!(is_idempotent(&set, true));
+        assert!(is_idempotent(cmd("GET").arg("key"), false));
+    }
+}
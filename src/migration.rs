@@ -0,0 +1,163 @@
+use futures::future;
+use log::warn;
+use redis::{aio::ConnectionLike, Cmd, Pipeline, RedisFuture, RedisResult, Value};
+
+use crate::{
+    idempotency::{classify, Idempotency},
+    Connection,
+};
+
+/// One of the two clusters of a [`DualWriteConnection`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cluster {
+    /// The cluster which is migrated from.
+    Old,
+    /// The cluster which is migrated to.
+    New,
+}
+
+/// Which failed writes fail a command sent through a [`DualWriteConnection`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WriteErrors {
+    /// A write fails if it failed on either cluster.
+    Any,
+    /// A write only fails if it failed on the cluster reads are sent to. Failures on the other
+    /// cluster are logged.
+    ReadCluster,
+}
+
+/// A connection for migrating data from one cluster to another without downtime, implementing
+/// `ConnectionLike` on top of a connection to each cluster.
+///
+/// Writes are sent to both clusters concurrently and return the reply of the cluster reads are
+/// sent to. Reads are sent to one cluster and sent to the other one as well when they fail or
+/// reply nil, as the key may not have been copied over yet. Commands which are not known to only
+/// read are writes. A migration would typically start reading from [`Cluster::Old`], switch to
+/// [`Cluster::New`] once the data has been copied and end with connecting to the new cluster
+/// alone.
+///
+/// ```rust,no_run
+/// use redis_cluster_async::{Client, Cluster, DualWriteConnection, WriteErrors};
+///
+/// # async fn run() -> redis::RedisResult<()> {
+/// let old = Client::open(vec!["redis://10.0.0.1:7000/"])?.get_connection().await?;
+/// let new = Client::open(vec!["redis://10.0.1.1:7000/"])?.get_connection().await?;
+/// let mut connection = DualWriteConnection::new(old, new)
+///     .read_from(Cluster::New)
+///     .write_errors(WriteErrors::ReadCluster);
+/// redis::cmd("SET").arg("key").arg("value").query_async::<_, ()>(&mut connection).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct DualWriteConnection<C = redis::aio::MultiplexedConnection> {
+    old: Connection<C>,
+    new: Connection<C>,
+    read_from: Cluster,
+    write_errors: WriteErrors,
+}
+
+impl<C> DualWriteConnection<C> {
+    /// Create a connection writing to `old` and `new` and reading from `old`, which fails writes
+    /// which failed on either cluster.
+    pub fn new(old: Connection<C>, new: Connection<C>) -> Self {
+        DualWriteConnection {
+            old,
+            new,
+            read_from: Cluster::Old,
+            write_errors: WriteErrors::Any,
+        }
+    }
+
+    /// Send reads to `cluster` first. Default: [`Cluster::Old`]
+    pub fn read_from(mut self, cluster: Cluster) -> Self {
+        self.read_from = cluster;
+        self
+    }
+
+    /// Decide which failed writes fail the command. Default: [`WriteErrors::Any`]
+    pub fn write_errors(mut self, errors: WriteErrors) -> Self {
+        self.write_errors = errors;
+        self
+    }
+
+    // The connection to the cluster reads are sent to, and the other one
+    fn split(&mut self) -> (&mut Connection<C>, &mut Connection<C>) {
+        match self.read_from {
+            Cluster::Old => (&mut self.old, &mut self.new),
+            Cluster::New => (&mut self.new, &mut self.old),
+        }
+    }
+
+    // Settles the replies of a write to the read cluster and to the other one
+    fn settle<T>(&self, read: RedisResult<T>, other: RedisResult<T>) -> RedisResult<T> {
+        match (read, other) {
+            (Ok(_), Err(err)) if self.write_errors == WriteErrors::Any => Err(err),
+            (read, Err(err)) => {
+                let other = match self.read_from {
+                    Cluster::Old => Cluster::New,
+                    Cluster::New => Cluster::Old,
+                };
+                warn!("Write to the {:?} cluster failed: {}", other, err);
+                read
+            }
+            (read, Ok(_)) => read,
+        }
+    }
+}
+
+impl<C> ConnectionLike for DualWriteConnection<C>
+where
+    C: ConnectionLike + Send + 'static,
+{
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        Box::pin(async move {
+            let (read, other) = self.split();
+            if classify(cmd) == Idempotency::ReadOnly {
+                return match read.req_command(cmd).await {
+                    Ok(Value::Nil) => match other.req_command(cmd).await {
+                        Ok(value) => Ok(value),
+                        Err(_) => Ok(Value::Nil),
+                    },
+                    Ok(value) => Ok(value),
+                    Err(err) => other.req_command(cmd).await.map_err(|_| err),
+                };
+            }
+            let (read, other) = future::join(read.req_command(cmd), other.req_command(cmd)).await;
+            self.settle(read, other)
+        })
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        pipeline: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        Box::pin(async move {
+            let (read, other) = self.split();
+            if pipeline
+                .cmd_iter()
+                .all(|cmd| classify(cmd) == Idempotency::ReadOnly)
+            {
+                return match read.req_packed_commands(pipeline, offset, count).await {
+                    Ok(values) => Ok(values),
+                    Err(err) => other
+                        .req_packed_commands(pipeline, offset, count)
+                        .await
+                        .map_err(|_| err),
+                };
+            }
+            let (read, other) = future::join(
+                read.req_packed_commands(pipeline, offset, count),
+                other.req_packed_commands(pipeline, offset, count),
+            )
+            .await;
+            self.settle(read, other)
+        })
+    }
+
+    fn get_db(&self) -> i64 {
+        0
+    }
+}
@@ -0,0 +1,127 @@
+//! Copies of the commands of a connection sent to a second, shadow, cluster, see
+//! [`Client::set_mirror`].
+//!
+//! Mirrored commands are sent in tasks of their own and their replies and errors are dropped, so
+//! the shadow cluster can not slow down or fail the commands of the connection. Commands which
+//! name a node of the cluster (like [`Connection::send_raw`]) are not mirrored, as the shadow
+//! cluster does not have that node.
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use futures::{
+    future::{self, BoxFuture, FutureExt},
+    Future,
+};
+use log::warn;
+use rand::{thread_rng, Rng};
+use redis::{aio::ConnectionLike, Cmd, Pipeline};
+
+use crate::{Client, ClusterParams, Connect, Connection, Route, Runtime};
+
+// Mirrored commands without a reply yet past which commands are not mirrored anymore, so a shadow
+// cluster which falls behind does not make the mirrored commands pile up
+const MAX_IN_FLIGHT: usize = 1000;
+
+#[derive(Clone)]
+pub(crate) struct MirrorConfig {
+    pub(crate) shadow: Arc<Client>,
+    pub(crate) fraction: f64,
+}
+
+pub(crate) struct Mirror<C> {
+    shadow: Connection<C>,
+    fraction: f64,
+    runtime: Arc<dyn Runtime>,
+    in_flight: AtomicUsize,
+}
+
+// Connects to the shadow cluster of `params`, `None` if there is none or it can not be reached
+pub(crate) fn connect<C>(params: &ClusterParams) -> BoxFuture<'static, Option<Arc<Mirror<C>>>>
+where
+    C: ConnectionLike + Connect + Clone + Send + Sync + Unpin + 'static,
+{
+    let config = match &params.mirror {
+        Some(config) => config.clone(),
+        None => return future::ready(None).boxed(),
+    };
+    let runtime = params.runtime.clone();
+    async move {
+        match config.shadow.get_generic_connection().await {
+            Ok(shadow) => Some(Arc::new(Mirror {
+                shadow,
+                fraction: config.fraction,
+                runtime,
+                in_flight: AtomicUsize::new(0),
+            })),
+            Err(err) => {
+                warn!(
+                    "Not mirroring, the shadow cluster can not be reached: {}",
+                    err
+                );
+                None
+            }
+        }
+    }
+    .boxed()
+}
+
+impl<C> Mirror<C>
+where
+    C: ConnectionLike + Send + 'static,
+{
+    pub(crate) fn mirror_cmd(self: &Arc<Self>, cmd: &Cmd, route: Option<&Route>) {
+        if let Some(mut shadow) = self.sample(route) {
+            let cmd = cmd.clone();
+            self.spawn(async move {
+                let _ = shadow.req_packed_command(&cmd).await;
+            });
+        }
+    }
+
+    pub(crate) fn mirror_pipeline(
+        self: &Arc<Self>,
+        pipeline: &Pipeline,
+        offset: usize,
+        count: usize,
+        route: Option<&Route>,
+    ) {
+        if let Some(mut shadow) = self.sample(route) {
+            let pipeline = pipeline.clone();
+            self.spawn(async move {
+                let _ = shadow.req_packed_commands(&pipeline, offset, count).await;
+            });
+        }
+    }
+
+    // The shadow connection to send a command to, routed like it is on the connection, if the
+    // command is picked to be mirrored
+    fn sample(&self, route: Option<&Route>) -> Option<Connection<C>> {
+        if thread_rng().gen::<f64>() >= self.fraction {
+            return None;
+        }
+        let shadow = match route {
+            None => self.shadow.clone(),
+            Some(Route::Slot(slot)) => self.shadow.with_slot(*slot),
+            Some(Route::Node(_)) => return None,
+        };
+        if self.in_flight.fetch_add(1, Ordering::Relaxed) >= MAX_IN_FLIGHT {
+            self.in_flight.fetch_sub(1, Ordering::Relaxed);
+            return None;
+        }
+        Some(shadow)
+    }
+
+    fn spawn(self: &Arc<Self>, send: impl Future<Output = ()> + Send + 'static) {
+        let mirror = self.clone();
+        self.runtime.spawn(
+            async move {
+                send.await;
+                mirror.in_flight.fetch_sub(1, Ordering::Relaxed);
+            }
+            .boxed(),
+        );
+    }
+}
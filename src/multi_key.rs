@@ -0,0 +1,410 @@
+//! Multi-key commands and pipelines split into one command or pipeline per slot.
+//!
+//! Redis rejects `MGET`, `MSET` and `DEL` with keys in more than one slot, so these helpers group
+//! the keys by slot, send the groups concurrently and merge the replies in the order of the keys.
+//! The commands are sent to whichever node owns a slot and follow redirections like any other
+//! command, but the groups succeed or fail on their own: the command is not atomic across slots.
+//! Pipelines are split the same way, see [`Connection::cluster_pipeline`].
+//!
+//! `MGET`, `MSET`, `DEL` and `UNLINK` sent with `query_async` are split the same way when their
+//! keys are in more than one slot, and their replies merged into the reply of a single command.
+//! Keys sharing a slot, e.g. through a hash tag, are still sent as one command.
+
+use std::{collections::HashMap, sync::atomic::Ordering};
+
+use futures::future;
+use redis::{
+    aio::ConnectionLike, Arg, Cmd, ErrorKind, FromRedisValue, Pipeline, RedisError, RedisResult,
+    ToRedisArgs, Value,
+};
+
+use crate::{command, key_slot, slot_for_command, Connection};
+
+impl<C> Connection<C>
+where
+    C: ConnectionLike + Send + 'static,
+{
+    /// Gets the values of `keys` with one `MGET` per slot, in the order of the keys and `None` for
+    /// a missing key.
+    ///
+    /// Fails with the error of the first failed slot (in the order of the keys) if any slot
+    /// failed, the values read from the other slots are discarded.
+    pub async fn cluster_mget<K, V>(&self, keys: &[K]) -> RedisResult<Vec<Option<V>>>
+    where
+        K: AsRef<[u8]>,
+        V: FromRedisValue,
+    {
+        let groups = self.by_slot(keys.iter().map(|key| key.as_ref()));
+        let replies = self
+            .per_slot::<Vec<Option<V>>>(&groups, |cmd, indices| {
+                cmd.arg("MGET");
+                for &index in indices {
+                    cmd.arg(keys[index].as_ref());
+                }
+            })
+            .await?;
+
+        let mut values = (0..keys.len()).map(|_| None).collect::<Vec<_>>();
+        for ((_, indices), reply) in groups.iter().zip(replies) {
+            for (&index, value) in indices.iter().zip(reply) {
+                values[index] = value;
+            }
+        }
+        Ok(values)
+    }
+
+    /// Sets every key of `pairs` to its value with one `MSET` per slot.
+    ///
+    /// Fails with the error of the first failed slot (in the order of the pairs) if any slot
+    /// failed. The keys of the other slots may have been set all the same.
+    pub async fn cluster_mset<K, V>(&self, pairs: &[(K, V)]) -> RedisResult<()>
+    where
+        K: AsRef<[u8]>,
+        V: ToRedisArgs,
+    {
+        let groups = self.by_slot(pairs.iter().map(|(key, _)| key.as_ref()));
+        self.per_slot::<()>(&groups, |cmd, indices| {
+            cmd.arg("MSET");
+            for &index in indices {
+                let (key, value) = &pairs[index];
+                cmd.arg(key.as_ref()).arg(value);
+            }
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Deletes `keys` with one `DEL` per slot and returns the number of keys which existed.
+    ///
+    /// Fails with the error of the first failed slot (in the order of the keys) if any slot
+    /// failed. The keys of the other slots may have been deleted all the same.
+    pub async fn cluster_del<K>(&self, keys: &[K]) -> RedisResult<usize>
+    where
+        K: AsRef<[u8]>,
+    {
+        let groups = self.by_slot(keys.iter().map(|key| key.as_ref()));
+        let deleted = self
+            .per_slot::<usize>(&groups, |cmd, indices| {
+                cmd.arg("DEL");
+                for &index in indices {
+                    cmd.arg(keys[index].as_ref());
+                }
+            })
+            .await?;
+        Ok(deleted.into_iter().sum())
+    }
+
+    /// Sends the commands of `pipeline` with one pipeline per slot, concurrently, and returns the
+    /// reply of every command in order. Every command reaches the node owning its key, and a
+    /// `MOVED` or `ASK` redirection only sends the commands of the redirected slot again instead
+    /// of the whole pipeline. Commands without a key are sent together to any node. Pipelines
+    /// sent with `query_async` are split the same way when their keys are in more than one slot,
+    /// this also splits those sent through a handle routing every command to one slot or node.
+    ///
+    /// Every command has a reply, [`Pipeline::ignore`] is not taken into account. Fails with the
+    /// error of the first failed slot (in the order of the commands) if any slot failed, the
+    /// commands of the other slots may have run all the same. Transactions can not be split and
+    /// are rejected.
+    ///
+    /// ```rust,no_run
+    /// # async fn run(connection: redis_cluster_async::Connection) -> redis::RedisResult<()> {
+    /// let mut pipeline = redis::pipe();
+    /// pipeline.incr("visits", 1).get("user:1").get("user:2");
+    /// let replies = connection.cluster_pipeline(&pipeline).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn cluster_pipeline(&self, pipeline: &Pipeline) -> RedisResult<Vec<Value>> {
+        reject_transaction(pipeline)?;
+        let groups = self.pipeline_by_slot(pipeline);
+        self.query_by_slot(pipeline, &groups).await
+    }
+
+    // The replies of the commands of `pipeline` if it is sent with one pipeline per slot, `None`
+    // if it is better sent as a whole: it is a transaction (read past its `MULTI`, so from a
+    // non-zero `offset`), its commands share a slot, the handle routes it already or every slot
+    // belongs to the same master
+    pub(crate) async fn split_pipeline(
+        &self,
+        pipeline: &Pipeline,
+        offset: usize,
+    ) -> Option<RedisResult<Vec<Value>>> {
+        if offset > 0 || self.route.is_some() || self.single_master() {
+            return None;
+        }
+        let groups = self.pipeline_by_slot(pipeline);
+        if groups.len() < 2 {
+            return None;
+        }
+        Some(self.query_by_slot(pipeline, &groups).await)
+    }
+
+    // The reply of the multi-key command `cmd` if it is sent with one command per slot, `None` if
+    // it is better sent as it is: it is not `MGET`, `MSET`, `DEL` or `UNLINK`, its keys share a
+    // slot, the handle routes it already or every slot belongs to the same master
+    pub(crate) async fn split_cmd(&self, cmd: &Cmd) -> Option<RedisResult<Value>> {
+        let name = command::name(cmd)?;
+        // The number of arguments following each key
+        let values = if command::is_one_of(name, &["MGET", "DEL", "UNLINK"]) {
+            0
+        } else if name.eq_ignore_ascii_case(b"MSET") {
+            1
+        } else {
+            return None;
+        };
+        if self.route.is_some() || self.single_master() {
+            return None;
+        }
+        let args = cmd
+            .args_iter()
+            .map(|arg| match arg {
+                Arg::Simple(arg) => Some(arg),
+                Arg::Cursor => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+        if (args.len() - 1) % (values + 1) != 0 {
+            return None;
+        }
+        let name = command::display(name);
+        let keys = &args[1..];
+        let groups = self.by_slot(keys.iter().step_by(values + 1).copied());
+        if groups.len() < 2 {
+            return None;
+        }
+        let replies = self
+            .per_slot::<Value>(&groups, |cmd, indices| {
+                cmd.arg(&name);
+                for &index in indices {
+                    let start = index * (values + 1);
+                    for arg in &keys[start..=start + values] {
+                        cmd.arg(*arg);
+                    }
+                }
+            })
+            .await;
+        Some(replies.and_then(|replies| merge_replies(&name, &groups, replies)))
+    }
+
+    // Sends the commands of every group of `pipeline` concurrently, routed by the slot of the
+    // group, and returns their replies in the order of the commands
+    async fn query_by_slot(
+        &self,
+        pipeline: &Pipeline,
+        groups: &[(Option<u16>, Vec<usize>)],
+    ) -> RedisResult<Vec<Value>> {
+        let cmds = pipeline.cmd_iter().collect::<Vec<_>>();
+        let requests = groups.iter().map(|(slot, indices)| {
+            let mut part = Pipeline::with_capacity(indices.len());
+            for &index in indices {
+                part.add_command(cmds[index].clone());
+            }
+            let mut connection = self.with_optional_slot(*slot);
+            async move { part.query_async::<_, Vec<Value>>(&mut connection).await }
+        });
+        let replies = future::join_all(requests)
+            .await
+            .into_iter()
+            .collect::<RedisResult<Vec<_>>>()?;
+
+        let mut values = vec![Value::Nil; cmds.len()];
+        for ((_, indices), reply) in groups.iter().zip(replies) {
+            for (&index, value) in indices.iter().zip(reply) {
+                values[index] = value;
+            }
+        }
+        Ok(values)
+    }
+
+    /// Sends the commands of `pipeline` without waiting for their replies, for write heavy
+    /// workloads which do not need them, like telemetry. The commands are split by slot like
+    /// [`Connection::cluster_pipeline`], those without a key going to the node of the first
+    /// command which has one, and every part is wrapped in `CLIENT REPLY OFF` and
+    /// `CLIENT REPLY ON`, so the nodes do not even send the replies. Returns once every node
+    /// answered the `CLIENT REPLY ON` ending its part, the commands having been run by then.
+    ///
+    /// Neither the replies nor the errors of the commands are known: a command sent to a slot
+    /// being migrated is not redirected and is lost. Only failing to send a part fails. Requires
+    /// node connections which support not getting a reply for every command they send, as the
+    /// multiplexed connection does. Transactions are rejected.
+    ///
+    /// ```rust,no_run
+    /// # async fn run(connection: redis_cluster_async::Connection) -> redis::RedisResult<()> {
+    /// let mut pipeline = redis::pipe();
+    /// for sample in 0..1000 {
+    ///     pipeline.cmd("XADD").arg("metrics").arg("*").arg("value").arg(sample);
+    /// }
+    /// connection.fire_and_forget(&pipeline).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn fire_and_forget(&self, pipeline: &Pipeline) -> RedisResult<()> {
+        reject_transaction(pipeline)?;
+        let cmds = pipeline.cmd_iter().collect::<Vec<_>>();
+        let groups = self.pipeline_by_slot(pipeline);
+        // Every part is routed, so that it is never split again between the slot of its
+        // `CLIENT REPLY` commands and those of the commands it wraps. The commands without a key
+        // go with the slot of the first command which has one.
+        let keyless_slot = groups.iter().find_map(|(slot, _)| *slot).unwrap_or(0);
+        let requests = groups.iter().map(|(slot, indices)| {
+            let mut part = Pipeline::with_capacity(indices.len() + 2);
+            part.cmd("CLIENT").arg("REPLY").arg("OFF");
+            for &index in indices {
+                part.add_command(cmds[index].clone());
+            }
+            part.cmd("CLIENT").arg("REPLY").arg("ON");
+            let mut connection = self.with_slot(slot.unwrap_or(keyless_slot));
+            // `CLIENT REPLY ON` is the only command with a reply
+            async move { connection.req_packed_commands(&part, 0, 1).await }
+        });
+        future::join_all(requests)
+            .await
+            .into_iter()
+            .try_for_each(|result| result.map(drop))
+    }
+
+    // The indices of the commands of `pipeline` grouped by slot, `None` for the commands without
+    // a key, the groups in the order of their first command
+    fn pipeline_by_slot(&self, pipeline: &Pipeline) -> Vec<(Option<u16>, Vec<usize>)> {
+        let mut groups = Vec::<(Option<u16>, Vec<usize>)>::new();
+        let mut positions = HashMap::new();
+        for (index, cmd) in pipeline.cmd_iter().enumerate() {
+            let slot = self.cmd_slot(cmd);
+            let position = *positions.entry(slot).or_insert_with(|| {
+                groups.push((slot, Vec::new()));
+                groups.len() - 1
+            });
+            groups[position].1.push(index);
+        }
+        groups
+    }
+
+    fn with_optional_slot(&self, slot: Option<u16>) -> Self {
+        match slot {
+            Some(slot) => self.with_slot(slot),
+            None => self.clone(),
+        }
+    }
+
+    // Whether every slot belongs to the same master in the last published slot map
+    fn single_master(&self) -> bool {
+        self.shared.single_master.load(Ordering::Relaxed)
+    }
+
+    // The slot `cmd` is routed by once its keys are rewritten
+    pub(crate) fn cmd_slot(&self, cmd: &Cmd) -> Option<u16> {
+        if self.shared.keys.is_identity() {
+            slot_for_command(cmd)
+        } else {
+            slot_for_command(&self.shared.keys.cmd(cmd))
+        }
+    }
+
+    // The indices of `keys` grouped by the slot they are in once rewritten, see `by_slot`
+    pub(crate) fn by_slot<'a>(
+        &self,
+        keys: impl Iterator<Item = &'a [u8]>,
+    ) -> Vec<(u16, Vec<usize>)> {
+        if self.shared.keys.is_identity() {
+            return by_slot(keys);
+        }
+        let keys = keys
+            .map(|key| self.shared.keys.key(key))
+            .collect::<Vec<_>>();
+        by_slot(keys.iter().map(Vec::as_slice))
+    }
+
+    // Sends the command built by `build` for every group concurrently, routed by its slot
+    pub(crate) async fn per_slot<T>(
+        &self,
+        groups: &[(u16, Vec<usize>)],
+        build: impl Fn(&mut Cmd, &[usize]),
+    ) -> RedisResult<Vec<T>>
+    where
+        T: FromRedisValue,
+    {
+        let requests = groups.iter().map(|(slot, indices)| {
+            let mut cmd = Cmd::new();
+            build(&mut cmd, indices);
+            let mut connection = self.with_slot(*slot);
+            async move { cmd.query_async::<_, T>(&mut connection).await }
+        });
+        future::join_all(requests).await.into_iter().collect()
+    }
+}
+
+// Fails for the pipelines made atomic, which a pipeline only tells by its encoding. Only for the
+// pipelines handed to the helpers splitting them, those sent with `query_async` have an offset.
+fn reject_transaction(pipeline: &Pipeline) -> RedisResult<()> {
+    if pipeline
+        .get_packed_pipeline()
+        .starts_with(b"*1\r\n$5\r\nMULTI\r\n")
+    {
+        return Err(RedisError::from((
+            ErrorKind::ClientError,
+            "A transaction can not be split by slot",
+        )));
+    }
+    Ok(())
+}
+
+// The reply of a single `name` command for all the keys of `groups` from the reply of each group
+fn merge_replies(
+    name: &str,
+    groups: &[(u16, Vec<usize>)],
+    replies: Vec<Value>,
+) -> RedisResult<Value> {
+    match name {
+        "MGET" => {
+            let count = groups.iter().map(|(_, indices)| indices.len()).sum();
+            let mut values = vec![Value::Nil; count];
+            for ((_, indices), reply) in groups.iter().zip(replies) {
+                let reply: Vec<Value> = redis::from_redis_value(&reply)?;
+                for (&index, value) in indices.iter().zip(reply) {
+                    values[index] = value;
+                }
+            }
+            Ok(Value::Bulk(values))
+        }
+        "MSET" => Ok(Value::Okay),
+        _ => {
+            let mut count = 0;
+            for reply in &replies {
+                count += redis::from_redis_value::<i64>(reply)?;
+            }
+            Ok(Value::Int(count))
+        }
+    }
+}
+
+// The indices of `keys` grouped by slot, the groups in the order of their first key
+fn by_slot<'a>(keys: impl Iterator<Item = &'a [u8]>) -> Vec<(u16, Vec<usize>)> {
+    let mut groups = Vec::new();
+    let mut positions = HashMap::new();
+    for (index, key) in keys.enumerate() {
+        let slot = key_slot(key);
+        let position = *positions.entry(slot).or_insert_with(|| {
+            groups.push((slot, Vec::new()));
+            groups.len() - 1
+        });
+        groups[position].1.push(index);
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_grouped_by_slot() {
+        let keys: [&[u8]; 4] = [b"{a}1", b"b", b"{a}2", b"c"];
+        assert_eq!(
+            by_slot(keys.iter().copied()),
+            [
+                (key_slot(b"a"), vec![0, 2]),
+                (key_slot(b"b"), vec![1]),
+                (key_slot(b"c"), vec![3]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,453 @@
+//! Rewriting of the keys of every command: the hash tags of
+//! [`Client::set_hash_tag_rules`](crate::Client::set_hash_tag_rules) are added first, then the
+//! prefix of [`Client::set_key_prefix`](crate::Client::set_key_prefix).
+//!
+//! The keys of a command are found from its name, covering the commands whose keys are not just
+//! the first argument (`MGET`, `MSET`, `EVAL`, `XREAD`, `ZUNIONSTORE`, `BITOP`, `LCS`, the
+//! `STORE` option of `SORT`, ...) and those which have no key at all (`PING`, `CLUSTER`,
+//! `PUBLISH`, `PUBSUB`, ...). Any other command has its first argument
+//! rewritten, the same argument it is routed by. Key patterns (`KEYS`, `SCAN MATCH`) and the keys
+//! in replies are left as they are.
+
+use redis::{Arg, Cmd, Pipeline, Value};
+
+use crate::{
+    command,
+    hash_tag::{self, HashTagRule},
+};
+
+// Commands whose arguments are not keys
+const KEYLESS: &[&str] = &[
+    "ACL",
+    "AUTH",
+    "BGREWRITEAOF",
+    "BGSAVE",
+    "CLIENT",
+    "CLUSTER",
+    "COMMAND",
+    "CONFIG",
+    "DBSIZE",
+    "DEBUG",
+    "DISCARD",
+    "ECHO",
+    "EXEC",
+    "FLUSHALL",
+    "FLUSHDB",
+    "FUNCTION",
+    "HELLO",
+    "INFO",
+    "KEYS",
+    "LASTSAVE",
+    "LATENCY",
+    "MULTI",
+    "PING",
+    "PSUBSCRIBE",
+    "PUBLISH",
+    "PUBSUB",
+    "PUNSUBSCRIBE",
+    "RANDOMKEY",
+    "READONLY",
+    "READWRITE",
+    "SAVE",
+    "SCAN",
+    "SCRIPT",
+    "SELECT",
+    "SLOWLOG",
+    "SPUBLISH",
+    "SSUBSCRIBE",
+    "SUBSCRIBE",
+    "SUNSUBSCRIBE",
+    "TIME",
+    "UNSUBSCRIBE",
+    "UNWATCH",
+    "WAIT",
+];
+
+// Commands whose arguments are all keys
+const ALL_KEYS: &[&str] = &[
+    "DEL",
+    "EXISTS",
+    "MGET",
+    "PFCOUNT",
+    "PFMERGE",
+    "RENAME",
+    "RENAMENX",
+    "RPOPLPUSH",
+    "SDIFF",
+    "SDIFFSTORE",
+    "SINTER",
+    "SINTERSTORE",
+    "SUNION",
+    "SUNIONSTORE",
+    "TOUCH",
+    "UNLINK",
+    "WATCH",
+];
+
+// Commands whose arguments are all keys but the timeout at the end
+const KEYS_THEN_TIMEOUT: &[&str] = &["BLPOP", "BRPOP", "BZPOPMAX", "BZPOPMIN"];
+
+// Commands whose first two arguments are keys
+const TWO_KEYS: &[&str] = &[
+    "BLMOVE",
+    "BRPOPLPUSH",
+    "COPY",
+    "GEOSEARCHSTORE",
+    "LCS",
+    "LMOVE",
+    "SMOVE",
+    "ZRANGESTORE",
+];
+
+// Commands giving the number of their keys in an argument, with the index of that argument
+const COUNTED_KEYS: &[(&str, usize)] = &[
+    ("BLMPOP", 2),
+    ("BZMPOP", 2),
+    ("EVAL", 2),
+    ("EVALSHA", 2),
+    ("EVALSHA_RO", 2),
+    ("EVAL_RO", 2),
+    ("FCALL", 2),
+    ("FCALL_RO", 2),
+    ("LMPOP", 1),
+    ("SINTERCARD", 1),
+    ("ZDIFF", 1),
+    ("ZINTER", 1),
+    ("ZINTERCARD", 1),
+    ("ZMPOP", 1),
+    ("ZUNION", 1),
+];
+
+// Commands storing to their first argument and giving the number of the other keys after it
+const STORE_COUNTED_KEYS: &[&str] = &["ZDIFFSTORE", "ZINTERSTORE", "ZUNIONSTORE"];
+
+// Commands whose key follows a subcommand
+const SUBCOMMAND_KEY: &[&str] = &["MEMORY", "OBJECT", "XGROUP", "XINFO"];
+
+// Commands whose arguments after the subcommand are all keys
+const KEYS_AFTER_SUBCOMMAND: &[&str] = &["BITOP"];
+
+// Commands with a key first which may store their result to the key following a `STORE` or
+// `STOREDIST` option
+const STORE_OPTION: &[&str] = &["GEORADIUS", "GEORADIUSBYMEMBER", "SORT"];
+
+// The indices of the arguments of `args` (the command name first) which are keys
+pub(crate) fn key_positions(args: &[&[u8]]) -> Vec<usize> {
+    let name = match args.first() {
+        Some(name) => *name,
+        None => return Vec::new(),
+    };
+    let is = |commands: &[&str]| command::is_one_of(name, commands);
+    let counted = |count_at: usize| -> Vec<usize> {
+        let count = args
+            .get(count_at)
+            .and_then(|count| std::str::from_utf8(count).ok())
+            .and_then(|count| count.parse::<usize>().ok())
+            .unwrap_or(0);
+        (count_at + 1..args.len()).take(count).collect()
+    };
+    let positions = if is(KEYLESS) {
+        Vec::new()
+    } else if is(ALL_KEYS) {
+        (1..args.len()).collect()
+    } else if is(KEYS_THEN_TIMEOUT) {
+        (1..args.len().saturating_sub(1)).collect()
+    } else if is(TWO_KEYS) {
+        vec![1, 2]
+    } else if is(&["MSET", "MSETNX"]) {
+        (1..args.len()).step_by(2).collect()
+    } else if let Some((_, count_at)) = COUNTED_KEYS
+        .iter()
+        .find(|(command, _)| command.as_bytes().eq_ignore_ascii_case(name))
+    {
+        counted(*count_at)
+    } else if is(STORE_COUNTED_KEYS) {
+        let mut positions = vec![1];
+        positions.extend(counted(2));
+        positions
+    } else if is(SUBCOMMAND_KEY) {
+        vec![2]
+    } else if is(KEYS_AFTER_SUBCOMMAND) {
+        (2..args.len()).collect()
+    } else if is(STORE_OPTION) {
+        let mut positions = vec![1];
+        positions.extend(
+            (2..args.len())
+                .filter(|&position| command::is_one_of(args[position], &["STORE", "STOREDIST"]))
+                .map(|position| position + 1),
+        );
+        positions
+    } else if is(&["XREAD", "XREADGROUP"]) {
+        // The keys are the first half of the arguments after `STREAMS`, the ids the second half
+        match args
+            .iter()
+            .position(|arg| arg.eq_ignore_ascii_case(b"STREAMS"))
+        {
+            Some(streams) => {
+                let keys = (args.len() - streams - 1) / 2;
+                (streams + 1..streams + 1 + keys).collect()
+            }
+            None => Vec::new(),
+        }
+    } else {
+        vec![1]
+    };
+    positions
+        .into_iter()
+        .filter(|&position| position < args.len())
+        .collect()
+}
+
+// How the keys of the commands are rewritten before they are sent
+#[derive(Clone, Debug, Default)]
+pub(crate) struct KeyRewrite {
+    pub(crate) prefix: Option<Vec<u8>>,
+    pub(crate) hash_tags: Vec<HashTagRule>,
+}
+
+impl KeyRewrite {
+    // Whether keys are left as they are
+    pub(crate) fn is_identity(&self) -> bool {
+        self.prefix.is_none() && self.hash_tags.is_empty()
+    }
+
+    pub(crate) fn key(&self, key: &[u8]) -> Vec<u8> {
+        let tagged = hash_tag::apply(&self.hash_tags, key);
+        let key = tagged.as_deref().unwrap_or(key);
+        match &self.prefix {
+            Some(prefix) => [&prefix[..], key].concat(),
+            None => key.to_vec(),
+        }
+    }
+
+    // `pattern` matching the keys of the prefix only, with the glob characters of the prefix
+    // escaped
+    pub(crate) fn pattern(&self, pattern: &str) -> Vec<u8> {
+        let mut rewritten = Vec::new();
+        for &byte in self.prefix.iter().flatten() {
+            if matches!(byte, b'*' | b'?' | b'[' | b']' | b'\\') {
+                rewritten.push(b'\\');
+            }
+            rewritten.push(byte);
+        }
+        rewritten.extend_from_slice(pattern.as_bytes());
+        rewritten
+    }
+
+    // `key` as read from a node with the prefix removed, `None` if it is not under the prefix
+    pub(crate) fn strip_prefix<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]> {
+        match &self.prefix {
+            Some(prefix) => key.strip_prefix(&prefix[..]),
+            None => Some(key),
+        }
+    }
+
+    // `cmd` with its keys rewritten
+    pub(crate) fn cmd(&self, cmd: &Cmd) -> Cmd {
+        let packed;
+        let args = if cmd.args_iter().any(|arg| matches!(arg, Arg::Cursor)) {
+            // The cursor of a `SCAN` like command is only known once the command is packed
+            packed = match redis::parse_redis_value(&cmd.get_packed_command()) {
+                Ok(Value::Bulk(args)) => args,
+                _ => return cmd.clone(),
+            };
+            packed
+                .iter()
+                .map(|arg| match arg {
+                    Value::Data(arg) => &arg[..],
+                    _ => &[][..],
+                })
+                .collect::<Vec<_>>()
+        } else {
+            cmd.args_iter()
+                .map(|arg| match arg {
+                    Arg::Simple(arg) => arg,
+                    Arg::Cursor => unreachable!(),
+                })
+                .collect::<Vec<_>>()
+        };
+        let positions = key_positions(&args);
+        let mut rewritten = Cmd::new();
+        for (index, arg) in args.iter().enumerate() {
+            if positions.contains(&index) {
+                rewritten.arg(self.key(arg));
+            } else {
+                rewritten.arg(*arg);
+            }
+        }
+        rewritten
+    }
+
+    // `pipeline` with the keys of each command rewritten, sent as a transaction if `atomic`
+    pub(crate) fn pipeline(&self, pipeline: &Pipeline, atomic: bool) -> Pipeline {
+        let mut rewritten = Pipeline::new();
+        if atomic {
+            rewritten.atomic();
+        }
+        for cmd in pipeline.cmd_iter() {
+            rewritten.add_command(self.cmd(cmd));
+        }
+        rewritten
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use redis::cmd;
+
+    fn rewritten(keys: &KeyRewrite, cmd: &Cmd) -> Vec<String> {
+        keys.cmd(cmd)
+            .args_iter()
+            .map(|arg| match arg {
+                Arg::Simple(arg) => String::from_utf8_lossy(arg).into_owned(),
+                Arg::Cursor => unreachable!(),
+            })
+            .collect()
+    }
+
+    fn prefixed(cmd: &Cmd) -> Vec<String> {
+        let keys = KeyRewrite {
+            prefix: Some(b"t:".to_vec()),
+            ..Default::default()
+        };
+        rewritten(&keys, cmd)
+    }
+
+    #[test]
+    fn keys_are_prefixed() {
+        assert_eq!(
+            prefixed(cmd("SET").arg("key").arg("value")),
+            ["SET", "t:key", "value"]
+        );
+        assert_eq!(
+            prefixed(cmd("mset").arg("a").arg(1).arg("b").arg(2)),
+            ["mset", "t:a", "1", "t:b", "2"]
+        );
+        assert_eq!(
+            prefixed(cmd("BLPOP").arg("a").arg("b").arg(0)),
+            ["BLPOP", "t:a", "t:b", "0"]
+        );
+        assert_eq!(
+            prefixed(cmd("EVAL").arg("script").arg(1).arg("a").arg("arg")),
+            ["EVAL", "script", "1", "t:a", "arg"]
+        );
+        assert_eq!(
+            prefixed(cmd("ZUNIONSTORE").arg("d").arg(2).arg("a").arg("b")),
+            ["ZUNIONSTORE", "t:d", "2", "t:a", "t:b"]
+        );
+        assert_eq!(
+            prefixed(
+                cmd("XREAD")
+                    .arg("COUNT")
+                    .arg(1)
+                    .arg("STREAMS")
+                    .arg("a")
+                    .arg("b")
+                    .arg("0")
+                    .arg("0")
+            ),
+            ["XREAD", "COUNT", "1", "STREAMS", "t:a", "t:b", "0", "0"]
+        );
+        assert_eq!(
+            prefixed(cmd("OBJECT").arg("ENCODING").arg("a")),
+            ["OBJECT", "ENCODING", "t:a"]
+        );
+        assert_eq!(
+            prefixed(cmd("PUBLISH").arg("channel").arg("message")),
+            ["PUBLISH", "channel", "message"]
+        );
+        assert_eq!(
+            prefixed(cmd("SSCAN").arg("set").cursor_arg(12)),
+            ["SSCAN", "t:set", "12"]
+        );
+    }
+
+    #[test]
+    fn keys_are_found_past_the_first_argument() {
+        assert_eq!(
+            prefixed(cmd("BITOP").arg("AND").arg("d").arg("a").arg("b")),
+            ["BITOP", "AND", "t:d", "t:a", "t:b"]
+        );
+        assert_eq!(
+            prefixed(cmd("LCS").arg("a").arg("b").arg("LEN")),
+            ["LCS", "t:a", "t:b", "LEN"]
+        );
+        assert_eq!(
+            prefixed(cmd("PUBSUB").arg("CHANNELS").arg("news.*")),
+            ["PUBSUB", "CHANNELS", "news.*"]
+        );
+        assert_eq!(
+            prefixed(
+                cmd("SORT")
+                    .arg("a")
+                    .arg("LIMIT")
+                    .arg(0)
+                    .arg(5)
+                    .arg("STORE")
+                    .arg("d")
+            ),
+            ["SORT", "t:a", "LIMIT", "0", "5", "STORE", "t:d"]
+        );
+        assert_eq!(
+            prefixed(
+                cmd("GEORADIUS")
+                    .arg("a")
+                    .arg(15)
+                    .arg(37)
+                    .arg(200)
+                    .arg("km")
+                    .arg("STOREDIST")
+                    .arg("d")
+            ),
+            [
+                "GEORADIUS",
+                "t:a",
+                "15",
+                "37",
+                "200",
+                "km",
+                "STOREDIST",
+                "t:d"
+            ]
+        );
+        assert_eq!(
+            prefixed(
+                cmd("GEORADIUSBYMEMBER")
+                    .arg("a")
+                    .arg("m")
+                    .arg(200)
+                    .arg("km")
+                    .arg("STORE")
+                    .arg("d")
+            ),
+            ["GEORADIUSBYMEMBER", "t:a", "m", "200", "km", "STORE", "t:d"]
+        );
+        assert_eq!(prefixed(cmd("SORT").arg("a")), ["SORT", "t:a"]);
+    }
+
+    #[test]
+    fn scanned_keys_are_unprefixed() {
+        let keys = KeyRewrite {
+            prefix: Some(b"t*:".to_vec()),
+            ..Default::default()
+        };
+        assert_eq!(keys.pattern("user:*"), b"t\\*:user:*");
+        assert_eq!(keys.strip_prefix(b"t*:user:1"), Some(&b"user:1"[..]));
+        assert_eq!(keys.strip_prefix(b"other"), None);
+        assert_eq!(KeyRewrite::default().pattern("*"), b"*");
+    }
+
+    #[test]
+    fn keys_are_tagged_then_prefixed() {
+        let keys = KeyRewrite {
+            prefix: Some(b"t:".to_vec()),
+            hash_tags: vec![HashTagRule::new("user:*")],
+        };
+        assert_eq!(
+            rewritten(&keys, cmd("MGET").arg("user:1").arg("other")),
+            ["MGET", "t:{user}:1", "t:other"]
+        );
+    }
+}
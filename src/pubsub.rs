@@ -0,0 +1,210 @@
+//! Pub/Sub subscriptions kept up across failovers, see [`Connection::subscribe`].
+//!
+//! Messages published to a channel reach every node of the cluster, so a subscription is held
+//! on a single node connection of its own, opened to the master of the slot of the first
+//! channel. When that connection is lost or the node leaves the cluster, the slot map is
+//! refreshed and the channels are subscribed to again on the master of the slot then.
+
+use std::{io, time::Duration};
+
+use futures::{
+    future::{self, Either},
+    stream::{self, BoxStream, Stream, StreamExt},
+};
+use log::{trace, warn};
+use redis::{aio::ConnectionLike, Msg, RedisResult};
+
+use crate::{key_slot, node_name, Connection, NodeAddr, TopologyEvent};
+
+// Delay before subscribing again after the first failure, doubled with every further failure
+const RESUBSCRIBE_DELAY: Duration = Duration::from_millis(100);
+const MAX_RESUBSCRIBE_DELAY: Duration = Duration::from_secs(5);
+// Time allowed to connect and subscribe when no connection timeout is set
+const SUBSCRIBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+impl<C> Connection<C>
+where
+    C: ConnectionLike + Send + 'static,
+{
+    /// Subscribes to `channels` and returns the stream of the messages published to them. The
+    /// stream never ends: the subscription is made again on another node if its node fails over
+    /// or leaves the cluster, the messages published in the meantime being lost.
+    ///
+    /// The subscription uses a connection of its own, opened with `redis::Client` over tokio
+    /// whichever node connections this connection uses, with the credentials and TLS settings
+    /// of the cluster, see [`Client`](crate::Client). Connecting and subscribing fail after the
+    /// connection timeout of the client, or 10 seconds if it has none, and are tried again.
+    /// Dropping the stream closes it.
+    ///
+    /// ```rust,no_run
+    /// use futures::StreamExt;
+    ///
+    /// # async fn run(connection: redis_cluster_async::Connection) {
+    /// let mut messages = connection.subscribe(vec!["news".to_string()]);
+    /// while let Some(message) = messages.next().await {
+    ///     let payload: String = message.get_payload().unwrap();
+    ///     println!("{}: {}", message.get_channel_name(), payload);
+    /// }
+    /// # }
+    /// ```
+    pub fn subscribe(&self, channels: Vec<String>) -> impl Stream<Item = Msg> + Send + Unpin {
+        self.subscription(Kind::Channels, channels)
+    }
+
+    /// Subscribes to the channels matching `patterns`, see [`Connection::subscribe`].
+    pub fn psubscribe(&self, patterns: Vec<String>) -> impl Stream<Item = Msg> + Send + Unpin {
+        self.subscription(Kind::Patterns, patterns)
+    }
+
+    fn subscription(
+        &self,
+        kind: Kind,
+        channels: Vec<String>,
+    ) -> impl Stream<Item = Msg> + Send + Unpin {
+        let subscription = Subscription {
+            slot: channels
+                .first()
+                .map_or(0, |channel| key_slot(channel.as_bytes())),
+            topology: self.topology_events().boxed(),
+            connection: self.clone(),
+            kind,
+            channels,
+            node: None,
+            failures: 0,
+        };
+        stream::unfold(subscription, |mut subscription| async move {
+            let message = subscription.next().await;
+            Some((message, subscription))
+        })
+        .boxed()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Kind {
+    Channels,
+    Patterns,
+}
+
+struct Subscription<C> {
+    connection: Connection<C>,
+    kind: Kind,
+    channels: Vec<String>,
+    // The slot whose master is subscribed to
+    slot: u16,
+    topology: BoxStream<'static, TopologyEvent>,
+    // `host:port` of the subscribed node and its messages
+    node: Option<(String, BoxStream<'static, Msg>)>,
+    // Failures to subscribe since the last success
+    failures: u32,
+}
+
+impl<C> Subscription<C>
+where
+    C: ConnectionLike + Send + 'static,
+{
+    async fn next(&mut self) -> Msg {
+        loop {
+            let (node, messages) = match &mut self.node {
+                Some(node) => node,
+                None => {
+                    self.resubscribe().await;
+                    continue;
+                }
+            };
+            match future::select(messages.next(), self.topology.next()).await {
+                Either::Left((Some(message), _)) => return message,
+                Either::Left((None, _)) => {
+                    warn!("Lost the subscription connection to {}", node);
+                    self.node = None;
+                    self.refresh_slots().await;
+                }
+                // The connection was dropped, the subscription still works on its own
+                Either::Right((None, _)) => self.topology = stream::pending().boxed(),
+                Either::Right((Some(_), _)) => {
+                    let node = node.clone();
+                    if !self.connection.node_names(None).contains(&node) {
+                        trace!("Subscribing again as {} left the cluster", node);
+                        self.node = None;
+                    }
+                }
+            }
+        }
+    }
+
+    // Subscribes on the master of the slot, waiting first if the last attempts failed
+    async fn resubscribe(&mut self) {
+        if self.failures > 0 {
+            let delay = RESUBSCRIBE_DELAY
+                .saturating_mul(2u32.saturating_pow(self.failures - 1))
+                .min(MAX_RESUBSCRIBE_DELAY);
+            self.connection.shared.clock.sleep(delay).await;
+        }
+        let addr = match self.master() {
+            Some(addr) => addr,
+            None => {
+                self.failures += 1;
+                self.refresh_slots().await;
+                return;
+            }
+        };
+        match self.subscribe_on(&addr).await {
+            Ok(messages) => {
+                trace!("Subscribed on {}", node_name(&addr));
+                self.failures = 0;
+                self.node = Some((node_name(&addr), messages));
+            }
+            Err(err) => {
+                warn!("Could not subscribe on {}: {}", node_name(&addr), err);
+                self.failures += 1;
+                self.refresh_slots().await;
+            }
+        }
+    }
+
+    fn master(&self) -> Option<NodeAddr> {
+        let slots = self.connection.shared.slots.load();
+        let (_, addrs) = slots.range(&self.slot..).next()?;
+        Some(addrs.master.clone())
+    }
+
+    // Subscribes on `addr`, failing if that takes longer than the connection timeout
+    async fn subscribe_on(&mut self, addr: &str) -> RedisResult<BoxStream<'static, Msg>> {
+        let shared = self.connection.shared.clone();
+        let timeout = shared.connection_timeout.unwrap_or(SUBSCRIBE_TIMEOUT);
+        let subscribe = open_subscription(addr, self.kind, &self.channels);
+        futures::pin_mut!(subscribe);
+        match future::select(subscribe, shared.clock.sleep(timeout)).await {
+            Either::Left((result, _)) => result,
+            Either::Right(_) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Subscribing on the node timed out",
+            )
+            .into()),
+        }
+    }
+
+    // A command failing on a connection error to the master of the slot refreshes the slot map
+    async fn refresh_slots(&mut self) {
+        let mut connection = self.connection.with_slot(self.slot);
+        let _ = redis::cmd("PING")
+            .query_async::<_, redis::Value>(&mut connection)
+            .await;
+    }
+}
+
+async fn open_subscription(
+    addr: &str,
+    kind: Kind,
+    channels: &[String],
+) -> RedisResult<BoxStream<'static, Msg>> {
+    let client = redis::Client::open(addr)?;
+    let mut pubsub = client.get_async_connection().await?.into_pubsub();
+    for channel in channels {
+        match kind {
+            Kind::Channels => pubsub.subscribe(channel).await?,
+            Kind::Patterns => pubsub.psubscribe(channel).await?,
+        }
+    }
+    Ok(pubsub.into_on_message().boxed())
+}
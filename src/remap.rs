@@ -0,0 +1,87 @@
+/// Rewrites a node address announced by the cluster before connecting to it.
+///
+/// Some managed services put the cluster behind a proxy which exposes every node on its own port
+/// of a single host, while `CLUSTER SLOTS` keeps announcing the addresses the nodes know
+/// themselves by. Rules are installed with
+/// [`Client::set_remap_rules`](crate::Client::set_remap_rules) and the first rule which matches an
+/// announced address decides what it is rewritten to.
+///
+/// ```rust
+/// use redis_cluster_async::RemapRule;
+///
+/// let rules = vec![
+///     RemapRule::new().host("10.0.0.1").to_host("cache.example.com").to_port(15000),
+///     RemapRule::new().host("10.0.0.2").to_host("cache.example.com").to_port(15001),
+/// ];
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RemapRule {
+    host: Option<String>,
+    port: Option<u16>,
+    to_host: Option<String>,
+    to_port: Option<u16>,
+}
+
+impl RemapRule {
+    /// Create a rule which matches every address and leaves it unchanged.
+    pub fn new() -> Self {
+        RemapRule::default()
+    }
+
+    /// Only match addresses whose host is `host`.
+    pub fn host(mut self, host: impl Into<String>) -> Self {
+        self.host = Some(host.into());
+        self
+    }
+
+    /// Only match addresses whose port is `port`.
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
+
+    /// Replace the host of matching addresses with `host`.
+    pub fn to_host(mut self, host: impl Into<String>) -> Self {
+        self.to_host = Some(host.into());
+        self
+    }
+
+    /// Replace the port of matching addresses with `port`.
+    pub fn to_port(mut self, port: u16) -> Self {
+        self.to_port = Some(port);
+        self
+    }
+
+    fn matches(&self, host: &str, port: u16) -> bool {
+        !matches!(&self.host, Some(h) if h != host) && !matches!(self.port, Some(p) if p != port)
+    }
+}
+
+// Applies the first rule matching `host:port`
+pub(crate) fn remap<'a>(rules: &'a [RemapRule], host: &'a str, port: u16) -> (&'a str, u16) {
+    match rules.iter().find(|rule| rule.matches(host, port)) {
+        Some(rule) => (
+            rule.to_host.as_deref().unwrap_or(host),
+            rule.to_port.unwrap_or(port),
+        ),
+        None => (host, port),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_matching_rule_applies() {
+        let rules = vec![
+            RemapRule::new().host("10.0.0.1").port(6379).to_port(15000),
+            RemapRule::new().host("10.0.0.1").to_host("proxy"),
+            RemapRule::new().port(6380).to_host("other").to_port(16000),
+        ];
+        assert_eq!(remap(&rules, "10.0.0.1", 6379), ("10.0.0.1", 15000));
+        assert_eq!(remap(&rules, "10.0.0.1", 6380), ("proxy", 6380));
+        assert_eq!(remap(&rules, "10.0.0.2", 6380), ("other", 16000));
+        assert_eq!(remap(&rules, "10.0.0.2", 6379), ("10.0.0.2", 6379));
+    }
+}
@@ -0,0 +1,134 @@
+use std::time::Duration;
+
+use rand::{thread_rng, Rng};
+
+/// How commands are sent again after an error or a redirection, see
+/// [`Client::set_retry_config`](crate::Client::set_retry_config).
+///
+/// After `TRYAGAIN` and `CLUSTERDOWN` a command waits `base_delay * 2^retry`, kept between the
+/// minimum and maximum delay, before it is sent again. Other errors and redirections send it
+/// again right away, to another node, to the node importing the slot after `ASK`, or once the
+/// slot map has been refreshed.
+///
+/// ```rust
+/// use std::time::Duration;
+/// use redis_cluster_async::RetryConfig;
+///
+/// let retry = RetryConfig::new()
+///     .max_retries(Some(5))
+///     .max_redirects(Some(3))
+///     .min_delay(Duration::from_millis(50))
+///     .max_delay(Duration::from_secs(2))
+///     .jitter(0.5);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetryConfig {
+    pub(crate) max_retries: Option<u32>,
+    max_redirects: Option<u32>,
+    base_delay: Duration,
+    min_delay: Duration,
+    max_delay: Duration,
+    jitter: f64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            max_retries: Some(16),
+            max_redirects: None,
+            base_delay: Duration::from_millis(10),
+            min_delay: Duration::from_millis(1280),
+            max_delay: Duration::from_millis(655_360),
+            jitter: 0.0,
+        }
+    }
+}
+
+impl RetryConfig {
+    /// Create the default policy.
+    pub fn new() -> Self {
+        RetryConfig::default()
+    }
+
+    /// How many times a command is sent again, whatever the reason, `None` to retry forever.
+    /// Default: 16
+    pub fn max_retries(mut self, max_retries: Option<u32>) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// How many `MOVED` and `ASK` redirections a command follows. The next one fails it with a
+    /// client error naming the redirections, instead of the error of the last attempt.
+    /// Default: `None`, redirections only count as retries
+    pub fn max_redirects(mut self, max_redirects: Option<u32>) -> Self {
+        self.max_redirects = max_redirects;
+        self
+    }
+
+    /// Delay doubled with every retry after `TRYAGAIN` or `CLUSTERDOWN`. Default: 10ms
+    pub fn base_delay(mut self, delay: Duration) -> Self {
+        self.base_delay = delay;
+        self
+    }
+
+    /// Shortest delay before sending a command again. Default: 1.28s
+    pub fn min_delay(mut self, delay: Duration) -> Self {
+        self.min_delay = delay;
+        self
+    }
+
+    /// Longest delay before sending a command again. Default: 655.36s
+    pub fn max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+
+    /// Share of each delay, between 0 and 1, randomly taken off it, so that clients failing
+    /// together do not retry together. Default: 0
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    pub(crate) fn redirects_exceeded(&self, redirects: usize) -> bool {
+        matches!(self.max_redirects, Some(max) if redirects > max as usize)
+    }
+
+    // The delay before the `retry`th attempt after `TRYAGAIN` or `CLUSTERDOWN`
+    pub(crate) fn delay(&self, retry: u32) -> Duration {
+        let delay = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry))
+            .max(self.min_delay)
+            .min(self.max_delay);
+        if self.jitter > 0.0 {
+            delay.mul_f64(1.0 - self.jitter * thread_rng().gen::<f64>())
+        } else {
+            delay
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delays_double_within_bounds() {
+        let delays = (1..=18)
+            .map(|retry| RetryConfig::new().delay(retry).as_millis())
+            .collect::<Vec<_>>();
+        assert_eq!(delays[..7], [1280; 7]);
+        assert_eq!(delays[7..10], [2560, 5120, 10240]);
+        assert_eq!(delays[15..], [655_360; 3]);
+
+        let jittered = RetryConfig::new()
+            .min_delay(Duration::from_secs(1))
+            .max_delay(Duration::from_secs(1))
+            .jitter(0.5);
+        for retry in 0..10 {
+            let delay = jittered.delay(retry);
+            assert!(delay >= Duration::from_millis(500) && delay <= Duration::from_secs(1));
+        }
+    }
+}
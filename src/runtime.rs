@@ -0,0 +1,40 @@
+use std::{io, net::IpAddr};
+
+use futures::future::{BoxFuture, FutureExt};
+
+/// The async runtime a cluster connection runs on: it spawns the driver of the connection and
+/// its background tasks and resolves the hostnames of nodes.
+///
+/// The default, [`TokioRuntime`], uses tokio. The other runtime services are pluggable on their
+/// own: timers come from the [`Clock`](crate::Clock) and node connections are opened by the
+/// [`Connect`](crate::Connect) implementation of the connection type, so another runtime needs
+/// an implementation of each of them (redis-rs provides async-std connections). Tests can install
+/// a runtime which resolves hostnames without DNS with
+/// [`Client::set_runtime`](crate::Client::set_runtime).
+pub trait Runtime: Send + Sync + 'static {
+    /// Runs `task` in the background until it completes.
+    fn spawn(&self, task: BoxFuture<'static, ()>);
+
+    /// Resolves `host` to the addresses of its node, in the order they should be tried.
+    fn lookup_host(&self, host: String, port: u16) -> BoxFuture<'static, io::Result<Vec<IpAddr>>>;
+}
+
+/// A [`Runtime`] backed by tokio. Connections must be created within a tokio runtime.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TokioRuntime;
+
+impl Runtime for TokioRuntime {
+    fn spawn(&self, task: BoxFuture<'static, ()>) {
+        tokio::spawn(task);
+    }
+
+    fn lookup_host(&self, host: String, port: u16) -> BoxFuture<'static, io::Result<Vec<IpAddr>>> {
+        async move {
+            Ok(tokio::net::lookup_host((&*host, port))
+                .await?
+                .map(|addr| addr.ip())
+                .collect())
+        }
+        .boxed()
+    }
+}
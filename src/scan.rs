@@ -0,0 +1,519 @@
+//! Utilities walking the keys of every master with `SCAN`.
+//!
+//! Masters are scanned one after the other, in `host:port` order, so a scan only ever keeps one
+//! command in flight. Keys are matched and reported without the prefix of
+//! [`Client::set_key_prefix`](crate::Client::set_key_prefix), keys outside of it are skipped.
+//!
+//! The masters left to scan follow the topology: before moving on to the next master, those
+//! which are not masters anymore are dropped and those which became masters since the scan
+//! started (a promoted replica, a master added to the cluster) are scanned last.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    time::Duration,
+};
+
+use futures::{stream, Stream, StreamExt};
+use redis::{aio::ConnectionLike, FromRedisValue, Pipeline, RedisResult, Value};
+
+use crate::{Connection, NodeRole, Route};
+
+// The `COUNT` of the scans which do not let it be chosen
+const SCAN_COUNT: usize = 100;
+
+/// Which keys [`Connection::ttl_audit`] reports.
+///
+/// ```rust
+/// use std::time::Duration;
+/// use redis_cluster_async::TtlAudit;
+///
+/// // Cache entries which never expire or live for more than a day, 1000 keys per second
+/// let audit = TtlAudit::new("cache:*")
+///     .max_ttl(Duration::from_secs(24 * 60 * 60))
+///     .keys_per_second(1000);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TtlAudit {
+    pattern: String,
+    max_ttl: Option<Duration>,
+    batch_size: usize,
+    keys_per_second: Option<u32>,
+}
+
+impl TtlAudit {
+    /// Audit the keys matching `pattern`, a `SCAN MATCH` glob, reporting those without a TTL.
+    pub fn new(pattern: impl Into<String>) -> Self {
+        TtlAudit {
+            pattern: pattern.into(),
+            max_ttl: None,
+            batch_size: 100,
+            keys_per_second: None,
+        }
+    }
+
+    /// Also report the keys expiring in more than `ttl`. Default: only keys without a TTL
+    pub fn max_ttl(mut self, ttl: Duration) -> Self {
+        self.max_ttl = Some(ttl);
+        self
+    }
+
+    /// The `COUNT` of each `SCAN`, the TTLs of the keys found by one `SCAN` are then asked for
+    /// in a single pipeline. Default: 100
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Walk at most this many keys per second, each `SCAN` counting as its `COUNT` (see
+    /// [`TtlAudit::batch_size`]) whether the keys match or not, as that is what it costs the
+    /// master. Default: no limit
+    pub fn keys_per_second(mut self, keys_per_second: u32) -> Self {
+        self.keys_per_second = Some(keys_per_second.max(1));
+        self
+    }
+}
+
+/// A key reported by [`Connection::ttl_audit`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyTtl {
+    /// The master holding the key, as `host:port`.
+    pub node: String,
+    /// The key, without the prefix of the connection.
+    pub key: Vec<u8>,
+    /// The time left before the key expires, `None` if it never does.
+    pub ttl: Option<Duration>,
+}
+
+/// Which keys [`Connection::encoding_audit`] samples.
+///
+/// ```rust
+/// use redis_cluster_async::EncodingAudit;
+///
+/// // Sample 50000 hashes of users, at most 2000 keys per second
+/// let audit = EncodingAudit::new("user:*").sample_size(50_000).keys_per_second(2000);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EncodingAudit {
+    pattern: String,
+    sample_size: usize,
+    batch_size: usize,
+    keys_per_second: Option<u32>,
+}
+
+impl EncodingAudit {
+    /// Sample the keys matching `pattern`, a `SCAN MATCH` glob.
+    pub fn new(pattern: impl Into<String>) -> Self {
+        EncodingAudit {
+            pattern: pattern.into(),
+            sample_size: 10_000,
+            batch_size: 100,
+            keys_per_second: None,
+        }
+    }
+
+    /// Number of keys sampled, spread evenly over the masters. Default: 10000
+    pub fn sample_size(mut self, sample_size: usize) -> Self {
+        self.sample_size = sample_size;
+        self
+    }
+
+    /// The `COUNT` of each `SCAN`, see [`TtlAudit::batch_size`]. Default: 100
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Walk at most this many keys per second, see [`TtlAudit::keys_per_second`].
+    /// Default: no limit
+    pub fn keys_per_second(mut self, keys_per_second: u32) -> Self {
+        self.keys_per_second = Some(keys_per_second.max(1));
+        self
+    }
+}
+
+/// The encodings of the keys sampled by [`Connection::encoding_audit`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EncodingReport {
+    /// Number of keys sampled.
+    pub sampled: u64,
+    /// Number of sampled keys by type (`hash`, `zset`, ...), then by `OBJECT ENCODING`
+    /// (`listpack`, `hashtable`, ...).
+    pub encodings: HashMap<String, HashMap<String, u64>>,
+}
+
+impl EncodingReport {
+    /// The share of the sampled keys of `key_type` which have `encoding`, between 0 and 1. `None`
+    /// if no key of that type was sampled.
+    pub fn share(&self, key_type: &str, encoding: &str) -> Option<f64> {
+        let encodings = self.encodings.get(key_type)?;
+        let total = encodings.values().sum::<u64>();
+        let count = encodings.get(encoding).copied().unwrap_or(0);
+        Some(count as f64 / total as f64)
+    }
+}
+
+impl<C> Connection<C>
+where
+    C: ConnectionLike + Send + 'static,
+{
+    /// Returns a stream of the keys of every master, see [`Connection::scan_match`].
+    pub fn scan<K>(&self) -> impl Stream<Item = RedisResult<K>> + Send + Unpin
+    where
+        K: FromRedisValue + Send + 'static,
+    {
+        self.scan_match("*")
+    }
+
+    /// Returns a stream of the keys matching `pattern`, a `SCAN MATCH` glob, walking the `SCAN`
+    /// cursor of every master in turn. Unlike `SCAN` on a single node connection, which only
+    /// walks the node it happens to be sent to, this covers the whole cluster.
+    ///
+    /// A master failing to scan is reported as an error and skipped, the stream ends once every
+    /// master has been scanned. Keys may be reported twice or missed if slots move while
+    /// scanning, as with `SCAN` itself.
+    ///
+    /// ```rust,no_run
+    /// use futures::StreamExt;
+    ///
+    /// # async fn run(connection: redis_cluster_async::Connection) -> redis::RedisResult<()> {
+    /// let mut keys = connection.scan_match::<String>("user:*");
+    /// while let Some(key) = keys.next().await {
+    ///     println!("{}", key?);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn scan_match<K>(&self, pattern: &str) -> impl Stream<Item = RedisResult<K>> + Send + Unpin
+    where
+        K: FromRedisValue + Send + 'static,
+    {
+        let scan = MasterScan::new(self, pattern, SCAN_COUNT, None);
+        stream::unfold(
+            (scan, VecDeque::new()),
+            |(mut scan, mut found)| async move {
+                loop {
+                    if let Some(key) = found.pop_front() {
+                        let key = K::from_redis_value(&Value::Data(key));
+                        return Some((key, (scan, found)));
+                    }
+                    match scan.next_batch().await? {
+                        (_, Ok(keys)) => found.extend(keys),
+                        (_, Err(err)) => return Some((Err(err), (scan, found))),
+                    }
+                }
+            },
+        )
+        .boxed()
+    }
+
+    /// Scans every master for the keys matching the pattern of `audit` which have no TTL, or a
+    /// TTL above [`TtlAudit::max_ttl`], to catch a cache growing without bounds.
+    ///
+    /// A master failing to scan is reported as an error and skipped, the stream ends once every
+    /// master of the last discovered topology has been scanned. Keys may be reported twice or
+    /// missed if slots move while scanning, as with `SCAN` itself.
+    ///
+    /// ```rust,no_run
+    /// use futures::StreamExt;
+    /// use redis_cluster_async::TtlAudit;
+    ///
+    /// # async fn run(connection: redis_cluster_async::Connection) -> redis::RedisResult<()> {
+    /// let mut keys = connection.ttl_audit(TtlAudit::new("session:*").keys_per_second(5000));
+    /// while let Some(key) = keys.next().await {
+    ///     let key = key?;
+    ///     println!("{} {:?}", String::from_utf8_lossy(&key.key), key.ttl);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn ttl_audit(
+        &self,
+        audit: TtlAudit,
+    ) -> impl Stream<Item = RedisResult<KeyTtl>> + Send + Unpin {
+        let scan = MasterScan::new(
+            self,
+            &audit.pattern,
+            audit.batch_size,
+            audit.keys_per_second,
+        );
+        let max_ttl = audit.max_ttl;
+        stream::unfold(
+            (scan, VecDeque::new()),
+            move |(mut scan, mut found)| async move {
+                loop {
+                    if let Some(key) = found.pop_front() {
+                        return Some((Ok(key), (scan, found)));
+                    }
+                    let (node, keys) = scan.next_batch().await?;
+                    let ttls = match keys {
+                        Ok(keys) => scan.ttls(&node, keys).await,
+                        Err(err) => Err(err),
+                    };
+                    match ttls {
+                        Ok(ttls) => found.extend(ttls.into_iter().filter_map(|(key, ttl)| {
+                            let ttl = match ttl {
+                                // The key expired or was deleted since it was scanned
+                                -2 => return None,
+                                -1 => None,
+                                ttl => Some(Duration::from_millis(ttl.max(0) as u64)),
+                            };
+                            let reported = match (ttl, max_ttl) {
+                                (None, _) => true,
+                                (Some(ttl), Some(max_ttl)) => ttl > max_ttl,
+                                (Some(_), None) => false,
+                            };
+                            reported.then(|| KeyTtl {
+                                node: node.clone(),
+                                key,
+                                ttl,
+                            })
+                        })),
+                        Err(err) => return Some((Err(err), (scan, found))),
+                    }
+                }
+            },
+        )
+        .boxed()
+    }
+
+    /// Samples keys matching the pattern of `audit` on every master and counts their encoding
+    /// per type, to tune settings like `hash-max-listpack-entries` from the data actually held.
+    /// Fails with the first error of a master.
+    ///
+    /// ```rust,no_run
+    /// use redis_cluster_async::EncodingAudit;
+    ///
+    /// # async fn run(connection: redis_cluster_async::Connection) -> redis::RedisResult<()> {
+    /// let report = connection.encoding_audit(EncodingAudit::new("*")).await?;
+    /// if let Some(share) = report.share("hash", "hashtable") {
+    ///     println!("{:.0}% of the hashes are hash tables", share * 100.0);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn encoding_audit(&self, audit: EncodingAudit) -> RedisResult<EncodingReport> {
+        let mut scan = MasterScan::new(
+            self,
+            &audit.pattern,
+            audit.batch_size,
+            audit.keys_per_second,
+        );
+        let mut report = EncodingReport::default();
+        // Each master samples its share of what is left, so that masters holding few matching
+        // keys leave more to the others
+        let mut quota = 0;
+        let mut sampled = 0;
+        let mut current = None;
+        while let Some((node, keys)) = scan.next_batch().await {
+            if current.as_ref() != Some(&node) {
+                let left = audit.sample_size.saturating_sub(report.sampled as usize);
+                if left == 0 {
+                    break;
+                }
+                quota = left.div_ceil(scan.masters_left());
+                sampled = 0;
+                current = Some(node.clone());
+            }
+            let mut keys = keys?;
+            keys.truncate(quota - sampled);
+            if keys.len() == quota - sampled {
+                scan.skip_master();
+            }
+            if keys.is_empty() {
+                continue;
+            }
+            let mut pipeline = redis::pipe();
+            for key in &keys {
+                pipeline
+                    .cmd("TYPE")
+                    .arg(&key[..])
+                    .cmd("OBJECT")
+                    .arg("ENCODING")
+                    .arg(&key[..]);
+            }
+            let replies: Vec<Value> = scan.query(&node, &pipeline).await?;
+            sampled += keys.len();
+            for reply in replies.chunks(2) {
+                let key_type = redis::from_redis_value::<String>(&reply[0])?;
+                // The key expired or was deleted since it was scanned
+                let encoding = match redis::from_redis_value::<Option<String>>(&reply[1])? {
+                    Some(encoding) if key_type != "none" => encoding,
+                    _ => continue,
+                };
+                report.sampled += 1;
+                *report
+                    .encodings
+                    .entry(key_type)
+                    .or_default()
+                    .entry(encoding)
+                    .or_default() += 1;
+            }
+        }
+        Ok(report)
+    }
+
+    /// Deletes the keys matching `pattern`, a `SCAN MATCH` glob, on every master and returns the
+    /// number of keys deleted: the safe replacement for `KEYS` followed by `DEL`, which blocks
+    /// the nodes while they list every key. The keys found by each `SCAN` are removed with one
+    /// `UNLINK` per slot, so the memory is reclaimed in the background.
+    ///
+    /// Walks at most `keys_per_second` keys per second if given, see
+    /// [`TtlAudit::keys_per_second`]. Fails with the first error, the keys deleted until then
+    /// stay deleted. Keys written while deleting may be left.
+    pub async fn delete_pattern(
+        &self,
+        pattern: &str,
+        keys_per_second: Option<u32>,
+    ) -> RedisResult<u64> {
+        let mut scan = MasterScan::new(self, pattern, SCAN_COUNT, keys_per_second);
+        let mut deleted = 0;
+        while let Some((_, keys)) = scan.next_batch().await {
+            let keys = keys?;
+            let groups = self.by_slot(keys.iter().map(Vec::as_slice));
+            let unlinked = self
+                .per_slot::<u64>(&groups, |cmd, indices| {
+                    cmd.arg("UNLINK");
+                    for &index in indices {
+                        cmd.arg(&keys[index][..]);
+                    }
+                })
+                .await?;
+            deleted += unlinked.into_iter().sum::<u64>();
+        }
+        Ok(deleted)
+    }
+}
+
+// Walks the keys matching a pattern on one master after the other
+pub(crate) struct MasterScan<C> {
+    connection: Connection<C>,
+    // The masters left to scan, the one being scanned last
+    nodes: Vec<String>,
+    // The masters scanned or left to scan, any other master is new to the scan
+    seen: HashSet<String>,
+    // Whether the master being scanned has been walked through, it is then dropped from `nodes`
+    // on the next batch
+    finished: bool,
+    cursor: u64,
+    pattern: Vec<u8>,
+    count: usize,
+    // The time each `SCAN` is spread over when the scan is rate limited
+    interval: Option<Duration>,
+    started: bool,
+}
+
+impl<C> MasterScan<C>
+where
+    C: ConnectionLike + Send + 'static,
+{
+    pub(crate) fn new(
+        connection: &Connection<C>,
+        pattern: &str,
+        count: usize,
+        keys_per_second: Option<u32>,
+    ) -> Self {
+        let mut nodes = connection.node_names(Some(NodeRole::Master));
+        nodes.sort_by(|a, b| b.cmp(a));
+        MasterScan {
+            connection: connection.clone(),
+            seen: nodes.iter().cloned().collect(),
+            nodes,
+            finished: false,
+            cursor: 0,
+            pattern: connection.shared.keys.pattern(pattern),
+            count,
+            interval: keys_per_second.map(|keys_per_second| {
+                Duration::from_secs_f64(count as f64 / keys_per_second as f64)
+            }),
+            started: false,
+        }
+    }
+
+    // The master of the next batch of keys and the keys found, `None` once every master has been
+    // scanned. A master which fails to scan is given up on and its error returned instead.
+    pub(crate) async fn next_batch(&mut self) -> Option<(String, RedisResult<Vec<Vec<u8>>>)> {
+        if self.finished {
+            self.nodes.pop();
+            self.finished = false;
+            self.cursor = 0;
+            self.follow_topology();
+        }
+        let node = self.nodes.last()?.clone();
+        match self.interval {
+            Some(interval) if self.started => self.connection.shared.clock.sleep(interval).await,
+            _ => self.started = true,
+        }
+        let mut connection = self.connection.with_route(Route::Node(node.clone()));
+        let result = redis::cmd("SCAN")
+            .arg(self.cursor)
+            .arg("MATCH")
+            .arg(&self.pattern[..])
+            .arg("COUNT")
+            .arg(self.count)
+            .query_async::<_, (u64, Vec<Vec<u8>>)>(&mut connection)
+            .await;
+        let keys = match result {
+            Ok((cursor, keys)) => {
+                self.cursor = cursor;
+                let rewrite = &self.connection.shared.keys;
+                Ok(keys
+                    .iter()
+                    .filter_map(|key| rewrite.strip_prefix(key))
+                    .map(|key| key.to_vec())
+                    .collect())
+            }
+            Err(err) => {
+                self.cursor = 0;
+                Err(err)
+            }
+        };
+        self.finished = self.cursor == 0;
+        Some((node, keys))
+    }
+
+    // Drops the masters left to scan which are not masters anymore and queues the new masters
+    // after the others
+    fn follow_topology(&mut self) {
+        let masters = self.connection.node_names(Some(NodeRole::Master));
+        self.nodes.retain(|node| masters.contains(node));
+        let mut added = masters
+            .into_iter()
+            .filter(|master| self.seen.insert(master.clone()))
+            .collect::<Vec<_>>();
+        added.sort_by(|a, b| b.cmp(a));
+        self.nodes.splice(0..0, added);
+    }
+
+    // The `PTTL` of each of `keys`, held by `node`
+    async fn ttls(&self, node: &str, keys: Vec<Vec<u8>>) -> RedisResult<Vec<(Vec<u8>, i64)>> {
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut pipeline = redis::pipe();
+        for key in &keys {
+            pipeline.cmd("PTTL").arg(&key[..]);
+        }
+        let ttls: Vec<i64> = self.query(node, &pipeline).await?;
+        Ok(keys.into_iter().zip(ttls).collect())
+    }
+
+    // The number of masters left to scan, counting the one being scanned
+    pub(crate) fn masters_left(&self) -> usize {
+        self.nodes.len()
+    }
+
+    // Moves on to the next master with the next batch
+    pub(crate) fn skip_master(&mut self) {
+        self.finished = true;
+    }
+
+    // Sends `pipeline` about the keys found on `node` to `node`
+    pub(crate) async fn query<T: FromRedisValue>(
+        &self,
+        node: &str,
+        pipeline: &Pipeline,
+    ) -> RedisResult<T> {
+        let mut connection = self.connection.with_route(Route::Node(node.to_string()));
+        pipeline.query_async(&mut connection).await
+    }
+}
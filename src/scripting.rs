@@ -0,0 +1,184 @@
+//! Lua scripts routed by their keys, and sent again with `EVAL` to the nodes missing them.
+//!
+//! `Script::invoke_async` sends `EVALSHA` to the master of the slot of the first key, and after
+//! `NOSCRIPT` loads the script with `SCRIPT LOAD`, which has no key and reaches a random node.
+//! The last [`MAX_SCRIPTS`] scripts loaded through a connection are therefore kept with it,
+//! by their SHA1, and an `EVALSHA` of a known script failing with `NOSCRIPT` (on a node which was
+//! added, restarted or failed over since) is sent again as `EVAL` to the same slot, which caches
+//! it on that node too.
+//! See [`Client::set_preload_scripts`] to load the scripts on every master instead.
+//!
+//! [`Client::set_preload_scripts`]: crate::Client::set_preload_scripts
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::Arc,
+};
+
+use redis::{aio::ConnectionLike, Arg, Cmd, ErrorKind, RedisError, RedisResult, Script, Value};
+
+use crate::{
+    command, fan_out::NodeResults, key_slot, namespace::key_positions, Connection,
+    ExecutionInfoSlot, NodeRole,
+};
+
+/// How many scripts a connection keeps to send them again with `EVAL`. Once full, the script
+/// which was least recently loaded or sent again is forgotten.
+pub const MAX_SCRIPTS: usize = 256;
+
+// The commands handled here, the others are sent as they are
+const SCRIPT_COMMANDS: &[&str] = &[
+    "EVAL",
+    "EVALSHA",
+    "EVALSHA_RO",
+    "EVAL_RO",
+    "FCALL",
+    "FCALL_RO",
+    "SCRIPT",
+];
+
+impl<C> Connection<C>
+where
+    C: ConnectionLike + Send + 'static,
+{
+    // Handles `SCRIPT LOAD` and the script commands, `None` for the other commands
+    pub(crate) async fn script_cmd(
+        &mut self,
+        cmd: &Cmd,
+        execution_info: Option<ExecutionInfoSlot>,
+    ) -> Option<RedisResult<Value>> {
+        let name = command::name(cmd)?;
+        if !command::is_one_of(name, SCRIPT_COMMANDS) {
+            return None;
+        }
+        let args = cmd
+            .args_iter()
+            .map(|arg| match arg {
+                Arg::Simple(arg) => Some(arg),
+                Arg::Cursor => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+        if name.eq_ignore_ascii_case(b"SCRIPT") {
+            if args.len() != 3 || !args[1].eq_ignore_ascii_case(b"LOAD") {
+                return None;
+            }
+            let code = std::str::from_utf8(args[2]).ok()?;
+            let hash = Script::new(code).get_hash().to_string();
+            self.shared
+                .scripts
+                .lock()
+                .unwrap()
+                .insert(hash.clone(), Arc::from(code));
+            if self.route.is_some() || !self.shared.preload_scripts {
+                return None;
+            }
+            let loaded = self
+                .fan_out::<String>(Some(NodeRole::Master), cmd)
+                .await
+                .all_ok();
+            return Some(loaded.map(|_| Value::Data(hash.into_bytes())));
+        }
+
+        if let Err(err) = self.check_script_slot(cmd) {
+            return Some(Err(err));
+        }
+        let eval = if name.eq_ignore_ascii_case(b"EVALSHA") {
+            "EVAL"
+        } else if name.eq_ignore_ascii_case(b"EVALSHA_RO") {
+            "EVAL_RO"
+        } else {
+            return None;
+        };
+        let err = match self.exec_cmd(cmd, execution_info.clone()).await {
+            Err(err) if err.kind() == ErrorKind::NoScriptError => err,
+            result => return Some(result),
+        };
+        let hash = String::from_utf8_lossy(args.get(1)?).to_ascii_lowercase();
+        let code = self.shared.scripts.lock().unwrap().get(&hash);
+        let code = match code {
+            Some(code) => code,
+            None => return Some(Err(err)),
+        };
+        let mut eval = redis::cmd(eval);
+        eval.arg(&*code);
+        for arg in &args[2..] {
+            eval.arg(*arg);
+        }
+        Some(self.exec_cmd(&eval, execution_info).await)
+    }
+
+    // Fails if the keys of the script, once rewritten, are in more than one slot
+    fn check_script_slot(&self, cmd: &Cmd) -> RedisResult<()> {
+        let cmd = self.shared.keys.cmd(cmd);
+        let args = cmd
+            .args_iter()
+            .map(|arg| match arg {
+                Arg::Simple(arg) => arg,
+                Arg::Cursor => &[][..],
+            })
+            .collect::<Vec<_>>();
+        let slots = key_positions(&args)
+            .into_iter()
+            .map(|position| key_slot(args[position]))
+            .collect::<BTreeSet<_>>();
+        if slots.len() > 1 {
+            return Err(RedisError::from((
+                ErrorKind::ClientError,
+                "The keys of a script must be in the same slot",
+                format!("keys in slots {:?}", slots),
+            )));
+        }
+        Ok(())
+    }
+}
+
+// The source of the scripts known to a connection by their SHA1, with the last time each one was
+// used
+#[derive(Default)]
+pub(crate) struct ScriptCache {
+    scripts: HashMap<String, (Arc<str>, u64)>,
+    uses: u64,
+}
+
+impl ScriptCache {
+    pub(crate) fn insert(&mut self, hash: String, code: Arc<str>) {
+        self.uses += 1;
+        self.scripts.insert(hash, (code, self.uses));
+        if self.scripts.len() > MAX_SCRIPTS {
+            let oldest = self
+                .scripts
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(hash, _)| hash.clone());
+            if let Some(oldest) = oldest {
+                self.scripts.remove(&oldest);
+            }
+        }
+    }
+
+    pub(crate) fn get(&mut self, hash: &str) -> Option<Arc<str>> {
+        self.uses += 1;
+        let (code, used) = self.scripts.get_mut(hash)?;
+        *used = self.uses;
+        Some(code.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn least_recently_used_scripts_are_forgotten() {
+        let mut cache = ScriptCache::default();
+        for i in 0..MAX_SCRIPTS {
+            cache.insert(i.to_string(), Arc::from("return 1"));
+        }
+        assert!(cache.get("0").is_some());
+        cache.insert("new".into(), Arc::from("return 2"));
+        assert_eq!(cache.scripts.len(), MAX_SCRIPTS);
+        assert!(cache.get("0").is_some());
+        assert!(cache.get("1").is_none());
+        assert!(cache.get("new").is_some());
+    }
+}
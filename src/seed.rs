@@ -0,0 +1,173 @@
+use std::{fmt, net::SocketAddr};
+
+use redis::{ConnectionAddr, ConnectionInfo, IntoConnectionInfo, RedisConnectionInfo, RedisResult};
+
+#[cfg(feature = "tls")]
+use crate::TlsMode;
+
+/// An initial node given by its parts instead of a URL.
+///
+/// ```rust
+/// use redis_cluster_async::{Client, Seed};
+///
+/// # fn run() -> redis::RedisResult<()> {
+/// let client = Client::open(vec![
+///     Seed::new("10.0.0.1", 7000).password("secret"),
+///     Seed::from("10.0.0.2:7000".parse::<std::net::SocketAddr>().unwrap()).password("secret"),
+/// ])?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, PartialEq, Eq)]
+pub struct Seed {
+    host: String,
+    port: u16,
+    #[cfg(feature = "tls")]
+    tls: Option<TlsMode>,
+    username: Option<String>,
+    password: Option<String>,
+}
+
+impl Seed {
+    /// Create a seed for the node at `host:port`, without TLS or credentials.
+    pub fn new(host: impl Into<String>, port: u16) -> Self {
+        Seed {
+            host: host.into(),
+            port,
+            #[cfg(feature = "tls")]
+            tls: None,
+            username: None,
+            password: None,
+        }
+    }
+
+    /// Connect to the node over TLS, verifying its certificate.
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self) -> Self {
+        self.tls = Some(TlsMode::Secure);
+        self
+    }
+
+    /// Connect to the node over TLS without verifying its certificate.
+    #[cfg(feature = "tls")]
+    pub fn tls_insecure(mut self) -> Self {
+        self.tls = Some(TlsMode::Insecure);
+        self
+    }
+
+    /// Authenticate as `username` (Redis 6 ACLs).
+    pub fn username(mut self, username: impl Into<String>) -> Self {
+        self.username = Some(username.into());
+        self
+    }
+
+    /// Authenticate with `password`.
+    pub fn password(mut self, password: impl Into<String>) -> Self {
+        self.password = Some(password.into());
+        self
+    }
+}
+
+// The password is left out so seeds can be logged
+impl fmt::Debug for Seed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut f = f.debug_struct("Seed");
+        f.field("host", &self.host).field("port", &self.port);
+        #[cfg(feature = "tls")]
+        f.field("tls", &self.tls);
+        f.field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+impl From<SocketAddr> for Seed {
+    fn from(addr: SocketAddr) -> Self {
+        Seed::new(addr.ip().to_string(), addr.port())
+    }
+}
+
+impl<T> From<(T, u16)> for Seed
+where
+    T: Into<String>,
+{
+    fn from((host, port): (T, u16)) -> Self {
+        Seed::new(host, port)
+    }
+}
+
+impl IntoConnectionInfo for Seed {
+    fn into_connection_info(self) -> RedisResult<ConnectionInfo> {
+        #[cfg(feature = "tls")]
+        let addr = match self.tls {
+            Some(mode) => ConnectionAddr::TcpTls {
+                host: self.host,
+                port: self.port,
+                insecure: mode == TlsMode::Insecure,
+            },
+            None => ConnectionAddr::Tcp(self.host, self.port),
+        };
+        #[cfg(not(feature = "tls"))]
+        let addr = ConnectionAddr::Tcp(self.host, self.port);
+        Ok(ConnectionInfo {
+            addr,
+            redis: RedisConnectionInfo {
+                db: 0,
+                username: self.username,
+                password: self.password,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn into_connection_info() {
+        let info = Seed::from(("cluster.example.com", 7000))
+            .username("user")
+            .password("secret")
+            .into_connection_info()
+            .unwrap();
+        assert_eq!(
+            info.addr,
+            ConnectionAddr::Tcp("cluster.example.com".into(), 7000)
+        );
+        assert_eq!(info.redis.username.as_deref(), Some("user"));
+        assert_eq!(info.redis.password.as_deref(), Some("secret"));
+        assert!(!format!("{:?}", Seed::new("a", 1).password("secret")).contains("secret"));
+
+        let addr = SocketAddr::from(([10, 0, 0, 1], 7000));
+        assert_eq!(
+            Seed::from(addr).into_connection_info().unwrap().addr,
+            ConnectionAddr::Tcp("10.0.0.1".into(), 7000)
+        );
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn tls_is_applied() {
+        assert_eq!(
+            Seed::new("a", 1).tls().into_connection_info().unwrap().addr,
+            ConnectionAddr::TcpTls {
+                host: "a".into(),
+                port: 1,
+                insecure: false,
+            }
+        );
+        assert_eq!(
+            Seed::new("a", 1)
+                .tls_insecure()
+                .into_connection_info()
+                .unwrap()
+                .addr,
+            ConnectionAddr::TcpTls {
+                host: "a".into(),
+                port: 1,
+                insecure: true,
+            }
+        );
+    }
+}
@@ -0,0 +1,87 @@
+//! Hash slots of keys. A request keeps the slot it was routed with for its retries and
+//! redirections. The slot is not memoized on the command: splitting a pipeline or a multi-key
+//! command computes the slots of its parts again, which costs one CRC16 per key and was preferred
+//! to storing the slot alongside every `Cmd`.
+
+/// Number of hash slots of a cluster.
+pub(crate) const SLOT_SIZE: usize = 16384;
+
+// CRC16 (XMODEM: polynomial 0x1021, no reflection, initial value 0) of every byte value, so the
+// checksum takes one lookup per byte
+const CRC16_TABLE: [u16; 256] = crc16_table();
+
+const fn crc16_table() -> [u16; 256] {
+    let mut table = [0; 256];
+    let mut byte = 0;
+    while byte < 256 {
+        let mut crc = (byte as u16) << 8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[byte] = crc;
+        byte += 1;
+    }
+    table
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0, |crc, &byte| {
+        (crc << 8) ^ CRC16_TABLE[((crc >> 8) as u8 ^ byte) as usize]
+    })
+}
+
+/// The hash slot of `key`, as given by `CLUSTER KEYSLOT`: the CRC16 (XMODEM) of the key, or of
+/// its hash tag, modulo 16384.
+///
+/// Keys are arbitrary bytes, they do not need to be UTF-8. A hash tag is whatever is between the
+/// first `{` and the first `}` after it, if that is not empty, whatever bytes it contains.
+///
+/// ```rust
+/// use redis_cluster_async::key_slot;
+///
+/// assert_eq!(key_slot(b"foo"), 12182);
+/// assert_eq!(key_slot(b"\xff{\x00\x01}a"), key_slot(b"\xff{\x00\x01}b"));
+/// ```
+pub fn key_slot(key: &[u8]) -> u16 {
+    crc16(hash_tag(key).unwrap_or(key)) % SLOT_SIZE as u16
+}
+
+// The non-empty part between the first `{` and the next `}`, which is all that determines the
+// hash slot of a key
+pub(crate) fn hash_tag(key: &[u8]) -> Option<&[u8]> {
+    let open = key.iter().position(|&b| b == b'{')?;
+    let rest = &key[open + 1..];
+    let close = rest.iter().position(|&b| b == b'}')?;
+    Some(&rest[..close]).filter(|tag| !tag.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crc16::{State, XMODEM};
+
+    #[test]
+    fn crc16_matches_reference() {
+        assert_eq!(crc16(b"123456789"), 0x31c3);
+        let data: Vec<u8> = (0..=255)
+            .chain((0..1000).map(|i| (i * 7 % 251) as u8))
+            .collect();
+        for len in 0..data.len() {
+            assert_eq!(
+                crc16(&data[..len]),
+                State::<XMODEM>::calculate(&data[..len])
+            );
+        }
+
+        assert_eq!(hash_tag(b"a{b}c{d}"), Some(&b"b"[..]));
+        assert_eq!(hash_tag(b"a{}c{d}"), None);
+        assert_eq!(hash_tag(b"a{bc"), None);
+    }
+}
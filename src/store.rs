@@ -0,0 +1,181 @@
+//! Persistence of the last known slot map, so that a connection created after a restart can
+//! route commands without asking the initial nodes for `CLUSTER SLOTS` first.
+//!
+//! The slot map is saved as text, one slot range per line: `start-end master replica...`, the
+//! nodes as `host:port`. Credentials are not saved, they are taken from the initial nodes when
+//! the slot map is loaded.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use redis::{ErrorKind, RedisError, RedisResult};
+
+use crate::{build_connection_string, node_name, Slot, SlotMap};
+
+/// Where the slot map is saved whenever a connection discovers it, see
+/// [`Client::set_topology_store`](crate::Client::set_topology_store).
+///
+/// `save` is called by the task driving the connection, so it should return quickly.
+pub trait TopologyStore: Send + Sync + 'static {
+    /// Returns the slot map saved last, `None` if none was saved yet.
+    fn load(&self) -> io::Result<Option<String>>;
+
+    /// Replaces the saved slot map with `topology`.
+    fn save(&self, topology: &str) -> io::Result<()>;
+}
+
+/// A [`TopologyStore`] keeping the slot map in a file.
+#[derive(Clone, Debug)]
+pub struct FileTopologyStore {
+    path: PathBuf,
+}
+
+impl FileTopologyStore {
+    /// Saves the slot map to `path`, which is created if it does not exist.
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        FileTopologyStore {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+}
+
+impl TopologyStore for FileTopologyStore {
+    fn load(&self) -> io::Result<Option<String>> {
+        match fs::read_to_string(&self.path) {
+            Ok(topology) => Ok(Some(topology)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn save(&self, topology: &str) -> io::Result<()> {
+        // Write to a temporary file first so a crash does not leave half of a slot map behind
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        fs::write(&tmp, topology)?;
+        fs::rename(&tmp, &self.path)
+    }
+}
+
+pub(crate) fn encode(slots: &SlotMap) -> String {
+    let mut start = 0;
+    let mut topology = String::new();
+    for (&end, addrs) in slots {
+        topology.push_str(&format!("{}-{} {}", start, end, node_name(&addrs.master)));
+        for replica in &addrs.replicas {
+            topology.push(' ');
+            topology.push_str(&node_name(replica));
+        }
+        topology.push('\n');
+        start = end + 1;
+    }
+    topology
+}
+
+// The slot ranges of `topology`, the nodes addressed with the given credentials and TLS settings
+pub(crate) fn decode(
+    topology: &str,
+    username: Option<&str>,
+    password: Option<&str>,
+    use_tls: bool,
+    tls_insecure: bool,
+) -> RedisResult<Vec<Slot>> {
+    let addr = |node: &str| {
+        let (host, port) = node.rsplit_once(':')?;
+        let port = port.parse().ok()?;
+        Some(build_connection_string(
+            username,
+            password,
+            host,
+            port,
+            use_tls,
+            tls_insecure,
+        ))
+    };
+    topology
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let mut fields = line.split_whitespace();
+            let range = fields
+                .next()
+                .and_then(|range| range.split_once('-'))
+                .and_then(|(start, end)| Some((start.parse().ok()?, end.parse().ok()?)));
+            let nodes = fields.map(addr).collect::<Option<Vec<_>>>();
+            match (range, nodes) {
+                (Some((start, end)), Some(mut nodes)) if !nodes.is_empty() => {
+                    let replicas = nodes.split_off(1);
+                    Ok(Slot {
+                        start,
+                        end,
+                        master: nodes.pop().unwrap(),
+                        replicas,
+                    })
+                }
+                _ => Err(RedisError::from((
+                    ErrorKind::TypeError,
+                    "Invalid saved topology",
+                    line.to_string(),
+                ))),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::SlotAddrs;
+
+    #[test]
+    fn topology_round_trip() {
+        let mut slots = SlotMap::new();
+        slots.insert(
+            8191,
+            SlotAddrs {
+                master: "redis://:secret@10.0.0.1:7000".into(),
+                replicas: vec!["redis://:secret@10.0.0.2:7000".into()],
+            },
+        );
+        slots.insert(
+            16383,
+            SlotAddrs {
+                master: "redis://:secret@10.0.0.3:7000".into(),
+                replicas: Vec::new(),
+            },
+        );
+        let topology = encode(&slots);
+        assert_eq!(
+            topology,
+            "0-8191 10.0.0.1:7000 10.0.0.2:7000\n8192-16383 10.0.0.3:7000\n"
+        );
+
+        let decoded = decode(&topology, None, Some("other"), true, false).unwrap();
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(
+            (decoded[0].start, decoded[0].end, &decoded[0].master[..]),
+            (0, 8191, "rediss://:other@10.0.0.1:7000")
+        );
+        assert_eq!(decoded[0].replicas, ["rediss://:other@10.0.0.2:7000"]);
+        assert!(decoded[1].replicas.is_empty());
+
+        assert!(decode("0-16383\n", None, None, false, false).is_err());
+        assert!(decode("0-16383 10.0.0.1\n", None, None, false, false).is_err());
+    }
+
+    #[test]
+    fn file_store() {
+        let path = std::env::temp_dir().join(format!("topology-{}", std::process::id()));
+        let store = FileTopologyStore::new(&path);
+        assert_eq!(store.load().unwrap(), None);
+        store.save("0-16383 10.0.0.1:7000\n").unwrap();
+        assert_eq!(
+            store.load().unwrap().as_deref(),
+            Some("0-16383 10.0.0.1:7000\n")
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,225 @@
+//! Helpers for tests which run against a real Redis cluster, enabled by the `test-utils` feature.
+//!
+//! [`RedisEnv`] waits for the cluster to be ready, empties it and hands out a [`Client`] for it.
+//! Tests using it are serialized within the test binary so they do not see each other's keys.
+//!
+//! ```rust,no_run
+//! use redis_cluster_async::test_utils::RedisEnv;
+//!
+//! #[tokio::test]
+//! async fn set_and_get() {
+//!     let env = RedisEnv::new().await;
+//!     let mut connection = env.client.get_connection().await.unwrap();
+//!     redis::cmd("SET").arg("key").arg("value").query_async::<_, ()>(&mut connection).await.unwrap();
+//! }
+//! ```
+//!
+//! The [`model`] module checks the replies of the cluster against a reference model while the
+//! cluster fails over and reshards, [`migration`] leaves a slot half migrated for as long as a
+//! test needs.
+
+pub mod migration;
+pub mod model;
+
+use std::{
+    env,
+    sync::{Mutex, MutexGuard},
+    time::Duration,
+};
+
+use redis::{
+    aio::MultiplexedConnection, AsyncCommands, ConnectionAddr, ErrorKind, RedisError, RedisResult,
+};
+use tokio::runtime::Runtime;
+
+use crate::Client;
+
+/// The node the cluster is discovered from unless `REDIS_CLUSTER_URL` is set.
+pub const DEFAULT_URL: &str = "redis://127.0.0.1:7000/";
+
+// Number of masters the cluster is expected to settle on unless `REDIS_CLUSTER_MASTERS` is set
+const DEFAULT_MASTERS: usize = 3;
+
+static LOCK: Mutex<()> = Mutex::new(());
+
+/// A test cluster which has been emptied, held exclusively until the value is dropped.
+pub struct RedisEnv {
+    /// A client for the masters of the cluster.
+    pub client: Client,
+    /// A connection to every node of the cluster, replicas included.
+    pub nodes: Vec<MultiplexedConnection>,
+    masters: Vec<String>,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl RedisEnv {
+    /// Connects to the cluster at `REDIS_CLUSTER_URL` (default [`DEFAULT_URL`]), waits until it
+    /// has `REDIS_CLUSTER_MASTERS` masters (default 3) and empties every master.
+    ///
+    /// # Panics
+    ///
+    /// If the URL is invalid. A cluster which can not be reached is waited for.
+    pub async fn new() -> Self {
+        let url = env::var("REDIS_CLUSTER_URL").unwrap_or_else(|_| DEFAULT_URL.to_string());
+        let masters = env::var("REDIS_CLUSTER_MASTERS")
+            .ok()
+            .and_then(|masters| masters.parse().ok())
+            .unwrap_or(DEFAULT_MASTERS);
+        Self::connect(&url, masters).await
+    }
+
+    /// Like [`RedisEnv::new`], for the cluster reachable through `url` with `masters` masters.
+    // The lock is what keeps tests apart, it is meant to be held for as long as the test runs
+    #[allow(clippy::await_holding_lock)]
+    pub async fn connect(url: &str, masters: usize) -> Self {
+        // A test which panicked while holding the lock does not affect the next one
+        let lock = LOCK.lock().unwrap_or_else(|poison| poison.into_inner());
+        let client = redis::Client::open(url)
+            .unwrap_or_else(|err| panic!("Invalid cluster URL '{}': {}", url, err));
+
+        loop {
+            match Self::try_connect(&client, masters).await {
+                Ok((master_urls, nodes)) => {
+                    return RedisEnv {
+                        client: Client::open(master_urls.clone()).unwrap(),
+                        nodes,
+                        masters: master_urls,
+                        _lock: lock,
+                    }
+                }
+                Err(err) => log::warn!("Waiting for the test cluster: {}", err),
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    /// Empties every master again, for tests which need a clean cluster more than once.
+    pub async fn reset(&mut self) -> RedisResult<()> {
+        for url in &self.masters {
+            let mut conn = redis::Client::open(&url[..])?
+                .get_multiplexed_tokio_connection()
+                .await?;
+            flush(&mut conn).await?;
+        }
+        Ok(())
+    }
+
+    /// Creates (or updates) the user `username` with access to every key and command on every
+    /// node, for tests of authentication.
+    pub async fn add_user(&mut self, username: &str, password: &str) -> RedisResult<()> {
+        for conn in &mut self.nodes {
+            conn.acl_setuser_rules::<_, ()>(
+                username,
+                &[
+                    redis::acl::Rule::AllKeys,
+                    redis::acl::Rule::AllCommands,
+                    redis::acl::Rule::On,
+                    redis::acl::Rule::AddPass(password.into()),
+                ],
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    async fn try_connect(
+        client: &redis::Client,
+        masters: usize,
+    ) -> RedisResult<(Vec<String>, Vec<MultiplexedConnection>)> {
+        let mut conn = client.get_multiplexed_tokio_connection().await?;
+        let host = match &client.get_connection_info().addr {
+            ConnectionAddr::Tcp(host, _) | ConnectionAddr::TcpTls { host, .. } => host.clone(),
+            ConnectionAddr::Unix(_) => "localhost".to_string(),
+        };
+        let node_infos = cluster_nodes(&mut conn, &host).await?;
+        let found = node_infos.iter().filter(|(_, master)| *master).count();
+        if found != masters {
+            return Err(RedisError::from((
+                ErrorKind::ClusterDown,
+                "Cluster is not ready",
+                format!("{} of {} masters are up", found, masters),
+            )));
+        }
+
+        let mut master_urls = Vec::new();
+        let mut nodes = Vec::new();
+        for (url, master) in node_infos {
+            let mut conn = redis::Client::open(&url[..])?
+                .get_multiplexed_tokio_connection()
+                .await?;
+            if master {
+                flush(&mut conn).await?;
+                master_urls.push(url);
+            }
+            nodes.push(conn);
+        }
+        Ok((master_urls, nodes))
+    }
+}
+
+/// A [`RedisEnv`] together with the runtime it was created on, for tests which are not `async`.
+pub struct RuntimeEnv {
+    /// The test cluster.
+    pub redis: RedisEnv,
+    /// A current thread runtime with I/O and timers enabled.
+    pub runtime: Runtime,
+}
+
+impl Default for RuntimeEnv {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RuntimeEnv {
+    /// Creates a runtime and a [`RedisEnv::new`] on it.
+    pub fn new() -> Self {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_io()
+            .enable_time()
+            .build()
+            .unwrap();
+        let redis = runtime.block_on(RedisEnv::new());
+        Self { runtime, redis }
+    }
+}
+
+async fn flush(conn: &mut MultiplexedConnection) -> RedisResult<()> {
+    let cmd = redis::cmd("FLUSHALL");
+    match tokio::time::timeout(Duration::from_secs(3), cmd.query_async::<_, ()>(conn)).await {
+        Ok(result) => result,
+        Err(_) => Err(RedisError::from((ErrorKind::IoError, "FLUSHALL timed out"))),
+    }
+}
+
+// The URL of every node and whether it is a master, from `CLUSTER NODES`. Nodes are reached on
+// `host`, as the addresses they announce may only be valid inside of a container.
+async fn cluster_nodes(
+    conn: &mut MultiplexedConnection,
+    host: &str,
+) -> RedisResult<Vec<(String, bool)>> {
+    let nodes: String = redis::cmd("CLUSTER").arg("NODES").query_async(conn).await?;
+    nodes
+        .lines()
+        .map(|line| {
+            let mut fields = line.split(' ');
+            // <id> <ip:port@cport[,hostname]> <flags> ...
+            let port = fields
+                .nth(1)
+                .and_then(|addr| addr.split('@').next())
+                .and_then(|addr| addr.rsplit_once(':'))
+                .map(|(_, port)| port);
+            match (port, fields.next()) {
+                (Some(port), Some(flags)) => Ok((
+                    format!("redis://{}:{}", host, port),
+                    flags.contains("master"),
+                )),
+                _ => Err(RedisError::from((
+                    ErrorKind::TypeError,
+                    "Invalid CLUSTER NODES line",
+                    line.to_string(),
+                ))),
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,224 @@
+//! Slot migrations run step by step, so that tests can send commands while the keys of a slot
+//! are split between two masters.
+//!
+//! ```rust,no_run
+//! use redis_cluster_async::test_utils::{migration::SlotMigration, RedisEnv};
+//!
+//! # async fn run() -> redis::RedisResult<()> {
+//! let env = RedisEnv::new().await;
+//! let mut migration = SlotMigration::start(env.nodes.clone(), "key")
+//!     .await?
+//!     .expect("The cluster has several masters");
+//! // Commands for the migrated keys are now redirected with `ASK`
+//! migration.migrate_keys(10).await?;
+//! migration.finish().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use redis::{aio::MultiplexedConnection, cmd, ErrorKind, RedisError, RedisResult};
+
+// Keys moved per `MIGRATE` while finishing a migration
+const MIGRATE_BATCH: usize = 100;
+
+/// The migration of the slot of a key from its master to the next master, ordered by id.
+pub struct SlotMigration {
+    nodes: Vec<MultiplexedConnection>,
+    slot: u16,
+    source: usize,
+    target: usize,
+    // The node id of the target, and its address as announced to the other nodes
+    target_id: String,
+    target_addr: (String, u16),
+    // Index in `nodes` of every master
+    masters: Vec<usize>,
+}
+
+impl SlotMigration {
+    /// Marks the slot of `key` as `IMPORTING` on the next master and `MIGRATING` on its own,
+    /// without moving any key yet. `nodes` is a connection to every node of the cluster, as in
+    /// [`RedisEnv::nodes`](super::RedisEnv::nodes). Returns `None` if the cluster has a single
+    /// master.
+    pub async fn start(
+        mut nodes: Vec<MultiplexedConnection>,
+        key: &str,
+    ) -> RedisResult<Option<Self>> {
+        let first = nodes.first_mut().ok_or_else(|| {
+            RedisError::from((ErrorKind::ClientError, "No node to reshard the cluster on"))
+        })?;
+        let slot: u16 = cmd("CLUSTER")
+            .arg("KEYSLOT")
+            .arg(key)
+            .query_async(first)
+            .await?;
+        let listing: String = cmd("CLUSTER").arg("NODES").query_async(first).await?;
+        let mut masters: Vec<NodeEntry> = listing
+            .lines()
+            .filter_map(parse_node)
+            .filter(|node| node.master)
+            .collect();
+        masters.sort_by(|a, b| a.id.cmp(&b.id));
+
+        let owner = masters
+            .iter()
+            .position(|node| {
+                node.slots
+                    .iter()
+                    .any(|&(start, end)| start <= slot && slot <= end)
+            })
+            .ok_or_else(|| {
+                RedisError::from((
+                    ErrorKind::ClusterDown,
+                    "Slot is not served",
+                    slot.to_string(),
+                ))
+            })?;
+        let source = &masters[owner];
+        let target = &masters[(owner + 1) % masters.len()];
+        if source.id == target.id {
+            return Ok(None);
+        }
+
+        let mut ids = Vec::with_capacity(nodes.len());
+        for node in nodes.iter_mut() {
+            let id: String = cmd("CLUSTER").arg("MYID").query_async(node).await?;
+            ids.push(id);
+        }
+        let index = |id: &str| {
+            ids.iter().position(|node| node == id).ok_or_else(|| {
+                RedisError::from((
+                    ErrorKind::ClientError,
+                    "No connection to node",
+                    id.to_string(),
+                ))
+            })
+        };
+        let (source_index, target_index) = (index(&source.id)?, index(&target.id)?);
+        let master_indexes = masters
+            .iter()
+            .map(|master| index(&master.id))
+            .collect::<RedisResult<_>>()?;
+
+        cmd("CLUSTER")
+            .arg("SETSLOT")
+            .arg(slot)
+            .arg("IMPORTING")
+            .arg(&source.id)
+            .query_async::<_, ()>(&mut nodes[target_index])
+            .await?;
+        cmd("CLUSTER")
+            .arg("SETSLOT")
+            .arg(slot)
+            .arg("MIGRATING")
+            .arg(&target.id)
+            .query_async::<_, ()>(&mut nodes[source_index])
+            .await?;
+        Ok(Some(SlotMigration {
+            slot,
+            source: source_index,
+            target: target_index,
+            target_id: target.id.clone(),
+            target_addr: (target.host.clone(), target.port),
+            masters: master_indexes,
+            nodes,
+        }))
+    }
+
+    /// The slot being migrated.
+    pub fn slot(&self) -> u16 {
+        self.slot
+    }
+
+    /// Moves up to `count` of the keys left in the slot to the target, returning how many were
+    /// moved.
+    pub async fn migrate_keys(&mut self, count: usize) -> RedisResult<usize> {
+        let keys: Vec<String> = cmd("CLUSTER")
+            .arg("GETKEYSINSLOT")
+            .arg(self.slot)
+            .arg(count)
+            .query_async(&mut self.nodes[self.source])
+            .await?;
+        if keys.is_empty() {
+            return Ok(0);
+        }
+        cmd("MIGRATE")
+            .arg(&self.target_addr.0)
+            .arg(self.target_addr.1)
+            .arg("")
+            .arg(0)
+            .arg(5000)
+            .arg("KEYS")
+            .arg(&keys)
+            .query_async::<_, ()>(&mut self.nodes[self.source])
+            .await?;
+        Ok(keys.len())
+    }
+
+    /// Moves the keys left and assigns the slot to the target on every master.
+    pub async fn finish(mut self) -> RedisResult<()> {
+        while self.migrate_keys(MIGRATE_BATCH).await? > 0 {}
+        // The target first, so that it serves the slot once the source redirects to it
+        let mut masters = self.masters.clone();
+        masters.sort_by_key(|&master| (master != self.target, master != self.source));
+        for master in masters {
+            cmd("CLUSTER")
+                .arg("SETSLOT")
+                .arg(self.slot)
+                .arg("NODE")
+                .arg(&self.target_id)
+                .query_async::<_, ()>(&mut self.nodes[master])
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+// A node as listed by `CLUSTER NODES`
+struct NodeEntry {
+    id: String,
+    host: String,
+    port: u16,
+    master: bool,
+    slots: Vec<(u16, u16)>,
+}
+
+// <id> <ip:port@cport[,hostname]> <flags> <master> <ping-sent> <pong-recv> <epoch> <link> <slot>...
+fn parse_node(line: &str) -> Option<NodeEntry> {
+    let fields: Vec<&str> = line.split(' ').collect();
+    let (host, port) = fields.get(1)?.split('@').next()?.rsplit_once(':')?;
+    let slots = fields
+        .iter()
+        .skip(8)
+        // Slots being migrated are listed as `[slot->-id]`
+        .filter(|range| !range.starts_with('['))
+        .filter_map(|range| match range.split_once('-') {
+            Some((start, end)) => Some((start.parse().ok()?, end.parse().ok()?)),
+            None => range.parse().ok().map(|slot| (slot, slot)),
+        })
+        .collect();
+    Some(NodeEntry {
+        id: fields[0].to_string(),
+        host: host.to_string(),
+        port: port.parse().ok()?,
+        master: fields.get(2)?.contains("master"),
+        slots,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn node_listing() {
+        let node = parse_node(
+            "07c3 127.0.0.1:7001@17001 master - 0 1426238317239 2 connected 0-10 12 [13->-e7d1]",
+        )
+        .unwrap();
+        assert_eq!(
+            (&*node.host, node.port, node.master),
+            ("127.0.0.1", 7001, true)
+        );
+        assert_eq!(node.slots, [(0, 10), (12, 12)]);
+    }
+}
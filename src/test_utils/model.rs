@@ -0,0 +1,321 @@
+//! Model-based consistency checks: random operations are run against the cluster, interleaved
+//! with failovers and slot migrations, and every reply is compared with the one a plain map of
+//! the same keys gives.
+//!
+//! ```rust,no_run
+//! use proptest::proptest;
+//! use redis_cluster_async::test_utils::{model, RuntimeEnv};
+//!
+//! #[test]
+//! fn consistency() {
+//!     let env = RuntimeEnv::new();
+//!     let connection = env.runtime.block_on(env.redis.client.get_connection()).unwrap();
+//!     proptest!(|(ops in model::ops(8, 1..40))| {
+//!         env.runtime
+//!             .block_on(model::check(&env.redis, &connection, &ops))
+//!             .unwrap_or_else(|err| panic!("{}", err));
+//!     });
+//! }
+//! ```
+//!
+//! A disruption runs concurrently with the operations which follow it, until it completes or the
+//! next disruption is reached. The operations themselves are sent one after the other, so the
+//! model knows exactly which reply to expect from each of them.
+
+use std::{collections::HashMap, error::Error, fmt};
+
+use futures::future::{self, BoxFuture, Either, FutureExt};
+use proptest::{collection::SizeRange, prelude::*};
+use redis::{aio::MultiplexedConnection, cmd, ErrorKind, RedisError, RedisResult, Value};
+
+use super::{migration::SlotMigration, RedisEnv};
+use crate::Connection;
+
+/// An operation of a model check.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Op {
+    /// `SET key value`
+    Set(String, i64),
+    /// `GET key`
+    Get(String),
+    /// `INCRBY key increment`
+    Incr(String, i64),
+    /// `DEL key`
+    Del(String),
+    /// `CLUSTER FAILOVER` on every replica.
+    Failover,
+    /// Migrates the slot of the key to another master.
+    Reshard(String),
+}
+
+impl Op {
+    fn is_disruption(&self) -> bool {
+        matches!(self, Op::Failover | Op::Reshard(_))
+    }
+
+    fn key(&self) -> Option<&str> {
+        match self {
+            Op::Set(key, _) | Op::Get(key) | Op::Incr(key, _) | Op::Del(key) => Some(key),
+            Op::Failover | Op::Reshard(_) => None,
+        }
+    }
+}
+
+/// The reply to an [`Op`], as far as the model tells replies apart.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Reply {
+    /// `OK`
+    Okay,
+    /// No value
+    Nil,
+    /// An integer, or a value holding one
+    Int(i64),
+}
+
+impl Reply {
+    fn from_value(value: &Value) -> RedisResult<Reply> {
+        match value {
+            Value::Okay => Ok(Reply::Okay),
+            Value::Status(status) if status == "OK" => Ok(Reply::Okay),
+            Value::Nil => Ok(Reply::Nil),
+            Value::Int(n) => Ok(Reply::Int(*n)),
+            Value::Data(_) => Ok(Reply::Int(redis::from_redis_value(value)?)),
+            _ => Err(RedisError::from((
+                ErrorKind::TypeError,
+                "Unexpected reply",
+                format!("{:?}", value),
+            ))),
+        }
+    }
+}
+
+/// The reference model: the value of every key which is set.
+#[derive(Clone, Debug, Default)]
+pub struct Model {
+    values: HashMap<String, i64>,
+}
+
+impl Model {
+    /// Applies `op` to the model and returns the reply the cluster is expected to give, `None`
+    /// for disruptions which do not change any value.
+    pub fn apply(&mut self, op: &Op) -> Option<Reply> {
+        Some(match op {
+            Op::Set(key, value) => {
+                self.values.insert(key.clone(), *value);
+                Reply::Okay
+            }
+            Op::Get(key) => match self.values.get(key) {
+                Some(value) => Reply::Int(*value),
+                None => Reply::Nil,
+            },
+            Op::Incr(key, increment) => {
+                let value = self.values.entry(key.clone()).or_insert(0);
+                *value += increment;
+                Reply::Int(*value)
+            }
+            Op::Del(key) => Reply::Int(self.values.remove(key).is_some() as i64),
+            Op::Failover | Op::Reshard(_) => return None,
+        })
+    }
+}
+
+/// Why a model check failed.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum CheckError {
+    /// The keys used by the check could not be deleted beforehand.
+    Setup(RedisError),
+    /// An operation did not get the reply of the model.
+    Mismatch {
+        /// Index of the operation.
+        step: usize,
+        /// The operation.
+        op: Op,
+        /// The reply of the model.
+        expected: Reply,
+        /// The reply of the cluster.
+        actual: RedisResult<Reply>,
+    },
+    /// A disruption failed.
+    Disruption {
+        /// Index of the disruption.
+        step: usize,
+        /// The disruption.
+        op: Op,
+        /// The error it failed with.
+        error: RedisError,
+    },
+}
+
+impl fmt::Display for CheckError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckError::Setup(error) => write!(f, "Could not clear the keys: {}", error),
+            CheckError::Mismatch {
+                step,
+                op,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Step {}: {:?} returned {:?}, expected {:?}",
+                step, op, actual, expected
+            ),
+            CheckError::Disruption { step, op, error } => {
+                write!(f, "Step {}: {:?} failed: {}", step, op, error)
+            }
+        }
+    }
+}
+
+impl Error for CheckError {}
+
+/// A random operation on one of `keys` keys, disruptions included.
+///
+/// # Panics
+///
+/// If `keys` is 0.
+pub fn op(keys: usize) -> impl Strategy<Value = Op> {
+    assert!(keys > 0, "A model check needs at least one key");
+    let key = (0..keys).prop_map(|i| format!("model-{}", i));
+    prop_oneof![
+        10 => (key.clone(), -1000..1000i64).prop_map(|(key, value)| Op::Set(key, value)),
+        10 => key.clone().prop_map(Op::Get),
+        5 => (key.clone(), -10..10i64).prop_map(|(key, increment)| Op::Incr(key, increment)),
+        3 => key.clone().prop_map(Op::Del),
+        1 => Just(Op::Failover),
+        1 => key.prop_map(Op::Reshard),
+    ]
+}
+
+/// A sequence of random operations on `keys` keys, see [`op`].
+pub fn ops(keys: usize, len: impl Into<SizeRange>) -> impl Strategy<Value = Vec<Op>> {
+    proptest::collection::vec(op(keys), len)
+}
+
+/// Runs `ops` through `connection` and checks every reply against a [`Model`]. Disruptions are
+/// run on the nodes of `env`. The keys of `ops` are deleted first, so the cluster starts out like
+/// the model.
+pub async fn check(env: &RedisEnv, connection: &Connection, ops: &[Op]) -> Result<(), CheckError> {
+    let mut connection = connection.clone();
+    for key in ops.iter().filter_map(Op::key) {
+        cmd("DEL")
+            .arg(key)
+            .query_async::<_, ()>(&mut connection)
+            .await
+            .map_err(CheckError::Setup)?;
+    }
+
+    let mut model = Model::default();
+    let mut disruption: Option<(usize, BoxFuture<'static, RedisResult<()>>)> = None;
+    for (step, op) in ops.iter().enumerate() {
+        if op.is_disruption() {
+            if let Some((step, running)) = disruption.take() {
+                finish(ops, step, running.await)?;
+            }
+            disruption = Some((step, disrupt(env.nodes.clone(), op.clone()).boxed()));
+            continue;
+        }
+
+        let expected = model.apply(op).expect("Operations have a reply");
+        let execution = Box::pin(execute(&mut connection, op));
+        let actual = match &mut disruption {
+            Some((disrupted, running)) => match future::select(running, execution).await {
+                Either::Left((result, execution)) => {
+                    finish(ops, *disrupted, result)?;
+                    disruption = None;
+                    execution.await
+                }
+                Either::Right((actual, _)) => actual,
+            },
+            None => execution.await,
+        };
+        if actual.as_ref() != Ok(&expected) {
+            return Err(CheckError::Mismatch {
+                step,
+                op: op.clone(),
+                expected,
+                actual,
+            });
+        }
+    }
+    if let Some((step, running)) = disruption {
+        finish(ops, step, running.await)?;
+    }
+    Ok(())
+}
+
+fn finish(ops: &[Op], step: usize, result: RedisResult<()>) -> Result<(), CheckError> {
+    result.map_err(|error| CheckError::Disruption {
+        step,
+        op: ops[step].clone(),
+        error,
+    })
+}
+
+async fn execute(connection: &mut Connection, op: &Op) -> RedisResult<Reply> {
+    let command = match op {
+        Op::Set(key, value) => cmd("SET").arg(key).arg(*value).clone(),
+        Op::Get(key) => cmd("GET").arg(key).clone(),
+        Op::Incr(key, increment) => cmd("INCRBY").arg(key).arg(*increment).clone(),
+        Op::Del(key) => cmd("DEL").arg(key).clone(),
+        Op::Failover | Op::Reshard(_) => unreachable!("Disruptions are not executed as commands"),
+    };
+    let value: Value = command.query_async(connection).await?;
+    Reply::from_value(&value)
+}
+
+async fn disrupt(mut nodes: Vec<MultiplexedConnection>, op: Op) -> RedisResult<()> {
+    match op {
+        Op::Failover => failover(&mut nodes).await,
+        Op::Reshard(key) => reshard(nodes, &key).await,
+        _ => Ok(()),
+    }
+}
+
+// Fails over every replica, masters refuse the command. Succeeds if any node accepted it.
+async fn failover(nodes: &mut [MultiplexedConnection]) -> RedisResult<()> {
+    let mut result = Err(RedisError::from((
+        ErrorKind::ClientError,
+        "No replica to fail over",
+    )));
+    for node in nodes {
+        let failover = cmd("CLUSTER")
+            .arg("FAILOVER")
+            .query_async::<_, ()>(node)
+            .await;
+        result = result.or(failover);
+    }
+    result
+}
+
+// Moves the slot of `key` from its master to the next one, with its keys
+async fn reshard(nodes: Vec<MultiplexedConnection>, key: &str) -> RedisResult<()> {
+    match SlotMigration::start(nodes, key).await? {
+        Some(migration) => migration.finish().await,
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn model_replies() {
+        let key = || "key".to_string();
+        let mut model = Model::default();
+        assert_eq!(model.apply(&Op::Get(key())), Some(Reply::Nil));
+        assert_eq!(model.apply(&Op::Incr(key(), 2)), Some(Reply::Int(2)));
+        assert_eq!(model.apply(&Op::Set(key(), 10)), Some(Reply::Okay));
+        assert_eq!(model.apply(&Op::Failover), None);
+        assert_eq!(model.apply(&Op::Get(key())), Some(Reply::Int(10)));
+        assert_eq!(model.apply(&Op::Del(key())), Some(Reply::Int(1)));
+        assert_eq!(model.apply(&Op::Del(key())), Some(Reply::Int(0)));
+
+        assert_eq!(
+            Reply::from_value(&Value::Data(b"-3".to_vec())).unwrap(),
+            Reply::Int(-3)
+        );
+    }
+}
@@ -0,0 +1,154 @@
+//! Changes between consecutive slot maps, reported to [`Connection::topology_events`](crate::Connection::topology_events).
+
+/// A change of the cluster topology, as seen by the client when it refreshed its slot map. Nodes
+/// are given as `host:port`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TopologyEvent {
+    /// A master which did not serve any slot before serves some now.
+    ShardAdded {
+        /// The new master.
+        master: String,
+    },
+    /// The slots `start..=end` are served by another master.
+    SlotsMoved {
+        /// First slot of the range.
+        start: u16,
+        /// Last slot of the range.
+        end: u16,
+        /// The master which served them.
+        from: String,
+        /// The master which serves them now.
+        to: String,
+    },
+    /// A master does not serve any slot anymore.
+    ShardRemoved {
+        /// The former master.
+        master: String,
+    },
+}
+
+// A map from slot ranges to the `host:port` of their master, sorted and covering every slot
+pub(crate) type SlotRanges = Vec<(u16, u16, String)>;
+
+// The events turning `old` into `new`: masters which appeared, moved slot ranges (consecutive
+// slots moving between the same masters are reported as one range) and masters which are gone
+pub(crate) fn diff(old: &SlotRanges, new: &SlotRanges) -> Vec<TopologyEvent> {
+    let mut moved: Vec<TopologyEvent> = Vec::new();
+    let (mut old_ranges, mut new_ranges) = (old.iter().peekable(), new.iter().peekable());
+    while let (Some(&&(old_start, old_end, ref from)), Some(&&(new_start, new_end, ref to))) =
+        (old_ranges.peek(), new_ranges.peek())
+    {
+        let (start, end) = (old_start.max(new_start), old_end.min(new_end));
+        if from != to {
+            match moved.last_mut() {
+                Some(TopologyEvent::SlotsMoved {
+                    end: last_end,
+                    from: last_from,
+                    to: last_to,
+                    ..
+                }) if *last_end + 1 == start && last_from == from && last_to == to => {
+                    *last_end = end
+                }
+                _ => moved.push(TopologyEvent::SlotsMoved {
+                    start,
+                    end,
+                    from: from.clone(),
+                    to: to.clone(),
+                }),
+            }
+        }
+        if old_end == end {
+            old_ranges.next();
+        }
+        if new_end == end {
+            new_ranges.next();
+        }
+    }
+
+    let masters = |ranges: &SlotRanges| {
+        let mut masters: Vec<String> = Vec::new();
+        for (_, _, master) in ranges {
+            if !masters.contains(master) {
+                masters.push(master.clone());
+            }
+        }
+        masters
+    };
+    let (old_masters, new_masters) = (masters(old), masters(new));
+    let added = new_masters
+        .iter()
+        .filter(|master| !old_masters.contains(master))
+        .map(|master| TopologyEvent::ShardAdded {
+            master: master.clone(),
+        });
+    let removed = old_masters
+        .iter()
+        .filter(|master| !new_masters.contains(master))
+        .map(|master| TopologyEvent::ShardRemoved {
+            master: master.clone(),
+        });
+    added.chain(moved).chain(removed).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges(ranges: &[(u16, u16, &str)]) -> SlotRanges {
+        ranges
+            .iter()
+            .map(|&(start, end, master)| (start, end, master.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn diff_slot_maps() {
+        let old = ranges(&[(0, 8191, "a:1"), (8192, 16383, "b:1")]);
+        assert_eq!(diff(&old, &old), []);
+
+        // `c` takes over the end of `a` and the start of `b`, `b` is left with one slot
+        let new = ranges(&[
+            (0, 4095, "a:1"),
+            (4096, 16382, "c:1"),
+            (16383, 16383, "b:1"),
+        ]);
+        assert_eq!(
+            diff(&old, &new),
+            [
+                TopologyEvent::ShardAdded {
+                    master: "c:1".into()
+                },
+                TopologyEvent::SlotsMoved {
+                    start: 4096,
+                    end: 8191,
+                    from: "a:1".into(),
+                    to: "c:1".into()
+                },
+                TopologyEvent::SlotsMoved {
+                    start: 8192,
+                    end: 16382,
+                    from: "b:1".into(),
+                    to: "c:1".into()
+                },
+            ]
+        );
+
+        // Ranges split differently but served by the same masters are merged
+        let new = ranges(&[(0, 100, "b:1"), (101, 8191, "b:1"), (8192, 16383, "b:1")]);
+        assert_eq!(
+            diff(&old, &new),
+            [
+                TopologyEvent::SlotsMoved {
+                    start: 0,
+                    end: 8191,
+                    from: "a:1".into(),
+                    to: "b:1".into()
+                },
+                TopologyEvent::ShardRemoved {
+                    master: "a:1".into()
+                },
+            ]
+        );
+    }
+}
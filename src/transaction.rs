@@ -0,0 +1,92 @@
+//! `MULTI`/`EXEC` transactions whose keys share a slot, see [`Connection::transaction`].
+
+use std::collections::BTreeSet;
+
+use redis::{
+    aio::ConnectionLike, Arg, ErrorKind, FromRedisValue, Pipeline, RedisError, RedisResult,
+};
+
+use crate::{key_slot, namespace::key_positions, Connection};
+
+impl<C> Connection<C>
+where
+    C: ConnectionLike + Send + 'static,
+{
+    /// Runs the commands of `pipeline` in a `MULTI`/`EXEC` transaction and returns the replies
+    /// of the commands which are not ignored, like an atomic pipeline sent with `query_async`.
+    /// `pipeline` is sent as a transaction whether or not [`Pipeline::atomic`] was called on it.
+    ///
+    /// Every key of every command has to be in the same slot, use a hash tag (`{user:1}:name`,
+    /// `{user:1}:visits`) to put them together. The whole block, from `MULTI` to `EXEC`, is sent
+    /// at once over the connection of the master of that slot, so the commands of the other
+    /// users of the connection can not end up inside of it. A `MOVED` redirection, which aborts
+    /// the transaction before any command ran, sends the whole transaction again to the new
+    /// owner of the slot like any other command.
+    ///
+    /// `WATCH` is not supported, the node connection being shared with the other users of the
+    /// cluster connection.
+    ///
+    /// ```rust,no_run
+    /// # async fn run(connection: redis_cluster_async::Connection) -> redis::RedisResult<()> {
+    /// let mut pipeline = redis::pipe();
+    /// pipeline
+    ///     .set("{user:1}:name", "ada")
+    ///     .ignore()
+    ///     .incr("{user:1}:visits", 1);
+    /// let (visits,): (u64,) = connection.transaction(&pipeline).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Fails with a client error, without sending anything, if the keys are in more than one
+    /// slot or if `pipeline` contains `WATCH`.
+    pub async fn transaction<T: FromRedisValue>(&self, pipeline: &Pipeline) -> RedisResult<T> {
+        let slot = self.transaction_slot(pipeline)?;
+        let mut transaction = pipeline.clone();
+        transaction.atomic();
+        let mut connection = match slot {
+            Some(slot) => self.with_slot(slot),
+            None => self.clone(),
+        };
+        transaction.query_async(&mut connection).await
+    }
+
+    // The slot of every key of `pipeline` once rewritten, `None` if it has no key
+    fn transaction_slot(&self, pipeline: &Pipeline) -> RedisResult<Option<u16>> {
+        let mut slots = BTreeSet::new();
+        for cmd in pipeline.cmd_iter() {
+            let cmd = self.shared.keys.cmd(cmd);
+            let args = cmd
+                .args_iter()
+                .map(|arg| match arg {
+                    Arg::Simple(arg) => arg,
+                    Arg::Cursor => &[][..],
+                })
+                .collect::<Vec<_>>();
+            if args
+                .first()
+                .is_some_and(|name| name.eq_ignore_ascii_case(b"WATCH"))
+            {
+                return Err(RedisError::from((
+                    ErrorKind::ClientError,
+                    "WATCH can not be used in a cluster transaction",
+                )));
+            }
+            slots.extend(
+                key_positions(&args)
+                    .into_iter()
+                    .map(|position| key_slot(args[position])),
+            );
+        }
+        if slots.len() > 1 {
+            return Err(RedisError::from((
+                ErrorKind::ClientError,
+                "The keys of a transaction must be in the same slot",
+                format!("keys in slots {:?}", slots),
+            )));
+        }
+        Ok(slots.into_iter().next())
+    }
+}
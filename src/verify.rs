@@ -0,0 +1,175 @@
+//! Reads answered by two nodes holding the same slot whose replies are compared, see
+//! [`Connection::verified_query`].
+
+use std::{fmt, sync::Arc};
+
+use futures::future;
+use log::warn;
+use rand::{seq::IteratorRandom, thread_rng};
+use redis::{aio::ConnectionLike, Cmd, ErrorKind, FromRedisValue, RedisError, RedisResult, Value};
+
+use crate::{command, idempotency, node_name, Connection, Route};
+
+/// How [`Connection::verified_query`] checks a read, and what it calls when the two replies
+/// differ.
+///
+/// ```rust
+/// use redis_cluster_async::ReadVerification;
+///
+/// let verification = ReadVerification::new(|divergence| {
+///     eprintln!(
+///         "{} and {} disagree on slot {}",
+///         divergence.first.0, divergence.second.0, divergence.slot
+///     );
+/// });
+/// ```
+#[derive(Clone)]
+pub struct ReadVerification {
+    replicas_only: bool,
+    on_divergence: Arc<dyn Fn(&Divergence) + Send + Sync>,
+}
+
+impl ReadVerification {
+    /// Compare the reply of the master with the reply of one of its replicas, calling
+    /// `on_divergence` whenever they differ.
+    pub fn new(on_divergence: impl Fn(&Divergence) + Send + Sync + 'static) -> Self {
+        ReadVerification {
+            replicas_only: false,
+            on_divergence: Arc::new(on_divergence),
+        }
+    }
+
+    /// Compare the replies of two replicas instead, keeping the verified reads off the master.
+    /// Default: `false`
+    pub fn replicas_only(mut self, replicas_only: bool) -> Self {
+        self.replicas_only = replicas_only;
+        self
+    }
+}
+
+impl fmt::Debug for ReadVerification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReadVerification")
+            .field("replicas_only", &self.replicas_only)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The replies of two nodes which differed for the same read, see [`ReadVerification`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    /// Name of the command, like `GET`.
+    pub command: String,
+    /// Slot of the keys read.
+    pub slot: u16,
+    /// `host:port` and reply of the node whose reply was returned, the master unless reading from
+    /// replicas only.
+    pub first: (String, Value),
+    /// `host:port` and reply of the replica it was compared with.
+    pub second: (String, Value),
+}
+
+impl<C> Connection<C>
+where
+    C: ConnectionLike + Send + 'static,
+{
+    /// Sends the read `cmd` to the master of its slot and to a random replica of it at once
+    /// (or to two replicas, see [`ReadVerification::replicas_only`]) and returns the reply of the
+    /// first, after calling the callback of `verification` if the replies differ. A divergence
+    /// is either replication lag or corruption, the replica may not have received the last writes
+    /// yet.
+    ///
+    /// Replicas are sent `READONLY` ahead of the read, so a [`CommandFilter::Allow`] has to allow
+    /// it. A read which the second node fails to answer is returned without being verified.
+    ///
+    /// # Errors
+    ///
+    /// Fails with a client error for commands which write or do not name a key, and for slots
+    /// without enough replicas to compare with.
+    ///
+    /// [`CommandFilter::Allow`]: crate::CommandFilter::Allow
+    pub async fn verified_query<T: FromRedisValue>(
+        &self,
+        cmd: &Cmd,
+        verification: &ReadVerification,
+    ) -> RedisResult<T> {
+        if !idempotency::is_idempotent(cmd, false) {
+            return Err(RedisError::from((
+                ErrorKind::ClientError,
+                "Only read-only commands can be verified",
+            )));
+        }
+        let slot = self.cmd_slot(cmd).ok_or_else(|| {
+            RedisError::from((
+                ErrorKind::ClientError,
+                "Only commands reading keys can be verified",
+            ))
+        })?;
+        let (first, second) = self.verifying_nodes(slot, verification.replicas_only)?;
+
+        let (first_reply, second_reply) = future::join(
+            self.read_on(&first, cmd, verification.replicas_only),
+            self.read_on(&second, cmd, true),
+        )
+        .await;
+        let first_reply = first_reply?;
+        match second_reply {
+            Ok(second_reply) if second_reply != first_reply => {
+                (verification.on_divergence)(&Divergence {
+                    command: command::display_name(cmd),
+                    slot,
+                    first: (first, first_reply.clone()),
+                    second: (second, second_reply),
+                })
+            }
+            Ok(_) => (),
+            Err(err) => warn!("Could not verify a read against {}: {}", second, err),
+        }
+        redis::from_redis_value(&first_reply)
+    }
+
+    // The `host:port` of the two nodes of `slot` a verified read is sent to
+    fn verifying_nodes(&self, slot: u16, replicas_only: bool) -> RedisResult<(String, String)> {
+        let slots = self.shared.slots.load();
+        let addrs = match slots.range(&slot..).next() {
+            Some((_, addrs)) => addrs,
+            None => {
+                return Err(RedisError::from((
+                    ErrorKind::ClusterDown,
+                    "No node serves the slot",
+                    slot.to_string(),
+                )))
+            }
+        };
+        let wanted = if replicas_only { 2 } else { 1 };
+        let mut replicas = addrs
+            .replicas
+            .iter()
+            .map(|addr| node_name(addr))
+            .choose_multiple(&mut thread_rng(), wanted);
+        if replicas.len() < wanted {
+            return Err(RedisError::from((
+                ErrorKind::ClientError,
+                "Not enough replicas to verify the read",
+                format!("slot {} has {} replicas", slot, addrs.replicas.len()),
+            )));
+        }
+        let second = replicas.pop().unwrap();
+        let first = replicas.pop().unwrap_or_else(|| node_name(&addrs.master));
+        Ok((first, second))
+    }
+
+    async fn read_on(&self, node: &str, cmd: &Cmd, replica: bool) -> RedisResult<Value> {
+        let mut connection = self.with_route(Route::Node(node.to_string()));
+        if !replica {
+            return connection.req_packed_command(cmd).await;
+        }
+        let (value,) = redis::pipe()
+            .cmd("READONLY")
+            .ignore()
+            .add_command(cmd.clone())
+            .query_async(&mut connection)
+            .await?;
+        Ok(value)
+    }
+}
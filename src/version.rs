@@ -0,0 +1,172 @@
+//! Redis versions of the nodes and the optional features which depend on them.
+
+use std::{collections::HashMap, fmt, str::FromStr};
+
+use redis::{aio::ConnectionLike, Cmd, ErrorKind, RedisError, RedisResult};
+
+use crate::Connection;
+
+/// A Redis server version, as reported by `INFO server`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RedisVersion {
+    /// Major version.
+    pub major: u16,
+    /// Minor version.
+    pub minor: u16,
+    /// Patch version.
+    pub patch: u16,
+}
+
+impl RedisVersion {
+    /// Creates a version from its parts.
+    pub const fn new(major: u16, minor: u16, patch: u16) -> Self {
+        RedisVersion {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    // Extracts `redis_version` from the reply of `INFO server`
+    fn from_info(info: &str) -> Option<Self> {
+        info.lines()
+            .find_map(|line| line.strip_prefix("redis_version:"))
+            .and_then(|version| version.trim().parse().ok())
+    }
+}
+
+impl FromStr for RedisVersion {
+    type Err = RedisError;
+
+    fn from_str(s: &str) -> RedisResult<Self> {
+        let invalid = || RedisError::from((ErrorKind::TypeError, "Invalid version", s.to_string()));
+        let mut parts = s.splitn(3, '.').map(|part| part.parse::<u16>());
+        let mut next = |required: bool| match parts.next() {
+            Some(part) => part.map_err(|_| invalid()),
+            None if required => Err(invalid()),
+            None => Ok(0),
+        };
+        Ok(RedisVersion::new(next(true)?, next(false)?, next(false)?))
+    }
+}
+
+impl fmt::Display for RedisVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// An optional feature which is only available from some Redis version on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Feature {
+    /// `CLIENT NO-EVICT`.
+    ClientNoEvict,
+    /// `CLIENT NO-TOUCH`.
+    ClientNoTouch,
+    /// `CLIENT PAUSE WRITE` and `CLIENT UNPAUSE`.
+    ClientPauseWrite,
+}
+
+impl Feature {
+    /// The first Redis version which supports the feature.
+    pub fn required_version(self) -> RedisVersion {
+        match self {
+            Feature::ClientPauseWrite => RedisVersion::new(6, 2, 0),
+            Feature::ClientNoEvict => RedisVersion::new(7, 0, 0),
+            Feature::ClientNoTouch => RedisVersion::new(7, 2, 0),
+        }
+    }
+
+    // Fails if a node running `version` does not support the feature
+    pub(crate) fn check(self, node: &str, version: RedisVersion) -> RedisResult<()> {
+        let required = self.required_version();
+        if version < required {
+            return Err(RedisError::from((
+                ErrorKind::ClientError,
+                "Feature is not supported by the cluster",
+                format!(
+                    "{} requires Redis {}, but {} runs {}",
+                    self, required, node, version
+                ),
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Feature::ClientNoEvict => "CLIENT NO-EVICT",
+            Feature::ClientNoTouch => "CLIENT NO-TOUCH",
+            Feature::ClientPauseWrite => "CLIENT PAUSE WRITE",
+        })
+    }
+}
+
+// Asks a freshly connected node for its version. Nodes which do not allow `INFO` (e.g. because of
+// their ACLs) or whose reply cannot be parsed are treated as having an unknown version.
+pub(crate) async fn query_version<C>(conn: &mut C) -> Option<RedisVersion>
+where
+    C: ConnectionLike + Send,
+{
+    let info: String = Cmd::new()
+        .arg("INFO")
+        .arg("server")
+        .query_async(conn)
+        .await
+        .ok()?;
+    RedisVersion::from_info(&info)
+}
+
+impl<C> Connection<C> {
+    /// Returns the version of every node in the last discovered topology, keyed by `host:port`.
+    /// The version of a node is queried once, when the client first connects to it, and nodes
+    /// whose version could not be determined then are left out.
+    pub fn node_versions(&self) -> HashMap<String, RedisVersion> {
+        let versions = self.shared.versions.lock().unwrap();
+        self.node_names(None)
+            .into_iter()
+            .filter_map(|node| {
+                let version = (*versions.get(&node)?)?;
+                Some((node, version))
+            })
+            .collect()
+    }
+
+    /// Fails with an error naming the offending node if any node of the cluster runs a version
+    /// which does not support `feature`.
+    pub fn check_feature(&self, feature: Feature) -> RedisResult<()> {
+        let mut versions = self.node_versions().into_iter().collect::<Vec<_>>();
+        versions.sort();
+        versions
+            .iter()
+            .try_for_each(|(node, version)| feature.check(node, *version))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_versions() {
+        let info = "# Server\r\nredis_version:7.2.4\r\nredis_git_sha1:00000000\r\n";
+        assert_eq!(
+            RedisVersion::from_info(info),
+            Some(RedisVersion::new(7, 2, 4))
+        );
+        assert_eq!("6.0".parse(), Ok(RedisVersion::new(6, 0, 0)));
+        assert!("7.x".parse::<RedisVersion>().is_err());
+        assert_eq!(RedisVersion::from_info("# Server\r\n"), None);
+
+        assert!(RedisVersion::new(7, 0, 11) < Feature::ClientNoTouch.required_version());
+        assert!(Feature::ClientNoTouch
+            .check("a:1", RedisVersion::new(7, 2, 0))
+            .is_ok());
+        assert!(Feature::ClientNoTouch
+            .check("a:1", RedisVersion::new(7, 0, 11))
+            .is_err());
+    }
+}
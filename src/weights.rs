@@ -0,0 +1,126 @@
+/// How the reads sent to replicas are spread over the nodes of a slot, for rolling out reads from
+/// replicas gradually or keeping them off some replicas.
+///
+/// Installed with [`Client::set_read_weights`](crate::Client::set_read_weights). Each read picks
+/// one of the master and the replicas of its slot with a probability proportional to the weight
+/// of the node. A node of weight 0 is never picked, unless every node of the slot has weight 0 in
+/// which case the master is.
+///
+/// ```rust
+/// use redis_cluster_async::ReadWeights;
+///
+/// // With one replica per master, keep 10% of the reads on the masters
+/// let weights = ReadWeights::new().master(1).replica(9);
+/// // Send the reads to one replica, falling back to the others only if it is gone
+/// let weights = ReadWeights::new()
+///     .node("10.0.0.2:7000", 1000)
+///     .replica(1);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReadWeights {
+    master: u32,
+    replica: u32,
+    nodes: Vec<(String, u32)>,
+}
+
+impl Default for ReadWeights {
+    fn default() -> Self {
+        ReadWeights {
+            master: 0,
+            replica: 1,
+            nodes: Vec::new(),
+        }
+    }
+}
+
+impl ReadWeights {
+    /// Create weights which spread the reads evenly over the replicas, the master getting none.
+    pub fn new() -> Self {
+        ReadWeights::default()
+    }
+
+    /// Weight of the masters which are not given one with [`ReadWeights::node`]. Default: 0
+    pub fn master(mut self, weight: u32) -> Self {
+        self.master = weight;
+        self
+    }
+
+    /// Weight of the replicas which are not given one with [`ReadWeights::node`]. Default: 1
+    pub fn replica(mut self, weight: u32) -> Self {
+        self.replica = weight;
+        self
+    }
+
+    /// Weight of the node at `node` (`host:port`, as announced by the cluster), whether it is a
+    /// master or a replica.
+    pub fn node(mut self, node: impl Into<String>, weight: u32) -> Self {
+        self.nodes.push((node.into(), weight));
+        self
+    }
+
+    fn weight(&self, node: &str, default: u32) -> u32 {
+        self.nodes
+            .iter()
+            .find(|(name, _)| name == node)
+            .map_or(default, |(_, weight)| *weight)
+    }
+
+    // Picks the node a read of a slot is sent to, `roll` being drawn uniformly from `0..1`
+    pub(crate) fn choose<'a, T>(
+        &self,
+        master: &'a T,
+        replicas: &'a [T],
+        name: impl Fn(&T) -> String,
+        roll: f64,
+    ) -> &'a T {
+        let candidates = std::iter::once((master, self.weight(&name(master), self.master)))
+            .chain(
+                replicas
+                    .iter()
+                    .map(|replica| (replica, self.weight(&name(replica), self.replica))),
+            )
+            .collect::<Vec<_>>();
+        let total = candidates
+            .iter()
+            .map(|(_, weight)| u64::from(*weight))
+            .sum::<u64>();
+        let mut target = (roll * total as f64) as u64;
+        for (node, weight) in candidates {
+            let weight = u64::from(weight);
+            if target < weight {
+                return node;
+            }
+            target -= weight;
+        }
+        master
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MASTER: &str = "m:1";
+    const REPLICAS: [&str; 2] = ["a:1", "b:1"];
+
+    fn choose(weights: &ReadWeights, roll: f64) -> &'static str {
+        weights.choose::<&str>(&MASTER, &REPLICAS, |node| node.to_string(), roll)
+    }
+
+    #[test]
+    fn reads_follow_the_weights() {
+        let even = ReadWeights::new();
+        assert_eq!(choose(&even, 0.0), "a:1");
+        assert_eq!(choose(&even, 0.49), "a:1");
+        assert_eq!(choose(&even, 0.5), "b:1");
+        assert_eq!(choose(&even, 0.99), "b:1");
+
+        let weights = ReadWeights::new().master(1).replica(9).node("b:1", 0);
+        assert_eq!(choose(&weights, 0.05), "m:1");
+        assert_eq!(choose(&weights, 0.15), "a:1");
+        assert_eq!(choose(&weights, 0.95), "a:1");
+
+        let nothing = ReadWeights::new().replica(0);
+        assert_eq!(choose(&nothing, 0.5), "m:1");
+    }
+}
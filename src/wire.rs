@@ -0,0 +1,92 @@
+//! Summaries of the commands sent to the nodes and of their replies, logged when wire tracing is
+//! enabled (see [`Connection::set_wire_trace`](crate::Connection::set_wire_trace)).
+//!
+//! Only the name and the key of a command are logged, never its values, and replies are reduced to
+//! their type and size, so the log can be enabled in production.
+
+use redis::{Arg, Cmd, RedisError, Value};
+
+use crate::command;
+
+// Longest key which is logged in full
+const MAX_KEY_LEN: usize = 64;
+
+// Commands whose first argument is a secret rather than a key
+const SECRET_ARGS: &[&str] = &["AUTH", "HELLO"];
+
+// `GET key`, with the key truncated
+pub(crate) fn describe_cmd(cmd: &Cmd) -> String {
+    let mut args = cmd.args_iter().filter_map(|arg| match arg {
+        Arg::Simple(arg) => Some(arg),
+        Arg::Cursor => None,
+    });
+    let name = match args.next() {
+        Some(name) => name,
+        None => return String::new(),
+    };
+    let secret = command::is_one_of(name, SECRET_ARGS);
+    let name = command::display(name);
+    match args.next() {
+        Some(_) if secret => format!("{} <redacted>", name),
+        Some(key) if key.len() > MAX_KEY_LEN => format!(
+            "{} {}... ({} bytes)",
+            name,
+            String::from_utf8_lossy(&key[..MAX_KEY_LEN]),
+            key.len()
+        ),
+        Some(key) => format!("{} {}", name, String::from_utf8_lossy(key)),
+        None => name,
+    }
+}
+
+// `bulk of 3`, `error: MOVED`, ...
+pub(crate) fn describe_reply(result: Result<Vec<&Value>, &RedisError>) -> String {
+    match result {
+        Ok(values) => values
+            .iter()
+            .map(|value| describe_value(value))
+            .collect::<Vec<_>>()
+            .join(", "),
+        Err(err) => match err.code() {
+            Some(code) => format!("error: {}", code),
+            None => format!("error: {}", err.category()),
+        },
+    }
+}
+
+fn describe_value(value: &Value) -> String {
+    match value {
+        Value::Nil => "nil".into(),
+        Value::Int(_) => "integer".into(),
+        Value::Data(data) => format!("{} bytes", data.len()),
+        Value::Bulk(values) => format!("bulk of {}", values.len()),
+        Value::Status(_) => "status".into(),
+        Value::Okay => "OK".into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use redis::cmd;
+
+    #[test]
+    fn values_are_not_described() {
+        assert_eq!(describe_cmd(cmd("set").arg("key").arg("value")), "SET key");
+        assert_eq!(
+            describe_cmd(cmd("AUTH").arg("user").arg("secret")),
+            "AUTH <redacted>"
+        );
+        assert_eq!(
+            describe_cmd(cmd("GET").arg(vec![b'k'; 100])),
+            format!("GET {}... (100 bytes)", "k".repeat(64))
+        );
+        assert_eq!(describe_cmd(&cmd("PING")), "PING");
+
+        let values = [Value::Data(b"secret".to_vec()), Value::Nil];
+        assert_eq!(describe_reply(Ok(values.iter().collect())), "6 bytes, nil");
+        let err = redis::parse_redis_value(b"-MOVED 1 a:6379\r\n").unwrap_err();
+        assert_eq!(describe_reply(Err(&err)), "error: MOVED");
+    }
+}
@@ -1,17 +1,24 @@
 use std::{
     collections::HashMap,
-    sync::{atomic, Arc, RwLock},
+    sync::{atomic, Arc, Mutex, RwLock},
+    time::{Duration, Instant},
 };
 
 use {
-    futures::future,
+    futures::future::{self, BoxFuture, FutureExt},
     once_cell::sync::Lazy,
     redis_cluster_async::{
+        key_slot,
         redis::{
             aio::ConnectionLike, cmd, parse_redis_value, IntoConnectionInfo, RedisFuture,
             RedisResult, Value,
         },
-        Client, Connect,
+        BackgroundError, Client, Clock, Cluster, CommandFilter, Connect, ConnectionConfig,
+        Divergence, DualWriteConnection, EncodingAudit, Feature, HashTagRule, KeyTtl, LatencyEvent,
+        LatencySample, NodeCounts, NodeResults, NodeRole, PauseMode, PausePolicy,
+        ReadFromReplicaStrategy, ReadVerification, ReadWeights, RedisVersion, RemapRule,
+        RetryConfig, SlowlogEntry, StartupPolicy, TopologyEvent, TopologyStore, TtlAudit,
+        WriteErrors,
     },
     tokio::runtime::Runtime,
 };
@@ -20,6 +27,9 @@ type Handler = Arc<dyn Fn(&redis::Cmd, u16) -> Result<(), RedisResult<Value>> +
 
 static HANDLERS: Lazy<RwLock<HashMap<String, Handler>>> = Lazy::new(Default::default);
 
+// Host, port and `insecure` of every TLS connection made
+static TLS_CONNECTIONS: Lazy<Mutex<Vec<(String, u16, bool)>>> = Lazy::new(Default::default);
+
 #[derive(Clone)]
 pub struct MockConnection {
     handler: Handler,
@@ -35,6 +45,17 @@ impl Connect for MockConnection {
 
         let (name, port) = match &info.addr {
             redis::ConnectionAddr::Tcp(addr, port) => (addr, *port),
+            redis::ConnectionAddr::TcpTls {
+                host,
+                port,
+                insecure,
+            } => {
+                TLS_CONNECTIONS
+                    .lock()
+                    .unwrap()
+                    .push((host.clone(), *port, *insecure));
+                (host, *port)
+            }
             _ => unreachable!(),
         };
         Box::pin(future::ok(MockConnection {
@@ -59,36 +80,96 @@ fn contains_slice(xs: &[u8], ys: &[u8]) -> bool {
 }
 
 fn respond_startup(name: &str, cmd: &[u8]) -> Result<(), RedisResult<Value>> {
-    if contains_slice(&cmd, b"PING") {
+    if contains_slice(cmd, b"PING") {
         Err(Ok(Value::Status("OK".into())))
-    } else if contains_slice(&cmd, b"CLUSTER") && contains_slice(&cmd, b"SLOTS") {
-        Err(Ok(Value::Bulk(vec![Value::Bulk(vec![
-            Value::Int(0),
-            Value::Int(16383),
-            Value::Bulk(vec![
-                Value::Data(name.as_bytes().to_vec()),
-                Value::Int(6379),
-            ]),
-        ])])))
+    } else if contains_slice(cmd, b"QUIT") {
+        Err(Ok(Value::Okay))
+    } else if contains_slice(cmd, b"INFO") {
+        Err(Ok(Value::Data(
+            b"# Server\r\nredis_version:7.2.0\r\n".to_vec(),
+        )))
+    } else if contains_slice(cmd, b"CLUSTER") && contains_slice(cmd, b"SLOTS") {
+        Err(Ok(slots_reply(name, &[(0, 16383, 6379, &[])])))
     } else {
         Ok(())
     }
 }
 
+// A `CLUSTER SLOTS` reply: the first and last slot of every range, the port of its master and the
+// ports of its replicas, all of them on the host `name`
+fn slots_reply(name: &str, ranges: &[(i64, i64, i64, &[i64])]) -> Value {
+    let node = |port: i64| {
+        Value::Bulk(vec![
+            Value::Data(name.as_bytes().to_vec()),
+            Value::Int(port),
+        ])
+    };
+    Value::Bulk(
+        ranges
+            .iter()
+            .map(|&(start, end, master, replicas)| {
+                let mut range = vec![Value::Int(start), Value::Int(end), node(master)];
+                range.extend(replicas.iter().map(|&replica| node(replica)));
+                Value::Bulk(range)
+            })
+            .collect(),
+    )
+}
+
+// `Some(true)` for `CLIENT REPLY ON`, `Some(false)` for `CLIENT REPLY OFF`
+fn client_reply(cmd: &redis::Cmd) -> Option<bool> {
+    let args = cmd
+        .args_iter()
+        .map(|arg| match arg {
+            redis::Arg::Simple(arg) => arg.to_ascii_uppercase(),
+            redis::Arg::Cursor => Vec::new(),
+        })
+        .collect::<Vec<_>>();
+    match &args[..] {
+        [client, reply, mode] if client == b"CLIENT" && reply == b"REPLY" => Some(mode == b"ON"),
+        _ => None,
+    }
+}
+
 impl ConnectionLike for MockConnection {
     fn req_packed_command<'a>(&'a mut self, cmd: &'a redis::Cmd) -> RedisFuture<'a, Value> {
         Box::pin(future::ready(
-            (self.handler)(&cmd, self.port).expect_err("Handler did not specify a response"),
+            (self.handler)(cmd, self.port).expect_err("Handler did not specify a response"),
         ))
     }
 
     fn req_packed_commands<'a>(
         &'a mut self,
-        _pipeline: &'a redis::Pipeline,
-        _offset: usize,
-        _count: usize,
+        pipeline: &'a redis::Pipeline,
+        offset: usize,
+        count: usize,
     ) -> RedisFuture<'a, Vec<Value>> {
-        Box::pin(future::ok(vec![]))
+        // Like a node, run every command but leave out the replies while `CLIENT REPLY OFF` is on
+        let mut replies_on = true;
+        let values = pipeline
+            .cmd_iter()
+            .filter_map(|cmd| {
+                let reply =
+                    (self.handler)(cmd, self.port).expect_err("Handler did not specify a response");
+                if let Some(on) = client_reply(cmd) {
+                    replies_on = on;
+                }
+                replies_on.then_some(reply)
+            })
+            .collect::<RedisResult<Vec<_>>>()
+            .and_then(|values| {
+                // A transaction asks for the reply to `EXEC` only, which follows the `QUEUED`
+                // reply to every command
+                if offset > values.len() {
+                    Ok(vec![Value::Bulk(values)])
+                } else if values.len() < offset + count {
+                    // A node connection would wait forever for the missing replies
+                    Err((redis::ErrorKind::ClientError, "Missing replies").into())
+                } else {
+                    Ok(values.into_iter().skip(offset).take(count).collect())
+                }
+            });
+        Box::pin(future::ready(values))
     }
 
     fn get_db(&self) -> i64 {
@@ -129,7 +210,7 @@ impl MockEnv {
             Arc::new(move |cmd, port| handler(&cmd.get_packed_command(), port)),
         );
 
-        let client = Client::open(vec![&*format!("redis://{}", id)]).unwrap();
+        let client = Client::open(vec![format!("redis://{}", id)]).unwrap();
         let connection = runtime.block_on(client.get_generic_connection()).unwrap();
         MockEnv {
             runtime,
@@ -206,11 +287,146 @@ fn tryagain_exhaust_retries() {
 
     assert_eq!(
         result.map_err(|err| err.to_string()),
-        Err("An error was signalled by the server: mock".to_string())
+        Err(format!(
+            "An error was signalled by the server: mock (node {}:6379, slot 6918, command GET)",
+            name
+        ))
     );
     assert_eq!(requests.load(atomic::Ordering::SeqCst), 3);
 }
 
+#[test]
+fn redirect_trace() {
+    let _ = env_logger::try_init();
+    let name = "redirect_trace";
+
+    let MockEnv {
+        runtime,
+        mut client,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], _| {
+        respond_startup(name, cmd)?;
+        Err(parse_redis_value(
+            format!("-MOVED 6918 {}:6380\r\n", name).as_bytes(),
+        ))
+    });
+
+    let mut connection = runtime
+        .block_on(
+            client
+                .set_retries(Some(1))
+                .get_generic_connection::<MockConnection>(),
+        )
+        .unwrap();
+
+    let err = runtime
+        .block_on(
+            cmd("GET")
+                .arg("test")
+                .query_async::<_, Option<i32>>(&mut connection),
+        )
+        .unwrap_err();
+    let hop = format!("{0}:6379 -> {0}:6380 (MOVED 6918)", name);
+    assert_eq!(
+        err.detail(),
+        Some(&*format!(
+            "6918 {0}:6380 (node {0}:6379, slot 6918, command GET, redirected {1}, {1})",
+            name, hop
+        ))
+    );
+}
+
+#[test]
+fn query_with_info() {
+    let _ = env_logger::try_init();
+    let name = "query_with_info";
+
+    let requests = atomic::AtomicUsize::new(0);
+    let MockEnv {
+        runtime,
+        mut client,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], _| {
+        respond_startup(name, cmd)?;
+        match requests.fetch_add(1, atomic::Ordering::SeqCst) {
+            0 => Err(parse_redis_value(
+                format!("-MOVED 6918 {}:6379\r\n", name).as_bytes(),
+            )),
+            _ => Err(Ok(Value::Data(b"123".to_vec()))),
+        }
+    });
+
+    let mut connection = runtime
+        .block_on(
+            client
+                .set_clock(SteppingClock::default())
+                .get_generic_connection::<MockConnection>(),
+        )
+        .unwrap();
+
+    let (value, info) = runtime
+        .block_on(connection.query_with_info::<Option<i32>>(cmd("GET").arg("test")))
+        .unwrap();
+    assert_eq!(value, Some(123));
+    assert_eq!(info.node, format!("{}:6379", name));
+    assert_eq!(info.retries, 1);
+    assert_eq!(info.redirects, 1);
+    assert!(info.latency >= Duration::from_secs(3600), "{:?}", info);
+}
+
+#[test]
+fn borrowed_query() {
+    let _ = env_logger::try_init();
+    let name = "borrowed_query";
+
+    let MockEnv {
+        runtime,
+        mut connection,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], _| {
+        respond_startup(name, cmd)?;
+        Err(Ok(Value::Data(b"123".to_vec())))
+    });
+
+    let get = cmd("GET").arg("test").clone();
+    for _ in 0..2 {
+        let value = runtime.block_on(connection.query::<Option<i32>>(&get));
+        assert_eq!(value, Ok(Some(123)));
+    }
+    assert_eq!(
+        runtime.block_on(connection.req_command(&get)),
+        Ok(Value::Data(b"123".to_vec()))
+    );
+}
+
+#[test]
+fn generic_commands() {
+    let _ = env_logger::try_init();
+    let name = "generic_commands";
+
+    // Written against any connection, as a third party crate would be
+    async fn bump<C: ConnectionLike + Send>(connection: &mut C) -> RedisResult<i64> {
+        use redis::AsyncCommands;
+
+        connection.incr("counter", 1).await
+    }
+
+    let MockEnv {
+        runtime,
+        mut connection,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], _| {
+        respond_startup(name, cmd)?;
+        Err(Ok(Value::Int(7)))
+    });
+
+    assert_eq!(runtime.block_on(bump(&mut connection)), Ok(7));
+}
+
 #[test]
 fn rebuild_with_extra_nodes() {
     let _ = env_logger::try_init();
@@ -229,7 +445,7 @@ fn rebuild_with_extra_nodes() {
         }
         started.store(true, atomic::Ordering::SeqCst);
 
-        if contains_slice(&cmd, b"PING") {
+        if contains_slice(cmd, b"PING") {
             return Err(Ok(Value::Status("OK".into())));
         }
 
@@ -241,24 +457,10 @@ fn rebuild_with_extra_nodes() {
             // implementation)
             0 => Err(parse_redis_value(b"-MOVED 123\r\n")),
             // Respond with the new masters
-            1 => Err(Ok(Value::Bulk(vec![
-                Value::Bulk(vec![
-                    Value::Int(0),
-                    Value::Int(1),
-                    Value::Bulk(vec![
-                        Value::Data(name.as_bytes().to_vec()),
-                        Value::Int(6379),
-                    ]),
-                ]),
-                Value::Bulk(vec![
-                    Value::Int(2),
-                    Value::Int(16383),
-                    Value::Bulk(vec![
-                        Value::Data(name.as_bytes().to_vec()),
-                        Value::Int(6380),
-                    ]),
-                ]),
-            ]))),
+            1 => Err(Ok(slots_reply(
+                name,
+                &[(0, 1, 6379, &[]), (2, 16383, 6380, &[])],
+            ))),
             _ => {
                 // Check that the correct node receives the request after rebuilding
                 assert_eq!(port, 6380);
@@ -275,3 +477,3842 @@ fn rebuild_with_extra_nodes() {
 
     assert_eq!(value, Ok(Some(123)));
 }
+
+#[test]
+fn topology_events() {
+    use futures::StreamExt;
+
+    let _ = env_logger::try_init();
+    let name = "topology_events";
+
+    let refreshed = atomic::AtomicBool::new(false);
+    let MockEnv {
+        runtime,
+        mut connection,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], port| {
+        if contains_slice(cmd, b"SLOTS") && refreshed.load(atomic::Ordering::SeqCst) {
+            return Err(Ok(slots_reply(
+                name,
+                &[(0, 8191, 6379, &[]), (8192, 16383, 6380, &[])],
+            )));
+        }
+        respond_startup(name, cmd)?;
+        if port == 6379 && !refreshed.swap(true, atomic::Ordering::SeqCst) {
+            return Err(parse_redis_value(
+                format!("-MOVED 6918 {}:6380\r\n", name).as_bytes(),
+            ));
+        }
+        Err(Ok(Value::Data(b"123".to_vec())))
+    });
+
+    let mut events = connection.topology_events();
+    let value = runtime.block_on(
+        cmd("GET")
+            .arg("test")
+            .query_async::<_, Option<i32>>(&mut connection),
+    );
+    assert_eq!(value, Ok(Some(123)));
+
+    let node = format!("{}:6380", name);
+    assert_eq!(
+        events.next().now_or_never(),
+        Some(Some(TopologyEvent::ShardAdded {
+            master: node.clone()
+        }))
+    );
+    assert_eq!(
+        events.next().now_or_never(),
+        Some(Some(TopologyEvent::SlotsMoved {
+            start: 8192,
+            end: 16383,
+            from: format!("{}:6379", name),
+            to: node,
+        }))
+    );
+    assert_eq!(events.next().now_or_never(), None);
+}
+
+#[test]
+fn topology_store() {
+    #[derive(Clone, Default)]
+    struct MemoryStore(Arc<Mutex<Option<String>>>);
+
+    impl TopologyStore for MemoryStore {
+        fn load(&self) -> std::io::Result<Option<String>> {
+            Ok(self.0.lock().unwrap().clone())
+        }
+
+        fn save(&self, topology: &str) -> std::io::Result<()> {
+            *self.0.lock().unwrap() = Some(topology.to_string());
+            Ok(())
+        }
+    }
+
+    let _ = env_logger::try_init();
+    let name = "topology_store";
+
+    let slot_queries = Arc::new(atomic::AtomicUsize::new(0));
+    let MockEnv {
+        runtime,
+        mut client,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, {
+        let slot_queries = slot_queries.clone();
+        move |cmd: &[u8], port| {
+            if contains_slice(cmd, b"SLOTS") {
+                slot_queries.fetch_add(1, atomic::Ordering::SeqCst);
+            }
+            respond_startup(name, cmd)?;
+            Err(Ok(Value::Int(port.into())))
+        }
+    });
+    let store = MemoryStore::default();
+    client.set_topology_store(store.clone());
+
+    // The saved slot map is used without asking the initial node
+    let saved = format!("0-16383 {}:6380\n", name);
+    *store.0.lock().unwrap() = Some(saved.clone());
+    slot_queries.store(0, atomic::Ordering::SeqCst);
+    let mut connection = runtime
+        .block_on(client.get_generic_connection::<MockConnection>())
+        .unwrap();
+    let value = runtime.block_on(
+        cmd("GET")
+            .arg("test")
+            .query_async::<_, u16>(&mut connection),
+    );
+    assert_eq!(value, Ok(6380));
+    assert_eq!(slot_queries.load(atomic::Ordering::SeqCst), 0);
+    assert_eq!(store.0.lock().unwrap().as_ref(), Some(&saved));
+
+    // A slot map which can not be read is ignored, and replaced by the discovered one
+    *store.0.lock().unwrap() = Some("garbage".to_string());
+    let mut connection = runtime
+        .block_on(client.get_generic_connection::<MockConnection>())
+        .unwrap();
+    let value = runtime.block_on(
+        cmd("GET")
+            .arg("test")
+            .query_async::<_, u16>(&mut connection),
+    );
+    assert_eq!(value, Ok(6379));
+    assert_eq!(slot_queries.load(atomic::Ordering::SeqCst), 1);
+    assert_eq!(
+        store.0.lock().unwrap().as_deref(),
+        Some(&*format!("0-16383 {}:6379\n", name))
+    );
+}
+
+#[test]
+fn multi_key_helpers() {
+    let _ = env_logger::try_init();
+    let name = "multi_key_helpers";
+
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let MockEnv {
+        runtime,
+        connection,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, {
+        let sent = sent.clone();
+        move |cmd: &[u8], port| {
+            if contains_slice(cmd, b"SLOTS") {
+                return Err(Ok(slots_reply(
+                    name,
+                    &[(0, 8191, 6379, &[]), (8192, 16383, 6380, &[])],
+                )));
+            }
+            respond_startup(name, cmd)?;
+            let args: Vec<Vec<u8>> =
+                redis::from_redis_value(&parse_redis_value(cmd).unwrap()).unwrap();
+            sent.lock().unwrap().push((port, args.len() - 1));
+            let keys = &args[1..];
+            match &args[0][..] {
+                b"MGET" => Err(Ok(Value::Bulk(
+                    keys.iter()
+                        .map(|key| match &key[..] {
+                            b"{bar}missing" => Value::Nil,
+                            key => Value::Data(key.to_vec()),
+                        })
+                        .collect(),
+                ))),
+                b"MSET" => Err(Ok(Value::Okay)),
+                b"DEL" if keys.iter().any(|key| key == b"foo") => {
+                    Err(parse_redis_value(b"-ERR failed\r\n"))
+                }
+                b"DEL" => Err(Ok(Value::Int(keys.len() as i64))),
+                _ => panic!("Unexpected command {:?}", args),
+            }
+        }
+    });
+
+    // `foo` is served by 6380, the keys tagged with `bar` by 6379
+    let values = runtime.block_on(connection.cluster_mget::<_, String>(&[
+        "{bar}1",
+        "foo",
+        "{bar}missing",
+        "{bar}2",
+    ]));
+    assert_eq!(
+        values,
+        Ok(vec![
+            Some("{bar}1".to_string()),
+            Some("foo".to_string()),
+            None,
+            Some("{bar}2".to_string()),
+        ])
+    );
+    let mut sent_mget = sent.lock().unwrap().drain(..).collect::<Vec<_>>();
+    sent_mget.sort();
+    assert_eq!(sent_mget, [(6379, 3), (6380, 1)]);
+
+    let result = runtime.block_on(connection.cluster_mset(&[("foo", 1), ("{bar}1", 2)]));
+    assert_eq!(result, Ok(()));
+    let mut sent_mset = sent.lock().unwrap().drain(..).collect::<Vec<_>>();
+    sent_mset.sort();
+    assert_eq!(sent_mset, [(6379, 2), (6380, 2)]);
+
+    assert_eq!(
+        runtime.block_on(connection.cluster_del(&["{bar}1", "{bar}2"])),
+        Ok(2)
+    );
+    // The failure of one slot fails the whole command
+    let result = runtime.block_on(connection.cluster_del(&["{bar}1", "foo"]));
+    assert_eq!(
+        result.map_err(|err| err.code().map(str::to_string)),
+        Err(Some("ERR".into()))
+    );
+
+    assert_eq!(
+        runtime.block_on(connection.cluster_mget::<&str, String>(&[])),
+        Ok(vec![])
+    );
+}
+
+#[test]
+fn cross_slot_pipeline() {
+    let _ = env_logger::try_init();
+    let name = "cross_slot_pipeline";
+
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let MockEnv {
+        runtime,
+        mut connection,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, {
+        let sent = sent.clone();
+        move |cmd: &[u8], port| {
+            if contains_slice(cmd, b"SLOTS") {
+                return Err(Ok(slots_reply(
+                    name,
+                    &[(0, 8191, 6379, &[]), (8192, 16383, 6380, &[])],
+                )));
+            }
+            respond_startup(name, cmd)?;
+            let args: Vec<String> =
+                redis::from_redis_value(&parse_redis_value(cmd).unwrap()).unwrap();
+            sent.lock().unwrap().push((port, args[1].clone()));
+            Err(Ok(Value::Data(
+                format!("{}@{}", args[1], port).into_bytes(),
+            )))
+        }
+    });
+
+    // `bar` is served by 6379, `foo` and `qux` by 6380
+    let values = runtime.block_on(
+        redis::pipe()
+            .get("foo")
+            .get("bar")
+            .ignore()
+            .get("qux")
+            .query_async::<_, Vec<String>>(&mut connection),
+    );
+    assert_eq!(values, Ok(vec!["foo@6380".into(), "qux@6380".into()]));
+    let mut sent = sent.lock().unwrap().drain(..).collect::<Vec<_>>();
+    sent.sort();
+    assert_eq!(
+        sent,
+        [
+            (6379, "bar".to_string()),
+            (6380, "foo".to_string()),
+            (6380, "qux".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn cross_slot_commands() {
+    let _ = env_logger::try_init();
+    let name = "cross_slot_commands";
+
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let MockEnv {
+        runtime,
+        mut connection,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, {
+        let sent = sent.clone();
+        move |cmd: &[u8], port| {
+            if contains_slice(cmd, b"SLOTS") {
+                return Err(Ok(slots_reply(
+                    name,
+                    &[(0, 8191, 6379, &[]), (8192, 16383, 6380, &[])],
+                )));
+            }
+            respond_startup(name, cmd)?;
+            let args: Vec<String> =
+                redis::from_redis_value(&parse_redis_value(cmd).unwrap()).unwrap();
+            sent.lock().unwrap().push((port, args.join(" ")));
+            let keys = &args[1..];
+            match &args[0][..] {
+                "MGET" => Err(Ok(Value::Bulk(
+                    keys.iter()
+                        .map(|key| Value::Data(format!("{}@{}", key, port).into_bytes()))
+                        .collect(),
+                ))),
+                "MSET" => Err(Ok(Value::Okay)),
+                _ => Err(Ok(Value::Int(keys.len() as i64))),
+            }
+        }
+    });
+    let sent = || {
+        let mut sent = sent.lock().unwrap().drain(..).collect::<Vec<_>>();
+        sent.sort();
+        sent
+    };
+
+    // `bar` is served by 6379, `foo` and `qux` by 6380
+    let values = runtime.block_on(
+        cmd("MGET")
+            .arg(&["foo", "bar", "qux"])
+            .query_async::<_, Vec<String>>(&mut connection),
+    );
+    assert_eq!(
+        values,
+        Ok(vec![
+            "foo@6380".into(),
+            "bar@6379".into(),
+            "qux@6380".into()
+        ])
+    );
+    assert_eq!(
+        sent(),
+        [
+            (6379, "MGET bar".to_string()),
+            (6380, "MGET foo".to_string()),
+            (6380, "MGET qux".to_string())
+        ]
+    );
+
+    let set = runtime.block_on(
+        cmd("MSET")
+            .arg(&["foo", "1", "bar", "2"])
+            .query_async::<_, ()>(&mut connection),
+    );
+    assert_eq!(set, Ok(()));
+    assert_eq!(
+        sent(),
+        [
+            (6379, "MSET bar 2".to_string()),
+            (6380, "MSET foo 1".to_string())
+        ]
+    );
+
+    let deleted = runtime.block_on(
+        cmd("UNLINK")
+            .arg(&["foo", "bar", "qux"])
+            .query_async::<_, i64>(&mut connection),
+    );
+    assert_eq!(deleted, Ok(3));
+    assert_eq!(sent().len(), 3);
+
+    // Keys sharing a hash tag are sent as one command
+    let deleted = runtime.block_on(
+        cmd("DEL")
+            .arg(&["{foo}a", "{foo}b"])
+            .query_async::<_, i64>(&mut connection),
+    );
+    assert_eq!(deleted, Ok(2));
+    assert_eq!(sent(), [(6380, "DEL {foo}a {foo}b".to_string())]);
+
+    // `query_with_info` splits them the same way, with no single node to report
+    let (deleted, info) = runtime
+        .block_on(connection.query_with_info::<i64>(cmd("DEL").arg(&["foo", "bar"])))
+        .unwrap();
+    assert_eq!(deleted, 2);
+    assert_eq!(info.node, "");
+    assert_eq!(sent().len(), 2);
+}
+
+#[test]
+fn cluster_pipeline() {
+    let _ = env_logger::try_init();
+    let name = "cluster_pipeline";
+
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let moved = Arc::new(atomic::AtomicBool::new(false));
+    let MockEnv {
+        runtime,
+        connection,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, {
+        let sent = sent.clone();
+        let moved = moved.clone();
+        move |cmd: &[u8], port| {
+            if contains_slice(cmd, b"SLOTS") {
+                if moved.load(atomic::Ordering::SeqCst) {
+                    return Err(Ok(slots_reply(
+                        name,
+                        &[
+                            (0, 8191, 6379, &[]),
+                            (8192, 12181, 6380, &[]),
+                            (12182, 12182, 6379, &[]),
+                            (12183, 16383, 6380, &[]),
+                        ],
+                    )));
+                }
+                return Err(Ok(slots_reply(
+                    name,
+                    &[(0, 8191, 6379, &[]), (8192, 16383, 6380, &[])],
+                )));
+            }
+            respond_startup(name, cmd)?;
+            let args: Vec<String> =
+                redis::from_redis_value(&parse_redis_value(cmd).unwrap()).unwrap();
+            sent.lock().unwrap().push((port, args[1].clone()));
+            // The slot of `foo` moved to 6379
+            if port == 6380 && args[1] == "foo" {
+                moved.store(true, atomic::Ordering::SeqCst);
+                return Err(parse_redis_value(
+                    format!("-MOVED 12182 {}:6379\r\n", name).as_bytes(),
+                ));
+            }
+            Err(Ok(Value::Data(
+                format!("{}@{}", args[1], port).into_bytes(),
+            )))
+        }
+    });
+
+    // `bar` is served by 6379, `foo` and `qux` by 6380
+    let mut pipeline = redis::pipe();
+    pipeline.get("foo").get("bar").get("qux");
+    let values = runtime.block_on(connection.cluster_pipeline(&pipeline));
+    assert_eq!(
+        values,
+        Ok(vec![
+            Value::Data(b"foo@6379".to_vec()),
+            Value::Data(b"bar@6379".to_vec()),
+            Value::Data(b"qux@6380".to_vec()),
+        ])
+    );
+    // Only the commands of the redirected slot were sent again
+    let mut sent = sent.lock().unwrap().drain(..).collect::<Vec<_>>();
+    sent.sort();
+    assert_eq!(
+        sent,
+        [
+            (6379, "bar".to_string()),
+            (6379, "foo".to_string()),
+            (6380, "foo".to_string()),
+            (6380, "qux".to_string()),
+        ]
+    );
+
+    assert!(runtime
+        .block_on(connection.cluster_pipeline(redis::pipe().atomic().get("foo")))
+        .is_err());
+
+    // A transaction sent with `query_async` is never split, whatever the slots of its keys
+    let values = runtime.block_on(
+        redis::pipe()
+            .atomic()
+            .get("bar")
+            .get("qux")
+            .query_async::<_, Vec<String>>(&mut connection.clone()),
+    );
+    assert_eq!(values, Ok(vec!["bar@6379".into(), "qux@6379".into()]));
+}
+
+#[test]
+fn fire_and_forget() {
+    let _ = env_logger::try_init();
+    let name = "fire_and_forget";
+
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let MockEnv {
+        runtime,
+        connection,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, {
+        let sent = sent.clone();
+        move |cmd: &[u8], port| {
+            if contains_slice(cmd, b"SLOTS") {
+                return Err(Ok(slots_reply(
+                    name,
+                    &[(0, 8191, 6379, &[]), (8192, 16383, 6380, &[])],
+                )));
+            }
+            respond_startup(name, cmd)?;
+            let args: Vec<String> =
+                redis::from_redis_value(&parse_redis_value(cmd).unwrap()).unwrap();
+            sent.lock().unwrap().push((port, args.join(" ")));
+            Err(Ok(Value::Okay))
+        }
+    });
+
+    // `bar` is served by 6379, `foo` and `{foo}n` by 6380. `FLUSHALL` has no key and goes with
+    // the slot of the first command.
+    let mut pipeline = redis::pipe();
+    pipeline
+        .set("foo", 1)
+        .set("bar", 2)
+        .cmd("FLUSHALL")
+        .incr("{foo}n", 3);
+    assert_eq!(
+        runtime.block_on(connection.fire_and_forget(&pipeline)),
+        Ok(())
+    );
+    let sent = sent.lock().unwrap().drain(..).collect::<Vec<_>>();
+    // The parts sent to `port`, each from its `CLIENT REPLY OFF` to its `CLIENT REPLY ON`
+    let on = |port| {
+        let mut parts = Vec::new();
+        for (_, cmd) in sent.iter().filter(|(sent_to, _)| *sent_to == port) {
+            if cmd == "CLIENT REPLY OFF" {
+                parts.push(Vec::new());
+            }
+            parts.last_mut().unwrap().push(cmd.as_str());
+        }
+        parts.sort();
+        parts
+    };
+    assert_eq!(
+        on(6379),
+        [["CLIENT REPLY OFF", "SET bar 2", "CLIENT REPLY ON"]]
+    );
+    assert_eq!(
+        on(6380),
+        [
+            vec!["CLIENT REPLY OFF", "FLUSHALL", "CLIENT REPLY ON"],
+            vec![
+                "CLIENT REPLY OFF",
+                "SET foo 1",
+                "INCRBY {foo}n 3",
+                "CLIENT REPLY ON"
+            ],
+        ]
+    );
+}
+
+// A clock which completes every sleep immediately while recording the requested durations
+#[derive(Clone, Default)]
+struct RecordingClock {
+    sleeps: Arc<Mutex<Vec<Duration>>>,
+}
+
+impl Clock for RecordingClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        self.sleeps.lock().unwrap().push(duration);
+        Box::pin(future::ready(()))
+    }
+}
+
+// A clock whose every reading is an hour after the previous one, and which completes every sleep
+// immediately
+struct SteppingClock {
+    start: Instant,
+    readings: atomic::AtomicU32,
+}
+
+impl Default for SteppingClock {
+    fn default() -> Self {
+        SteppingClock {
+            start: Instant::now(),
+            readings: atomic::AtomicU32::new(0),
+        }
+    }
+}
+
+impl Clock for SteppingClock {
+    fn now(&self) -> Instant {
+        let readings = self.readings.fetch_add(1, atomic::Ordering::SeqCst);
+        self.start + Duration::from_secs(3600) * readings
+    }
+
+    fn sleep(&self, _: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(future::ready(()))
+    }
+}
+
+#[test]
+fn tryagain_backoff_uses_clock() {
+    let _ = env_logger::try_init();
+    let name = "tryagain_backoff_uses_clock";
+
+    let requests = atomic::AtomicUsize::new(0);
+    let MockEnv {
+        runtime,
+        mut client,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], _| {
+        respond_startup(name, cmd)?;
+
+        match requests.fetch_add(1, atomic::Ordering::SeqCst) {
+            0..=1 => Err(parse_redis_value(b"-TRYAGAIN mock\r\n")),
+            _ => Err(Ok(Value::Data(b"123".to_vec()))),
+        }
+    });
+
+    let clock = RecordingClock::default();
+    let mut connection = runtime
+        .block_on(
+            client
+                .set_clock(clock.clone())
+                .get_generic_connection::<MockConnection>(),
+        )
+        .unwrap();
+
+    let value = runtime.block_on(
+        cmd("GET")
+            .arg("test")
+            .query_async::<_, Option<i32>>(&mut connection),
+    );
+
+    assert_eq!(value, Ok(Some(123)));
+    assert_eq!(
+        *clock.sleeps.lock().unwrap(),
+        vec![Duration::from_millis(1280); 2]
+    );
+}
+
+#[test]
+fn retry_config() {
+    let _ = env_logger::try_init();
+    let name = "retry_config";
+
+    let requests = Arc::new(atomic::AtomicUsize::new(0));
+    let MockEnv {
+        runtime,
+        mut client,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, {
+        let requests = requests.clone();
+        move |cmd: &[u8], _| {
+            respond_startup(name, cmd)?;
+            requests.fetch_add(1, atomic::Ordering::SeqCst);
+            if contains_slice(cmd, b"moving") {
+                return Err(parse_redis_value(
+                    format!("-MOVED 6918 {}:6380\r\n", name).as_bytes(),
+                ));
+            }
+            Err(parse_redis_value(b"-TRYAGAIN mock\r\n"))
+        }
+    });
+
+    let clock = RecordingClock::default();
+    let mut connection = runtime
+        .block_on(
+            client
+                .set_clock(clock.clone())
+                .set_retry_config(
+                    RetryConfig::new()
+                        .max_retries(Some(2))
+                        .max_redirects(Some(1))
+                        .base_delay(Duration::from_millis(100))
+                        .min_delay(Duration::ZERO)
+                        .max_delay(Duration::from_millis(300)),
+                )
+                .get_generic_connection::<MockConnection>(),
+        )
+        .unwrap();
+
+    let result = runtime.block_on(
+        cmd("GET")
+            .arg("test")
+            .query_async::<_, Option<i32>>(&mut connection),
+    );
+    assert!(result.is_err());
+    assert_eq!(requests.swap(0, atomic::Ordering::SeqCst), 3);
+    assert_eq!(
+        *clock.sleeps.lock().unwrap(),
+        [Duration::from_millis(200), Duration::from_millis(300)]
+    );
+
+    // The second redirection exceeds the budget
+    let err = runtime
+        .block_on(
+            cmd("GET")
+                .arg("moving")
+                .query_async::<_, Option<i32>>(&mut connection),
+        )
+        .unwrap_err();
+    assert_eq!(err.kind(), redis::ErrorKind::ClientError);
+    assert!(err.to_string().starts_with("Too many redirections"));
+    assert_eq!(requests.load(atomic::Ordering::SeqCst), 2);
+}
+
+#[cfg(feature = "fault-injection")]
+#[test]
+fn injected_moved_triggers_refresh() {
+    use redis_cluster_async::fault::{self, Fault, FaultRule, FaultyConnection};
+
+    let _ = env_logger::try_init();
+    let name = "injected_moved_triggers_refresh";
+
+    let slot_requests = Arc::new(atomic::AtomicUsize::new(0));
+    let MockEnv {
+        runtime,
+        client,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, {
+        let slot_requests = slot_requests.clone();
+        move |cmd: &[u8], _| {
+            if contains_slice(cmd, b"SLOTS") {
+                slot_requests.fetch_add(1, atomic::Ordering::SeqCst);
+            }
+            respond_startup(name, cmd)?;
+            Err(Ok(Value::Data(b"123".to_vec())))
+        }
+    });
+
+    let mut connection = runtime
+        .block_on(client.get_generic_connection::<FaultyConnection<MockConnection>>())
+        .unwrap();
+    let slot_requests_after_startup = slot_requests.load(atomic::Ordering::SeqCst);
+
+    fault::inject(
+        FaultRule::new(Fault::Moved {
+            slot: 123,
+            node: format!("{}:6379", name),
+        })
+        .node(format!("{}:6379", name))
+        .command("GET")
+        .times(1),
+    );
+
+    let value = runtime.block_on(
+        cmd("GET")
+            .arg("test")
+            .query_async::<_, Option<i32>>(&mut connection),
+    );
+
+    assert_eq!(value, Ok(Some(123)));
+    assert_eq!(
+        slot_requests.load(atomic::Ordering::SeqCst),
+        slot_requests_after_startup + 1
+    );
+}
+
+#[test]
+fn many_concurrent_requests() {
+    let _ = env_logger::try_init();
+    let name = "many_concurrent_requests";
+
+    let MockEnv {
+        runtime,
+        connection,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], _| {
+        respond_startup(name, cmd)?;
+        Err(Ok(Value::Data(b"123".to_vec())))
+    });
+
+    let values = runtime.block_on(future::join_all((0..500).map(|i| {
+        let mut connection = connection.clone();
+        async move {
+            cmd("GET")
+                .arg(i)
+                .query_async::<_, Option<i32>>(&mut connection)
+                .await
+        }
+    })));
+
+    assert!(values.into_iter().all(|value| value == Ok(Some(123))));
+}
+
+#[test]
+fn startup_skips_failing_seed() {
+    let _ = env_logger::try_init();
+    let name = "startup_skips_failing_seed";
+    let failing = "startup_skips_failing_seed_failing";
+
+    let MockEnv {
+        runtime,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], _| {
+        respond_startup(name, cmd)?;
+        Err(Ok(Value::Data(b"123".to_vec())))
+    });
+    let _failing_handler = RemoveHandler(failing.to_string());
+    HANDLERS.write().unwrap().insert(
+        failing.to_string(),
+        Arc::new(|cmd, _| {
+            if contains_slice(&cmd.get_packed_command(), b"PING") {
+                Err(Ok(Value::Status("OK".into())))
+            } else {
+                Err(parse_redis_value(b"-CLUSTERDOWN mock\r\n"))
+            }
+        }),
+    );
+
+    let client = Client::open(vec![
+        format!("redis://{}", failing),
+        format!("redis://{}", name),
+    ])
+    .unwrap();
+    let mut connection = runtime
+        .block_on(client.get_generic_connection::<MockConnection>())
+        .unwrap();
+
+    let value = runtime.block_on(
+        cmd("GET")
+            .arg("test")
+            .query_async::<_, Option<i32>>(&mut connection),
+    );
+
+    assert_eq!(value, Ok(Some(123)));
+}
+
+#[test]
+fn startup_policy() {
+    let _ = env_logger::try_init();
+    let name = "startup_policy";
+
+    let MockEnv {
+        runtime,
+        mut client,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], port| {
+        if contains_slice(cmd, b"PING") {
+            // The second master is unreachable
+            return if port == 6380 {
+                Err(Err((redis::ErrorKind::IoError, "mock").into()))
+            } else {
+                Err(Ok(Value::Status("OK".into())))
+            };
+        }
+        if contains_slice(cmd, b"SLOTS") {
+            return Err(Ok(slots_reply(
+                name,
+                &[(0, 8191, 6379, &[]), (8192, 16383, 6380, &[])],
+            )));
+        }
+        Err(Ok(Value::Nil))
+    });
+
+    client.set_startup_policy(StartupPolicy::MinMasters(1));
+    assert!(runtime
+        .block_on(client.get_generic_connection::<MockConnection>())
+        .is_ok());
+
+    client.set_startup_policy(StartupPolicy::AllMasters);
+    let err = runtime
+        .block_on(client.get_generic_connection::<MockConnection>())
+        .map(|_| ())
+        .unwrap_err();
+    assert_eq!(
+        err.detail(),
+        Some("connected to 1 of 2 masters, 2 required")
+    );
+}
+
+#[test]
+fn error_callback() {
+    let _ = env_logger::try_init();
+    let name = "error_callback";
+
+    let MockEnv {
+        runtime,
+        mut client,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], port| {
+        if contains_slice(cmd, b"PING") {
+            return if port == 6380 {
+                Err(Err((redis::ErrorKind::IoError, "mock").into()))
+            } else {
+                Err(Ok(Value::Status("OK".into())))
+            };
+        }
+        if contains_slice(cmd, b"SLOTS") {
+            return Err(Ok(slots_reply(
+                name,
+                &[(0, 8191, 6379, &[]), (8192, 16383, 6380, &[])],
+            )));
+        }
+        respond_startup(name, cmd)
+    });
+
+    let errors = Arc::new(Mutex::new(Vec::new()));
+    let reported = errors.clone();
+    client
+        .set_startup_policy(StartupPolicy::MinMasters(1))
+        .set_error_callback(move |error| {
+            if let BackgroundError::Connect { node, .. } = error {
+                reported.lock().unwrap().push(node.to_string());
+            }
+        });
+    runtime
+        .block_on(client.get_generic_connection::<MockConnection>())
+        .unwrap();
+
+    assert_eq!(*errors.lock().unwrap(), vec![format!("{}:6380", name)]);
+}
+
+#[test]
+fn lost_connections_are_reported() {
+    let _ = env_logger::try_init();
+    let name = "lost_connections_are_reported";
+
+    let broken = Arc::new(atomic::AtomicBool::new(false));
+    let broken2 = broken.clone();
+    let MockEnv {
+        runtime,
+        mut client,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], _| {
+        // Only the check of the existing connection fails, not the one of the new connection
+        if contains_slice(cmd, b"PING") && broken2.swap(false, atomic::Ordering::SeqCst) {
+            return Err(Err((redis::ErrorKind::IoError, "mock").into()));
+        }
+        respond_startup(name, cmd)
+    });
+
+    let errors = Arc::new(Mutex::new(Vec::new()));
+    let reported = errors.clone();
+    client.set_error_callback(move |error| {
+        if let BackgroundError::ConnectionLost { node, error } = error {
+            assert_eq!(error.kind(), redis::ErrorKind::IoError);
+            reported.lock().unwrap().push(node.to_string());
+        }
+    });
+    let connection = runtime
+        .block_on(client.get_generic_connection::<MockConnection>())
+        .unwrap();
+    assert!(errors.lock().unwrap().is_empty());
+
+    broken.store(true, atomic::Ordering::SeqCst);
+    runtime.block_on(connection.refresh_slots()).unwrap();
+    assert_eq!(*errors.lock().unwrap(), vec![format!("{}:6379", name)]);
+}
+
+#[test]
+fn malformed_slots_are_an_error() {
+    let _ = env_logger::try_init();
+    let name = "malformed_slots_are_an_error";
+
+    let slots = |end: i64, port: i64| slots_reply(name, &[(0, end, port, &[])]);
+    let reply = Arc::new(Mutex::new(slots(16383, 6379)));
+    let MockEnv {
+        runtime,
+        client,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, {
+        let reply = reply.clone();
+        move |cmd: &[u8], _| {
+            if contains_slice(cmd, b"SLOTS") {
+                return Err(Ok(reply.lock().unwrap().clone()));
+            }
+            respond_startup(name, cmd)
+        }
+    });
+
+    let connect = |value| {
+        *reply.lock().unwrap() = value;
+        runtime
+            .block_on(client.get_generic_connection::<MockConnection>())
+            .map(|_| ())
+            .unwrap_err()
+    };
+
+    let err = connect(slots(65535, 6379));
+    assert_eq!(err.kind(), redis::ErrorKind::ResponseError);
+    assert_eq!(err.detail(), Some("Received invalid slot range 0..65535"));
+
+    let err = connect(slots(16383, 70000));
+    assert_eq!(
+        err.detail(),
+        Some(&*format!("Received invalid port 70000 for node {}", name))
+    );
+}
+
+#[test]
+fn refresh_falls_back_to_seeds() {
+    let _ = env_logger::try_init();
+    let name = "refresh_falls_back_to_seeds";
+
+    let slot_response = move |port| slots_reply(name, &[(0, 16383, port, &[])]);
+    let seed_slot_requests = atomic::AtomicUsize::new(0);
+    let MockEnv {
+        runtime,
+        mut connection,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], port| {
+        if !contains_slice(cmd, b"SLOTS") {
+            respond_startup(name, cmd)?;
+        }
+        match port {
+            // The seed first points at 6380 and at 6381 once 6380 has been replaced
+            6379 if contains_slice(cmd, b"SLOTS") => {
+                match seed_slot_requests.fetch_add(1, atomic::Ordering::SeqCst) {
+                    0 => Err(Ok(slot_response(6380))),
+                    _ => Err(Ok(slot_response(6381))),
+                }
+            }
+            6380 if contains_slice(cmd, b"SLOTS") => {
+                Err(Err((redis::ErrorKind::IoError, "mock").into()))
+            }
+            6380 => Err(parse_redis_value(b"-MOVED 123\r\n")),
+            6381 => Err(Ok(Value::Data(b"123".to_vec()))),
+            _ => panic!("Unexpected request on port {}", port),
+        }
+    });
+
+    let value = runtime.block_on(
+        cmd("GET")
+            .arg("test")
+            .query_async::<_, Option<i32>>(&mut connection),
+    );
+
+    assert_eq!(value, Ok(Some(123)));
+}
+
+#[test]
+fn node_counts() {
+    let _ = env_logger::try_init();
+    let name = "node_counts";
+
+    let MockEnv {
+        connection,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], port| {
+        if contains_slice(cmd, b"PING") {
+            // The second master is unreachable
+            return if port == 6380 {
+                Err(Err((redis::ErrorKind::IoError, "mock").into()))
+            } else {
+                Err(Ok(Value::Status("OK".into())))
+            };
+        }
+        if contains_slice(cmd, b"SLOTS") {
+            return Err(Ok(slots_reply(
+                name,
+                &[(0, 8191, 6379, &[6381]), (8192, 16383, 6380, &[6382])],
+            )));
+        }
+        Err(Ok(Value::Nil))
+    });
+
+    assert_eq!(
+        connection.node_counts(),
+        NodeCounts {
+            known: 4,
+            connected: 1,
+            masters: 2,
+            replicas: 2,
+        }
+    );
+}
+
+#[test]
+fn removed_nodes_are_disconnected() {
+    let _ = env_logger::try_init();
+    let name = "removed_nodes_are_disconnected";
+
+    let shrunk = Arc::new(atomic::AtomicBool::new(false));
+    let shrunk2 = shrunk.clone();
+    let MockEnv {
+        runtime,
+        client,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], port| {
+        let shrunk = shrunk2.load(atomic::Ordering::SeqCst);
+        if contains_slice(cmd, b"SLOTS") {
+            // The master at 6380 leaves the cluster, its slots move to 6379
+            return Err(Ok(if shrunk {
+                slots_reply(name, &[(0, 16383, 6379, &[6381])])
+            } else {
+                slots_reply(name, &[(0, 8191, 6379, &[6381]), (8192, 16383, 6380, &[])])
+            }));
+        }
+        respond_startup(name, cmd)?;
+        if contains_slice(cmd, b"READONLY") {
+            return Err(Ok(Value::Okay));
+        }
+        if shrunk && port == 6380 {
+            return Err(parse_redis_value(
+                format!("-MOVED 12182 {}:6379\r\n", name).as_bytes(),
+            ));
+        }
+        Err(Ok(Value::Int(port.into())))
+    });
+    let mut connection = runtime
+        .block_on(client.get_generic_connection_with::<MockConnection>(
+            ConnectionConfig::new().read_from_replicas(true),
+        ))
+        .unwrap();
+
+    let port = runtime.block_on(cmd("GET").arg("bar").query_async::<_, u16>(&mut connection));
+    assert_eq!(port, Ok(6381));
+    let port = runtime.block_on(cmd("GET").arg("foo").query_async::<_, u16>(&mut connection));
+    assert_eq!(port, Ok(6380));
+    assert_eq!(connection.node_counts().connected, 3);
+
+    shrunk.store(true, atomic::Ordering::SeqCst);
+    let port = runtime.block_on(
+        cmd("SET")
+            .arg("foo")
+            .arg(1)
+            .query_async::<_, u16>(&mut connection),
+    );
+    assert_eq!(port, Ok(6379));
+    runtime.block_on(async {
+        while connection.node_counts().known != 2 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    });
+
+    // The replica keeps its connection, the removed master loses it
+    let health = connection.health();
+    assert_eq!(
+        health
+            .iter()
+            .map(|node| (node.addr.clone(), node.connected))
+            .collect::<Vec<_>>(),
+        [
+            (format!("{}:6379", name), true),
+            (format!("{}:6381", name), true)
+        ]
+    );
+    assert_eq!(connection.node_counts().connected, 2);
+}
+
+#[test]
+fn health() {
+    let _ = env_logger::try_init();
+    let name = "health";
+
+    let MockEnv {
+        runtime,
+        mut connection,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], _| {
+        if contains_slice(cmd, b"SLOTS") {
+            return Err(Ok(slots_reply(name, &[(0, 16383, 6379, &[6380])])));
+        }
+        respond_startup(name, cmd)?;
+        if contains_slice(cmd, b"GET") {
+            return Err(Ok(Value::Data(b"123".to_vec())));
+        }
+        Err(parse_redis_value(b"-ERR mock\r\n"))
+    });
+
+    runtime
+        .block_on(
+            cmd("GET")
+                .arg("test")
+                .query_async::<_, i32>(&mut connection),
+        )
+        .unwrap();
+    runtime
+        .block_on(
+            cmd("SET")
+                .arg("test")
+                .arg(1)
+                .query_async::<_, ()>(&mut connection),
+        )
+        .unwrap_err();
+
+    let health = connection.health();
+    assert_eq!(health.len(), 2);
+
+    let master = &health[0];
+    assert_eq!(master.addr, format!("{}:6379", name));
+    assert_eq!(master.role, NodeRole::Master);
+    assert!(master.connected);
+    assert!(master.last_success.is_some());
+    assert!(master.latency.is_some());
+    assert!(master.last_error.as_deref().unwrap().contains("mock"));
+
+    let replica = &health[1];
+    assert_eq!(replica.addr, format!("{}:6380", name));
+    assert_eq!(replica.role, NodeRole::Replica);
+    assert!(!replica.connected);
+    assert_eq!(replica.last_success, None);
+}
+
+#[cfg(feature = "fault-injection")]
+#[test]
+fn fault_delay_uses_clock() {
+    use redis_cluster_async::fault::{self, Fault, FaultRule, FaultyConnection};
+
+    let _ = env_logger::try_init();
+    let name = "fault_delay_uses_clock";
+
+    let MockEnv {
+        runtime,
+        mut client,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], _| {
+        respond_startup(name, cmd)?;
+        Err(Ok(Value::Data(b"123".to_vec())))
+    });
+
+    let clock = RecordingClock::default();
+    let mut connection = runtime
+        .block_on(
+            client
+                .set_clock(clock.clone())
+                .get_generic_connection::<FaultyConnection<MockConnection>>(),
+        )
+        .unwrap();
+    fault::inject(
+        FaultRule::new(Fault::Delay(Duration::from_secs(3600)))
+            .node(format!("{}:6379", name))
+            .command("GET")
+            .times(1),
+    );
+    let value = runtime.block_on(
+        cmd("GET")
+            .arg("test")
+            .query_async::<_, i32>(&mut connection),
+    );
+    assert_eq!(value, Ok(123));
+    assert!(clock
+        .sleeps
+        .lock()
+        .unwrap()
+        .contains(&Duration::from_secs(3600)));
+}
+
+#[cfg(feature = "fault-injection")]
+#[test]
+fn drop_releases_node_connections() {
+    use redis_cluster_async::fault::{self, Fault, FaultRule, FaultyConnection};
+
+    let _ = env_logger::try_init();
+    let name = "drop_releases_node_connections";
+
+    let MockEnv {
+        runtime,
+        client,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], _| {
+        respond_startup(name, cmd)?;
+        Err(Ok(Value::Data(b"123".to_vec())))
+    });
+    let handler = HANDLERS.read().unwrap()[name].clone();
+    let node_connections = || Arc::strong_count(&handler) - 2;
+
+    let connection = runtime
+        .block_on(client.get_generic_connection::<FaultyConnection<MockConnection>>())
+        .unwrap();
+    assert!(node_connections() > 0);
+
+    // A request stuck on a slow node must not keep the node connections alive
+    fault::inject(
+        FaultRule::new(Fault::Delay(Duration::from_secs(3600)))
+            .node(format!("{}:6379", name))
+            .command("GET"),
+    );
+    let request = runtime.spawn({
+        let mut connection = connection.clone();
+        async move {
+            cmd("GET")
+                .arg("test")
+                .query_async::<_, i32>(&mut connection)
+                .await
+        }
+    });
+    runtime.block_on(async { tokio::time::sleep(Duration::from_millis(10)).await });
+    request.abort();
+    drop(connection);
+    runtime.block_on(async { tokio::time::sleep(Duration::from_millis(10)).await });
+
+    assert_eq!(node_connections(), 0);
+}
+
+#[test]
+fn quit_on_drop() {
+    let _ = env_logger::try_init();
+    let name = "quit_on_drop";
+
+    let quits = Arc::new(atomic::AtomicUsize::new(0));
+    let MockEnv {
+        runtime,
+        connection,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, {
+        let quits = quits.clone();
+        move |cmd: &[u8], _| {
+            if contains_slice(cmd, b"QUIT") {
+                quits.fetch_add(1, atomic::Ordering::SeqCst);
+            }
+            respond_startup(name, cmd)?;
+            Err(Ok(Value::Okay))
+        }
+    });
+
+    drop(connection);
+    runtime.block_on(async { tokio::time::sleep(Duration::from_millis(10)).await });
+
+    assert_eq!(quits.load(atomic::Ordering::SeqCst), 1);
+}
+
+#[test]
+fn client_no_evict_and_no_touch() {
+    let _ = env_logger::try_init();
+    let name = "client_no_evict_and_no_touch";
+
+    let flags = Arc::new(Mutex::new(Vec::new()));
+    let MockEnv {
+        runtime,
+        mut client,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, {
+        let flags = flags.clone();
+        move |cmd: &[u8], _| {
+            respond_startup(name, cmd)?;
+            for flag in ["NO-EVICT", "NO-TOUCH"] {
+                if contains_slice(cmd, flag.as_bytes()) {
+                    flags.lock().unwrap().push(flag);
+                }
+            }
+            Err(Ok(Value::Okay))
+        }
+    });
+    flags.lock().unwrap().clear();
+
+    runtime
+        .block_on(
+            client
+                .set_client_no_evict(true)
+                .set_client_no_touch(true)
+                .get_generic_connection::<MockConnection>(),
+        )
+        .unwrap();
+
+    assert_eq!(*flags.lock().unwrap(), ["NO-EVICT", "NO-TOUCH"]);
+}
+
+#[cfg(feature = "fault-injection")]
+#[test]
+fn replay_idempotent_after_connection_dropped() {
+    use redis_cluster_async::fault::{self, Fault, FaultRule, FaultyConnection};
+
+    let _ = env_logger::try_init();
+    let name = "replay_idempotent_after_connection_dropped";
+
+    let pings = Arc::new(atomic::AtomicUsize::new(0));
+    let MockEnv {
+        runtime,
+        client,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, {
+        let pings = pings.clone();
+        move |cmd: &[u8], _| {
+            if contains_slice(cmd, b"PING") {
+                pings.fetch_add(1, atomic::Ordering::SeqCst);
+            }
+            respond_startup(name, cmd)?;
+            Err(Ok(Value::Int(123)))
+        }
+    });
+
+    let mut connection = runtime
+        .block_on(client.get_generic_connection::<FaultyConnection<MockConnection>>())
+        .unwrap();
+    let pings_after_startup = pings.load(atomic::Ordering::SeqCst);
+
+    for command in ["GET", "INCR"] {
+        fault::inject(
+            FaultRule::new(Fault::ConnectionDropped)
+                .node(format!("{}:6379", name))
+                .command(command)
+                .times(1),
+        );
+    }
+
+    // Reads are sent again on a new connection
+    let value = runtime.block_on(
+        cmd("GET")
+            .arg("test")
+            .query_async::<_, i32>(&mut connection),
+    );
+    assert_eq!(value, Ok(123));
+    assert_eq!(
+        pings.load(atomic::Ordering::SeqCst),
+        pings_after_startup + 1
+    );
+
+    // Writes might have been applied already so the error is returned
+    let value = runtime.block_on(
+        cmd("INCR")
+            .arg("test")
+            .query_async::<_, i32>(&mut connection),
+    );
+    assert!(value.unwrap_err().is_connection_dropped());
+}
+
+#[cfg(feature = "fault-injection")]
+#[test]
+fn replay_blind_writes_when_enabled() {
+    use redis_cluster_async::fault::{self, Fault, FaultRule, FaultyConnection};
+
+    let _ = env_logger::try_init();
+    let name = "replay_blind_writes_when_enabled";
+
+    let MockEnv {
+        runtime,
+        mut client,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], _| {
+        respond_startup(name, cmd)?;
+        Err(Ok(Value::Okay))
+    });
+
+    for retry_blind_writes in [false, true] {
+        let mut connection = runtime
+            .block_on(
+                client
+                    .set_retry_blind_writes(retry_blind_writes)
+                    .get_generic_connection::<FaultyConnection<MockConnection>>(),
+            )
+            .unwrap();
+        fault::inject(
+            FaultRule::new(Fault::ConnectionDropped)
+                .node(format!("{}:6379", name))
+                .command("SET")
+                .times(1),
+        );
+
+        let result = runtime.block_on(
+            cmd("SET")
+                .arg("test")
+                .arg(1)
+                .query_async::<_, ()>(&mut connection),
+        );
+        assert_eq!(result.is_ok(), retry_blind_writes);
+    }
+}
+
+#[cfg(feature = "fault-injection")]
+#[test]
+fn retryable_overrides_classification() {
+    use redis_cluster_async::fault::{self, Fault, FaultRule, FaultyConnection};
+
+    let _ = env_logger::try_init();
+    let name = "retryable_overrides_classification";
+
+    let MockEnv {
+        runtime,
+        client,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], _| {
+        respond_startup(name, cmd)?;
+        Err(Ok(Value::Int(1)))
+    });
+    let connection = runtime
+        .block_on(client.get_generic_connection::<FaultyConnection<MockConnection>>())
+        .unwrap();
+
+    for (command, retryable) in [("INCR", true), ("GET", false)] {
+        fault::inject(
+            FaultRule::new(Fault::ConnectionDropped)
+                .node(format!("{}:6379", name))
+                .command(command)
+                .times(1),
+        );
+        let result = runtime.block_on(
+            cmd(command)
+                .arg("test")
+                .query_async::<_, i32>(&mut connection.retryable(retryable)),
+        );
+        assert_eq!(result.is_ok(), retryable, "{}", command);
+    }
+}
+
+#[test]
+fn with_routing_key() {
+    let _ = env_logger::try_init();
+    let name = "with_routing_key";
+
+    let MockEnv {
+        runtime,
+        connection,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], port| {
+        if contains_slice(cmd, b"SLOTS") {
+            return Err(Ok(slots_reply(
+                name,
+                &[(0, 8191, 6379, &[]), (8192, 16383, 6380, &[])],
+            )));
+        }
+        respond_startup(name, cmd)?;
+        Err(Ok(Value::Int(port.into())))
+    });
+
+    // `bar` is served by the first node and `foo` by the second
+    let port = runtime.block_on(
+        cmd("MODULE.CMD")
+            .arg("bar")
+            .query_async::<_, u16>(&mut connection.with_routing_key("foo")),
+    );
+    assert_eq!(port, Ok(6380));
+
+    let port = runtime.block_on(
+        redis::pipe()
+            .cmd("MODULE.CMD")
+            .arg("bar")
+            .query_async::<_, (u16,)>(&mut connection.with_routing_key("foo")),
+    );
+    assert_eq!(port, Ok((6380,)));
+
+    let port = runtime.block_on(
+        cmd("GET")
+            .arg("bar")
+            .query_async::<_, u16>(&mut connection.with_slot(key_slot(b"foo"))),
+    );
+    assert_eq!(port, Ok(6380));
+}
+
+#[test]
+fn send_raw() {
+    let _ = env_logger::try_init();
+    let name = "send_raw";
+
+    let MockEnv {
+        runtime,
+        mut connection,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], port| {
+        if contains_slice(cmd, b"SLOTS") {
+            return Err(Ok(slots_reply(
+                name,
+                &[(0, 8191, 6379, &[6381]), (8192, 16383, 6380, &[])],
+            )));
+        }
+        respond_startup(name, cmd)?;
+        match port {
+            6380 => Err(parse_redis_value(b"-MOVED 123 send_raw:6379\r\n")),
+            _ => Err(Ok(Value::Int(port.into()))),
+        }
+    });
+    let echo = b"*2\r\n$4\r\nECHO\r\n$2\r\nhi\r\n";
+
+    // Replicas are connected on demand
+    let value = runtime.block_on(connection.send_raw(&format!("{}:6381", name), echo));
+    assert_eq!(value, Ok(Value::Int(6381)));
+
+    // Redirections are returned instead of followed
+    let err = runtime
+        .block_on(connection.send_raw(&format!("{}:6380", name), echo))
+        .unwrap_err();
+    assert_eq!(err.kind(), redis::ErrorKind::Moved);
+
+    let err = runtime
+        .block_on(connection.send_raw(&format!("{}:6379", name), b"+PING\r\n"))
+        .unwrap_err();
+    assert_eq!(err.kind(), redis::ErrorKind::ClientError);
+}
+
+#[test]
+fn config_get_and_set() {
+    let _ = env_logger::try_init();
+    let name = "config_get_and_set";
+
+    let MockEnv {
+        runtime,
+        connection,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], port| {
+        if contains_slice(cmd, b"SLOTS") {
+            return Err(Ok(slots_reply(
+                name,
+                &[(0, 8191, 6379, &[6381]), (8192, 16383, 6380, &[])],
+            )));
+        }
+        respond_startup(name, cmd)?;
+        if contains_slice(cmd, b"GET") {
+            return Err(Ok(Value::Bulk(vec![
+                Value::Data(b"maxmemory".to_vec()),
+                Value::Data(port.to_string().into_bytes()),
+            ])));
+        }
+        match port {
+            6380 => Err(parse_redis_value(b"-ERR mock\r\n")),
+            _ => Err(Ok(Value::Okay)),
+        }
+    });
+    let node = |port| format!("{}:{}", name, port);
+
+    let values = runtime.block_on(connection.config_get("maxmemory"));
+    assert_eq!(values.len(), 3);
+    for port in [6379, 6380, 6381] {
+        assert_eq!(
+            values[&node(port)].as_ref().unwrap()["maxmemory"],
+            port.to_string()
+        );
+    }
+
+    let results = runtime.block_on(connection.config_set("maxmemory", "1gb"));
+    assert_eq!(results.len(), 3);
+    assert!(results[&node(6379)].is_ok());
+    assert!(results[&node(6380)].is_err());
+    assert!(results[&node(6381)].is_ok());
+}
+
+#[test]
+fn execute_on_all_nodes() {
+    let _ = env_logger::try_init();
+    let name = "execute_on_all_nodes";
+
+    let MockEnv {
+        runtime,
+        connection,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], port| {
+        if contains_slice(cmd, b"SLOTS") {
+            return Err(Ok(slots_reply(
+                name,
+                &[(0, 8191, 6379, &[6381]), (8192, 16383, 6380, &[])],
+            )));
+        }
+        respond_startup(name, cmd)?;
+        Err(Ok(Value::Int(port.into())))
+    });
+    let dbsize = cmd("DBSIZE");
+    let nodes = |results: std::collections::HashMap<String, RedisResult<Value>>| {
+        let mut nodes = results
+            .into_iter()
+            .map(|(node, result)| {
+                assert_eq!(
+                    Ok(Value::Int(
+                        node.rsplit(':').next().unwrap().parse().unwrap()
+                    )),
+                    result
+                );
+                node
+            })
+            .collect::<Vec<_>>();
+        nodes.sort();
+        nodes
+    };
+    let node = |port| format!("{}:{}", name, port);
+
+    assert_eq!(
+        nodes(runtime.block_on(connection.execute_on_all_nodes(&dbsize))),
+        [node(6379), node(6380), node(6381)]
+    );
+    assert_eq!(
+        nodes(runtime.block_on(connection.execute_on_all_masters(&dbsize))),
+        [node(6379), node(6380)]
+    );
+    assert_eq!(
+        nodes(runtime.block_on(connection.execute_on_all_replicas(&dbsize))),
+        [node(6381)]
+    );
+}
+
+#[test]
+fn fan_out_helpers() {
+    let _ = env_logger::try_init();
+    let name = "fan_out_helpers";
+
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let MockEnv {
+        runtime,
+        connection,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, {
+        let sent = sent.clone();
+        move |cmd: &[u8], port| {
+            if contains_slice(cmd, b"SLOTS") {
+                return Err(Ok(slots_reply(
+                    name,
+                    &[(0, 8191, 6379, &[6381]), (8192, 16383, 6380, &[])],
+                )));
+            }
+            respond_startup(name, cmd)?;
+            let args: Vec<String> =
+                redis::from_redis_value(&parse_redis_value(cmd).unwrap()).unwrap();
+            sent.lock().unwrap().push((port, args[0].clone()));
+            match &args[0][..] {
+                "DBSIZE" => Err(Ok(Value::Int(port.into()))),
+                "SCRIPT" => Err(Ok(Value::Data(
+                    redis::Script::new("return 1")
+                        .get_hash()
+                        .as_bytes()
+                        .to_vec(),
+                ))),
+                _ => Err(Ok(Value::Okay)),
+            }
+        }
+    });
+    let commands = |command: &str| {
+        let mut ports = sent
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, sent)| sent == command)
+            .map(|(port, _)| *port)
+            .collect::<Vec<_>>();
+        ports.sort();
+        ports
+    };
+
+    assert_eq!(runtime.block_on(connection.dbsize()), Ok(6379 + 6380));
+    let flushed = runtime.block_on(connection.flushdb());
+    assert!(flushed.values().all(Result::is_ok));
+    assert_eq!(commands("FLUSHDB"), [6379, 6380]);
+    let loaded = runtime.block_on(connection.script_load(&redis::Script::new("return 1")));
+    assert_eq!(loaded.len(), 3);
+    assert!(loaded.values().all(Result::is_ok));
+    assert_eq!(commands("SCRIPT"), [6379, 6380, 6381]);
+}
+
+#[test]
+fn latency_helpers() {
+    let _ = env_logger::try_init();
+    let name = "latency_helpers";
+
+    let MockEnv {
+        runtime,
+        connection,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], _| {
+        respond_startup(name, cmd)?;
+        if contains_slice(cmd, b"LATEST") {
+            return Err(Ok(Value::Bulk(vec![Value::Bulk(vec![
+                Value::Data(b"command".to_vec()),
+                Value::Int(1_700_000_000),
+                Value::Int(12),
+                Value::Int(30),
+            ])])));
+        }
+        Err(Ok(Value::Bulk(vec![Value::Bulk(vec![
+            Value::Int(1_700_000_000),
+            Value::Int(12),
+        ])])))
+    });
+    let timestamp = std::time::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+    let events = runtime.block_on(connection.latency_latest()).concat();
+    assert_eq!(
+        events,
+        Ok(vec![LatencyEvent {
+            node: format!("{}:6379", name),
+            event: "command".into(),
+            timestamp,
+            latest: Duration::from_millis(12),
+            max: Duration::from_millis(30),
+        }])
+    );
+
+    let samples = runtime
+        .block_on(connection.latency_history("command"))
+        .concat();
+    assert_eq!(
+        samples,
+        Ok(vec![LatencySample {
+            node: format!("{}:6379", name),
+            timestamp,
+            latency: Duration::from_millis(12),
+        }])
+    );
+}
+
+#[test]
+fn ping() {
+    let _ = env_logger::try_init();
+    let name = "ping";
+
+    let MockEnv {
+        runtime,
+        mut client,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], port| {
+        if contains_slice(cmd, b"SLOTS") {
+            return Err(Ok(slots_reply(name, &[(0, 16383, 6379, &[6380])])));
+        }
+        // The replica is down
+        if port == 6380 {
+            return Err(Err((redis::ErrorKind::IoError, "mock").into()));
+        }
+        respond_startup(name, cmd)?;
+        Err(Ok(Value::Nil))
+    });
+    let connection = runtime
+        .block_on(
+            client
+                .set_clock(SteppingClock::default())
+                .get_generic_connection::<MockConnection>(),
+        )
+        .unwrap();
+
+    // The round trip is timed with the clock of the connection
+    let round_trip = runtime
+        .block_on(connection.ping(&format!("{}:6379", name)))
+        .unwrap();
+    assert!(round_trip >= Duration::from_secs(3600), "{:?}", round_trip);
+
+    let masters = runtime.block_on(connection.ping_masters());
+    assert_eq!(masters.len(), 1);
+    assert!(masters.all_ok().is_ok());
+
+    let nodes = runtime.block_on(connection.ping_all_nodes());
+    assert_eq!(nodes.len(), 2);
+    assert!(nodes[&format!("{}:6379", name)].is_ok());
+    assert!(nodes[&format!("{}:6380", name)].is_err());
+}
+
+#[test]
+fn node_ids() {
+    let _ = env_logger::try_init();
+    let name = "node_ids";
+
+    let MockEnv {
+        runtime,
+        connection,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], port| {
+        if contains_slice(cmd, b"SLOTS") {
+            return Err(Ok(slots_reply(name, &[(0, 16383, 6379, &[6380])])));
+        }
+        respond_startup(name, cmd)?;
+        if contains_slice(cmd, b"MYID") {
+            return Err(Ok(Value::Data(format!("id{}", port).into_bytes())));
+        }
+        Err(Ok(Value::Nil))
+    });
+
+    assert_eq!(
+        runtime.block_on(connection.my_id(&format!("{}:6379", name))),
+        Ok("id6379".to_string())
+    );
+    let ids = runtime.block_on(connection.node_ids()).all_ok().unwrap();
+    assert_eq!(ids.len(), 2);
+    assert_eq!(ids[&format!("{}:6380", name)], "id6380");
+    assert_eq!(
+        runtime.block_on(connection.node_by_id("id6380")),
+        Ok(Some(format!("{}:6380", name)))
+    );
+    assert_eq!(runtime.block_on(connection.node_by_id("other")), Ok(None));
+}
+
+#[test]
+fn client_pause() {
+    let _ = env_logger::try_init();
+    let name = "client_pause";
+
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let sent2 = sent.clone();
+    let broken = Arc::new(atomic::AtomicBool::new(false));
+    let broken2 = broken.clone();
+    let MockEnv {
+        runtime,
+        connection,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], port| {
+        if contains_slice(cmd, b"SLOTS") {
+            return Err(Ok(slots_reply(
+                name,
+                &[(0, 8191, 6379, &[]), (8192, 16383, 6380, &[])],
+            )));
+        }
+        respond_startup(name, cmd)?;
+        if contains_slice(cmd, b"PAUSE") {
+            let cmd = String::from_utf8_lossy(cmd).replace("\r\n", " ");
+            sent2.lock().unwrap().push((port, cmd));
+            if port == 6380 && broken2.load(atomic::Ordering::SeqCst) {
+                return Err(parse_redis_value(b"-ERR mock\r\n"));
+            }
+        }
+        Err(Ok(Value::Okay))
+    });
+    let sent = move || {
+        let mut sent = std::mem::take(&mut *sent.lock().unwrap());
+        sent.sort_by_key(|(port, _)| *port);
+        sent
+    };
+
+    runtime
+        .block_on(connection.client_pause(PauseMode::All, Duration::from_millis(1500)))
+        .unwrap();
+    let paused = sent();
+    assert_eq!(paused.len(), 2);
+    assert!(paused
+        .iter()
+        .all(|(_, cmd)| cmd.contains("1500") && cmd.contains("ALL")));
+    runtime.block_on(connection.client_unpause()).unwrap();
+    assert_eq!(sent().len(), 2);
+
+    // The master which was paused is unpaused again when another one fails
+    broken.store(true, atomic::Ordering::SeqCst);
+    let result =
+        runtime.block_on(connection.client_pause(PauseMode::Write, Duration::from_secs(1)));
+    assert!(result.is_err());
+    let sent = sent();
+    assert_eq!(sent.len(), 3);
+    assert_eq!(sent[0].0, 6379);
+    assert!(sent[0].1.contains("WRITE"));
+    assert_eq!(sent[1].0, 6379);
+    assert!(sent[1].1.contains("UNPAUSE"));
+}
+
+#[test]
+fn slowlog() {
+    let _ = env_logger::try_init();
+    let name = "slowlog";
+
+    let MockEnv {
+        runtime,
+        connection,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], port| {
+        if contains_slice(cmd, b"SLOTS") {
+            return Err(Ok(slots_reply(
+                name,
+                &[(0, 8191, 6379, &[]), (8192, 16383, 6380, &[])],
+            )));
+        }
+        respond_startup(name, cmd)?;
+        if contains_slice(cmd, b"RESET") {
+            return Err(Ok(Value::Okay));
+        }
+        let args = Value::Bulk(vec![
+            Value::Data(b"GET".to_vec()),
+            Value::Data(b"foo".to_vec()),
+        ]);
+        let entry = if port == 6379 {
+            vec![
+                Value::Int(1),
+                Value::Int(100),
+                Value::Int(1500),
+                args,
+                Value::Data(b"127.0.0.1:5000".to_vec()),
+                Value::Data(b"".to_vec()),
+            ]
+        } else {
+            vec![Value::Int(7), Value::Int(200), Value::Int(20), args]
+        };
+        Err(Ok(Value::Bulk(vec![Value::Bulk(entry)])))
+    });
+    let timestamp = |secs| std::time::UNIX_EPOCH + Duration::from_secs(secs);
+    let args = vec!["GET".to_string(), "foo".to_string()];
+
+    assert_eq!(
+        runtime.block_on(connection.slowlog_get(10)),
+        Ok(vec![
+            SlowlogEntry {
+                node: format!("{}:6380", name),
+                id: 7,
+                timestamp: timestamp(200),
+                duration: Duration::from_micros(20),
+                args: args.clone(),
+                client_addr: None,
+                client_name: None,
+            },
+            SlowlogEntry {
+                node: format!("{}:6379", name),
+                id: 1,
+                timestamp: timestamp(100),
+                duration: Duration::from_micros(1500),
+                args,
+                client_addr: Some("127.0.0.1:5000".into()),
+                client_name: None,
+            },
+        ])
+    );
+    assert_eq!(
+        runtime
+            .block_on(connection.slowlog_reset())
+            .all_ok()
+            .map(|nodes| nodes.len()),
+        Ok(2)
+    );
+}
+
+#[test]
+fn clock_skew() {
+    let _ = env_logger::try_init();
+    let name = "clock_skew";
+
+    let MockEnv {
+        runtime,
+        connection,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], _| {
+        respond_startup(name, cmd)?;
+        // The node is 5 seconds behind
+        let time = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            - Duration::from_secs(5);
+        Err(Ok(Value::Bulk(vec![
+            Value::Data(time.as_secs().to_string().into_bytes()),
+            Value::Data(time.subsec_micros().to_string().into_bytes()),
+        ])))
+    });
+
+    let skews = runtime.block_on(connection.clock_skew()).all_ok().unwrap();
+    let skew = &skews[&format!("{}:6379", name)];
+    let offset = skew.offset_micros();
+    assert!(
+        (-5_500_000..-4_500_000).contains(&offset),
+        "unexpected offset {}",
+        offset
+    );
+}
+
+#[test]
+fn feature_gating() {
+    let _ = env_logger::try_init();
+    let name = "feature_gating";
+
+    let queried = Arc::new(atomic::AtomicUsize::new(0));
+    let queried2 = queried.clone();
+    let MockEnv {
+        runtime,
+        mut client,
+        connection,
+        handler: _handler,
+    } = MockEnv::new(name, move |cmd: &[u8], _| {
+        if contains_slice(cmd, b"INFO") {
+            queried2.fetch_add(1, atomic::Ordering::SeqCst);
+            return Err(Ok(Value::Data(
+                b"# Server\r\nredis_version:7.0.11\r\n".to_vec(),
+            )));
+        }
+        respond_startup(name, cmd)?;
+        Err(Ok(Value::Okay))
+    });
+
+    let node = format!("{}:6379", name);
+    assert_eq!(
+        connection.node_versions().get(&node),
+        Some(&RedisVersion::new(7, 0, 11))
+    );
+    assert!(connection.check_feature(Feature::ClientNoEvict).is_ok());
+    let err = connection
+        .check_feature(Feature::ClientNoTouch)
+        .unwrap_err();
+    assert_eq!(err.kind(), redis::ErrorKind::ClientError);
+    assert!(err.to_string().contains(&node), "{}", err);
+
+    // The version is only queried by the first connection to the node
+    runtime
+        .block_on(client.get_generic_connection::<MockConnection>())
+        .unwrap();
+    assert_eq!(queried.load(atomic::Ordering::SeqCst), 1);
+
+    let result = runtime.block_on(
+        client
+            .set_client_no_touch(true)
+            .get_generic_connection::<MockConnection>(),
+    );
+    assert!(result.is_err());
+}
+
+#[test]
+fn remap_announced_addresses() {
+    let _ = env_logger::try_init();
+    let name = "remap_announced_addresses";
+
+    // The nodes announce ports 7000 and 7001 but are reachable on 6379 and 6380
+    let MockEnv {
+        runtime,
+        mut client,
+        mut connection,
+        handler: _handler,
+    } = MockEnv::new(name, move |cmd: &[u8], port| {
+        if contains_slice(cmd, b"SLOTS") {
+            return Err(Ok(slots_reply(
+                name,
+                &[(0, 8191, 7000, &[]), (8192, 16383, 7001, &[])],
+            )));
+        }
+        respond_startup(name, cmd)?;
+        Err(Ok(Value::Int(port.into())))
+    });
+    let get = cmd("GET").arg("foo").clone();
+
+    let value = runtime.block_on(get.query_async::<_, u16>(&mut connection));
+    assert_eq!(value, Ok(7001));
+
+    let mut connection = runtime
+        .block_on(
+            client
+                .set_remap_rules(vec![
+                    RemapRule::new().port(7000).to_port(6379),
+                    RemapRule::new().port(7001).to_port(6380),
+                ])
+                .get_generic_connection::<MockConnection>(),
+        )
+        .unwrap();
+    let value = runtime.block_on(get.query_async::<_, u16>(&mut connection));
+    assert_eq!(value, Ok(6380));
+}
+
+#[test]
+fn shared_topology_and_connections() {
+    let _ = env_logger::try_init();
+    let name = "shared_topology_and_connections";
+
+    let slots = Arc::new(atomic::AtomicUsize::new(0));
+    let pings = Arc::new(atomic::AtomicUsize::new(0));
+    let MockEnv {
+        runtime,
+        mut client,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, {
+        let (slots, pings) = (slots.clone(), pings.clone());
+        move |cmd: &[u8], _| {
+            if contains_slice(cmd, b"SLOTS") {
+                slots.fetch_add(1, atomic::Ordering::SeqCst);
+            } else if contains_slice(cmd, b"PING") {
+                pings.fetch_add(1, atomic::Ordering::SeqCst);
+            }
+            respond_startup(name, cmd)?;
+            Err(Ok(Value::Okay))
+        }
+    });
+    let connect = |client: &Client| {
+        runtime
+            .block_on(client.get_generic_connection::<MockConnection>())
+            .unwrap()
+    };
+
+    client.set_shared_topology(true);
+    let _first = connect(&client);
+    let discoveries = slots.load(atomic::Ordering::SeqCst);
+    let _second = connect(&client);
+    assert_eq!(slots.load(atomic::Ordering::SeqCst), discoveries);
+
+    client.set_shared_connections(true);
+    let _third = connect(&client);
+    let connects = pings.load(atomic::Ordering::SeqCst);
+    let _fourth = connect(&client.clone());
+    assert_eq!(pings.load(atomic::Ordering::SeqCst), connects);
+}
+
+#[test]
+fn max_reply_size() {
+    let _ = env_logger::try_init();
+    let name = "max_reply_size";
+
+    let MockEnv {
+        runtime,
+        mut client,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], _| {
+        respond_startup(name, cmd)?;
+        if contains_slice(cmd, b"big") {
+            return Err(Ok(Value::Bulk(vec![Value::Data(vec![b'x'; 100]); 2])));
+        }
+        Err(Ok(Value::Data(b"small".to_vec())))
+    });
+
+    let mut connection = runtime
+        .block_on(
+            client
+                .set_max_reply_size(Some(150))
+                .get_generic_connection::<MockConnection>(),
+        )
+        .unwrap();
+
+    let value = runtime.block_on(
+        cmd("GET")
+            .arg("small")
+            .query_async::<_, String>(&mut connection),
+    );
+    assert_eq!(value, Ok("small".to_string()));
+
+    let err = runtime
+        .block_on(
+            cmd("LRANGE")
+                .arg("big")
+                .arg(0)
+                .arg(-1)
+                .query_async::<_, Vec<String>>(&mut connection),
+        )
+        .unwrap_err();
+    assert_eq!(err.kind(), redis::ErrorKind::ClientError);
+    assert_eq!(err.detail(), Some("200 bytes, at most 150 allowed"));
+}
+
+#[test]
+fn max_in_flight_bytes() {
+    let _ = env_logger::try_init();
+    let name = "max_in_flight_bytes";
+
+    let requests = Arc::new(atomic::AtomicUsize::new(0));
+    let MockEnv {
+        runtime,
+        mut client,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, {
+        let requests = requests.clone();
+        move |cmd: &[u8], _| {
+            respond_startup(name, cmd)?;
+            requests.fetch_add(1, atomic::Ordering::SeqCst);
+            Err(Ok(Value::Okay))
+        }
+    });
+
+    let mut connection = runtime
+        .block_on(
+            client
+                .set_max_in_flight_bytes(Some(16))
+                .get_generic_connection::<MockConnection>(),
+        )
+        .unwrap();
+
+    // "SET" + "key" + "value"
+    let value = runtime.block_on(
+        cmd("SET")
+            .arg("key")
+            .arg("value")
+            .query_async::<_, ()>(&mut connection),
+    );
+    assert_eq!(value, Ok(()));
+    assert_eq!(connection.in_flight_bytes(), 0);
+
+    let err = runtime
+        .block_on(
+            cmd("SET")
+                .arg("key")
+                .arg("a larger value")
+                .query_async::<_, ()>(&mut connection),
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.detail(),
+        Some("0 bytes in flight, sending 20 more would exceed the limit of 16")
+    );
+    assert_eq!(connection.in_flight_bytes(), 0);
+    assert_eq!(requests.load(atomic::Ordering::SeqCst), 1);
+}
+
+#[test]
+fn connection_config() {
+    let _ = env_logger::try_init();
+    let name = "connection_config";
+
+    let setup = Arc::new(Mutex::new(Vec::new()));
+    let MockEnv {
+        runtime,
+        client,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, {
+        let setup = setup.clone();
+        move |cmd: &[u8], port| {
+            if contains_slice(cmd, b"SLOTS") {
+                return Err(Ok(slots_reply(name, &[(0, 16383, 6379, &[6380])])));
+            }
+            respond_startup(name, cmd)?;
+            for command in ["READONLY", "SETNAME"] {
+                if contains_slice(cmd, command.as_bytes()) {
+                    setup.lock().unwrap().push((command, port));
+                    return Err(Ok(Value::Okay));
+                }
+            }
+            Err(Ok(Value::Int(port.into())))
+        }
+    });
+    setup.lock().unwrap().clear();
+
+    let mut connection = runtime
+        .block_on(
+            client.get_generic_connection_with::<MockConnection>(
+                ConnectionConfig::new()
+                    .client_name("reports")
+                    .read_from_replicas(true),
+            ),
+        )
+        .unwrap();
+
+    // Reads go to the replica, writes to the master
+    let value = runtime.block_on(cmd("GET").arg("foo").query_async::<_, u16>(&mut connection));
+    assert_eq!(value, Ok(6380));
+    let value = runtime.block_on(
+        cmd("SET")
+            .arg("foo")
+            .arg("bar")
+            .query_async::<_, u16>(&mut connection),
+    );
+    assert_eq!(value, Ok(6379));
+
+    let mut setup = setup.lock().unwrap().clone();
+    setup.sort();
+    assert_eq!(
+        setup,
+        [
+            ("READONLY", 6379),
+            ("READONLY", 6380),
+            ("SETNAME", 6379),
+            ("SETNAME", 6380)
+        ]
+    );
+}
+
+#[test]
+fn ordered_retries() {
+    let _ = env_logger::try_init();
+    let name = "ordered_retries";
+
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let MockEnv {
+        runtime,
+        client,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, {
+        let received = received.clone();
+        move |cmd: &[u8], _| {
+            respond_startup(name, cmd)?;
+            let args: Vec<String> =
+                redis::from_redis_value(&parse_redis_value(cmd).unwrap()).unwrap();
+            let mut received = received.lock().unwrap();
+            received.push(args[2].clone());
+            // The first command is redirected once
+            if received.len() == 1 {
+                return Err(parse_redis_value(
+                    format!("-MOVED 6918 {}:6379\r\n", name).as_bytes(),
+                ));
+            }
+            Err(Ok(Value::Okay))
+        }
+    });
+    let connection =
+        runtime
+            .block_on(client.get_generic_connection_with::<MockConnection>(
+                ConnectionConfig::new().ordered(true),
+            ))
+            .unwrap();
+
+    let set = |value: &'static str| {
+        let mut connection = connection.clone();
+        async move {
+            cmd("SET")
+                .arg("test")
+                .arg(value)
+                .query_async::<_, ()>(&mut connection)
+                .await
+        }
+    };
+    let (first, second) = runtime.block_on(future::join(set("1"), set("2")));
+    assert_eq!((first, second), (Ok(()), Ok(())));
+    assert_eq!(*received.lock().unwrap(), ["1", "1", "2"]);
+}
+
+#[test]
+fn command_filter() {
+    let _ = env_logger::try_init();
+    let name = "command_filter";
+
+    let MockEnv {
+        runtime,
+        mut client,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], _| {
+        respond_startup(name, cmd)?;
+        assert!(!contains_slice(cmd, b"FLUSHALL"), "FLUSHALL was sent");
+        Err(Ok(Value::Okay))
+    });
+    client.set_command_filter(Some(CommandFilter::Deny(vec!["FLUSHALL".into()])));
+    let mut connection = runtime
+        .block_on(client.get_generic_connection::<MockConnection>())
+        .unwrap();
+
+    let err = runtime
+        .block_on(cmd("flushall").query_async::<_, ()>(&mut connection))
+        .unwrap_err();
+    assert_eq!(err.kind(), redis::ErrorKind::ClientError);
+    assert_eq!(err.detail(), Some("FLUSHALL"));
+
+    let result = runtime.block_on(
+        redis::pipe()
+            .cmd("SET")
+            .arg("test")
+            .arg(1)
+            .cmd("FLUSHALL")
+            .query_async::<_, ()>(&mut connection),
+    );
+    assert!(result.is_err());
+    let result = runtime.block_on(
+        cmd("SET")
+            .arg("test")
+            .arg(1)
+            .query_async::<_, ()>(&mut connection),
+    );
+    assert_eq!(result, Ok(()));
+}
+
+#[test]
+fn key_prefix() {
+    let _ = env_logger::try_init();
+    let name = "key_prefix";
+
+    let MockEnv {
+        runtime,
+        mut client,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], _| {
+        respond_startup(name, cmd)?;
+        if contains_slice(cmd, b"tenant:test") {
+            Err(Ok(Value::Data(b"prefixed".to_vec())))
+        } else {
+            Err(Ok(Value::Data(b"plain".to_vec())))
+        }
+    });
+    client.set_key_prefix("tenant:");
+    let mut connection = runtime
+        .block_on(client.get_generic_connection::<MockConnection>())
+        .unwrap();
+
+    let value = runtime.block_on(
+        cmd("GET")
+            .arg("test")
+            .query_async::<_, Option<String>>(&mut connection),
+    );
+    assert_eq!(value, Ok(Some("prefixed".to_string())));
+
+    let value = runtime.block_on(
+        redis::pipe()
+            .atomic()
+            .cmd("GET")
+            .arg("test")
+            .cmd("ECHO")
+            .arg("test")
+            .query_async::<_, (Option<String>, String)>(&mut connection),
+    );
+    assert_eq!(
+        value,
+        Ok((Some("prefixed".to_string()), "plain".to_string()))
+    );
+
+    let values = runtime.block_on(connection.cluster_mget::<_, String>(&["test"]));
+    assert_eq!(values, Ok(vec![Some("prefixed".to_string())]));
+}
+
+#[test]
+fn hash_tag_rules() {
+    let _ = env_logger::try_init();
+    let name = "hash_tag_rules";
+
+    let mgets = Arc::new(atomic::AtomicUsize::new(0));
+    let mgets2 = mgets.clone();
+    let MockEnv {
+        runtime,
+        mut client,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], _| {
+        respond_startup(name, cmd)?;
+        if contains_slice(cmd, b"MGET") {
+            mgets2.fetch_add(1, atomic::Ordering::SeqCst);
+            assert!(contains_slice(cmd, b"{test:1}:a") && contains_slice(cmd, b"{test:1}:b"));
+            return Err(Ok(Value::Bulk(vec![
+                Value::Data(b"a".to_vec()),
+                Value::Data(b"b".to_vec()),
+            ])));
+        }
+        assert!(contains_slice(cmd, b"{test:1}:a"));
+        Err(Ok(Value::Okay))
+    });
+    client.set_hash_tag_rules(vec![HashTagRule::new("test:*").segments(2)]);
+    let mut connection = runtime
+        .block_on(client.get_generic_connection::<MockConnection>())
+        .unwrap();
+
+    let result = runtime.block_on(
+        cmd("SET")
+            .arg("test:1:a")
+            .arg(1)
+            .query_async::<_, ()>(&mut connection),
+    );
+    assert_eq!(result, Ok(()));
+
+    let values = runtime.block_on(connection.cluster_mget::<_, String>(&["test:1:a", "test:1:b"]));
+    assert_eq!(
+        values,
+        Ok(vec![Some("a".to_string()), Some("b".to_string())])
+    );
+    assert_eq!(mgets.load(atomic::Ordering::SeqCst), 1);
+}
+
+#[test]
+fn pause_and_resume() {
+    let _ = env_logger::try_init();
+    let name = "pause_and_resume";
+
+    let gets = Arc::new(atomic::AtomicUsize::new(0));
+    let gets2 = gets.clone();
+    let MockEnv {
+        runtime,
+        connection,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], _| {
+        respond_startup(name, cmd)?;
+        gets2.fetch_add(1, atomic::Ordering::SeqCst);
+        Err(Ok(Value::Data(b"123".to_vec())))
+    });
+
+    connection.pause(PausePolicy::Queue);
+    let mut held = connection.clone();
+    let get = runtime.spawn(async move {
+        cmd("GET")
+            .arg("test")
+            .query_async::<_, i32>(&mut held)
+            .await
+    });
+    runtime.block_on(async { tokio::time::sleep(Duration::from_millis(10)).await });
+    assert_eq!(gets.load(atomic::Ordering::SeqCst), 0);
+
+    connection.pause(PausePolicy::Reject);
+    let mut rejected = connection.clone();
+    let err = runtime
+        .block_on(cmd("GET").arg("test").query_async::<_, i32>(&mut rejected))
+        .unwrap_err();
+    assert_eq!(err.kind(), redis::ErrorKind::ClientError);
+
+    connection.resume();
+    assert_eq!(runtime.block_on(get).unwrap(), Ok(123));
+    assert_eq!(gets.load(atomic::Ordering::SeqCst), 1);
+}
+
+#[test]
+fn correlation_id() {
+    let _ = env_logger::try_init();
+    let name = "correlation_id";
+
+    let MockEnv {
+        runtime,
+        mut client,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], _| {
+        respond_startup(name, cmd)?;
+        Err(parse_redis_value(b"-TRYAGAIN mock\r\n"))
+    });
+    let connection = runtime
+        .block_on(
+            client
+                .set_retries(Some(0))
+                .get_generic_connection::<MockConnection>(),
+        )
+        .unwrap();
+
+    let mut tagged = connection.with_correlation_id("4f2a");
+    let err = runtime
+        .block_on(cmd("GET").arg("test").query_async::<_, i32>(&mut tagged))
+        .unwrap_err();
+    assert!(
+        err.to_string().ends_with("command GET, request 4f2a)"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn cluster_scan() {
+    use futures::StreamExt;
+
+    let _ = env_logger::try_init();
+    let name = "cluster_scan";
+
+    let reply = |cursor: &str, key: &str| {
+        Value::Bulk(vec![
+            Value::Data(cursor.as_bytes().to_vec()),
+            Value::Bulk(vec![Value::Data(key.as_bytes().to_vec())]),
+        ])
+    };
+    let moved = Arc::new(atomic::AtomicBool::new(false));
+    let MockEnv {
+        runtime,
+        mut connection,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, {
+        let moved = moved.clone();
+        move |cmd: &[u8], port| {
+            // 6381 replaces 6380 once it answered `MOVED`
+            if contains_slice(cmd, b"SLOTS") {
+                let second = if moved.load(atomic::Ordering::SeqCst) {
+                    6381
+                } else {
+                    6380
+                };
+                return Err(Ok(slots_reply(
+                    name,
+                    &[(0, 8191, 6379, &[]), (8192, 16383, second, &[])],
+                )));
+            }
+            respond_startup(name, cmd)?;
+            if contains_slice(cmd, b"SCAN") {
+                return Err(Ok(match (port, contains_slice(cmd, b"$1\r\n0\r\n")) {
+                    (6379, true) => reply("7", "a"),
+                    (6379, false) => reply("0", "b"),
+                    (6380, _) => reply("0", "c"),
+                    _ => reply("0", "d"),
+                }));
+            }
+            if port == 6380 && !moved.swap(true, atomic::Ordering::SeqCst) {
+                return Err(parse_redis_value(
+                    format!("-MOVED 12182 {}:6381\r\n", name).as_bytes(),
+                ));
+            }
+            Err(Ok(Value::Nil))
+        }
+    });
+
+    let keys = runtime.block_on(async {
+        let mut keys = connection.scan::<String>();
+        let mut scanned = vec![keys.next().await.unwrap().unwrap()];
+        // The topology changes while 6379 is scanned
+        let _: Option<String> = cmd("GET")
+            .arg("foo")
+            .query_async(&mut connection)
+            .await
+            .unwrap();
+        while let Some(key) = keys.next().await {
+            scanned.push(key.unwrap());
+        }
+        scanned
+    });
+    assert_eq!(keys, ["a", "b", "d"]);
+}
+
+#[test]
+fn ttl_audit() {
+    use futures::StreamExt;
+
+    let _ = env_logger::try_init();
+    let name = "ttl_audit";
+
+    let keys = |keys: &[&str]| {
+        Value::Bulk(
+            keys.iter()
+                .map(|key| Value::Data(key.as_bytes().to_vec()))
+                .collect(),
+        )
+    };
+    let MockEnv {
+        runtime,
+        connection,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], port| {
+        if contains_slice(cmd, b"SLOTS") {
+            return Err(Ok(slots_reply(
+                name,
+                &[(0, 8191, 6379, &[]), (8192, 16383, 6380, &[])],
+            )));
+        }
+        respond_startup(name, cmd)?;
+        if contains_slice(cmd, b"SCAN") {
+            assert!(contains_slice(cmd, b"$7\r\ncache:*\r\n"));
+            return Err(Ok(match (port, contains_slice(cmd, b"$1\r\n0\r\n")) {
+                (6379, true) => Value::Bulk(vec![Value::Data(b"5".to_vec()), keys(&["a", "b"])]),
+                (6379, false) => Value::Bulk(vec![Value::Data(b"0".to_vec()), keys(&["c"])]),
+                _ => Value::Bulk(vec![Value::Data(b"0".to_vec()), keys(&["d", "e"])]),
+            }));
+        }
+        if contains_slice(cmd, b"PTTL") {
+            let ttl = match cmd[cmd.len() - 3] {
+                b'a' => -1,
+                b'b' => 5_000,
+                b'c' => -2,
+                b'd' => 100_000_000,
+                _ => 60_000,
+            };
+            return Err(Ok(Value::Int(ttl)));
+        }
+        Err(Ok(Value::Nil))
+    });
+
+    let keys = runtime.block_on(async {
+        connection
+            .ttl_audit(
+                TtlAudit::new("cache:*")
+                    .max_ttl(Duration::from_secs(3600))
+                    .batch_size(10)
+                    .keys_per_second(1000),
+            )
+            .collect::<Vec<_>>()
+            .await
+    });
+    assert_eq!(
+        keys,
+        vec![
+            Ok(KeyTtl {
+                node: format!("{}:6379", name),
+                key: b"a".to_vec(),
+                ttl: None,
+            }),
+            Ok(KeyTtl {
+                node: format!("{}:6380", name),
+                key: b"d".to_vec(),
+                ttl: Some(Duration::from_secs(100_000)),
+            }),
+        ]
+    );
+}
+
+#[test]
+fn encoding_audit() {
+    let _ = env_logger::try_init();
+    let name = "encoding_audit";
+
+    let keys = |keys: &[&str]| {
+        Value::Bulk(
+            keys.iter()
+                .map(|key| Value::Data(key.as_bytes().to_vec()))
+                .collect(),
+        )
+    };
+    let MockEnv {
+        runtime,
+        connection,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], port| {
+        if contains_slice(cmd, b"SLOTS") {
+            return Err(Ok(slots_reply(
+                name,
+                &[(0, 8191, 6379, &[]), (8192, 16383, 6380, &[])],
+            )));
+        }
+        respond_startup(name, cmd)?;
+        if contains_slice(cmd, b"SCAN") {
+            let found = match port {
+                6379 => keys(&["h1", "h2", "h3"]),
+                _ => keys(&["z1", "s1"]),
+            };
+            return Err(Ok(Value::Bulk(vec![Value::Data(b"0".to_vec()), found])));
+        }
+        let key = &cmd[cmd.len() - 4..cmd.len() - 2];
+        if contains_slice(cmd, b"TYPE") {
+            let key_type = match key[0] {
+                b'h' => "hash",
+                b'z' => "zset",
+                _ => "none",
+            };
+            return Err(Ok(Value::Status(key_type.to_string())));
+        }
+        if contains_slice(cmd, b"ENCODING") {
+            return Err(Ok(match key {
+                b"h1" | b"h3" => Value::Data(b"listpack".to_vec()),
+                b"h2" => Value::Data(b"hashtable".to_vec()),
+                b"z1" => Value::Data(b"skiplist".to_vec()),
+                _ => Value::Nil,
+            }));
+        }
+        Err(Ok(Value::Nil))
+    });
+
+    let report = runtime
+        .block_on(connection.encoding_audit(EncodingAudit::new("*").sample_size(4)))
+        .unwrap();
+    assert_eq!(report.sampled, 3);
+    assert_eq!(report.encodings["hash"].len(), 2);
+    assert_eq!(report.encodings["zset"]["skiplist"], 1);
+    assert_eq!(report.share("hash", "hashtable"), Some(0.5));
+    assert_eq!(report.share("list", "quicklist"), None);
+}
+
+#[test]
+fn delete_pattern() {
+    let _ = env_logger::try_init();
+    let name = "delete_pattern";
+
+    let unlinked = Arc::new(Mutex::new(Vec::new()));
+    let MockEnv {
+        runtime,
+        connection,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, {
+        let unlinked = unlinked.clone();
+        move |cmd: &[u8], port| {
+            if contains_slice(cmd, b"SLOTS") {
+                return Err(Ok(slots_reply(
+                    name,
+                    &[(0, 8191, 6379, &[]), (8192, 16383, 6380, &[])],
+                )));
+            }
+            respond_startup(name, cmd)?;
+            let args: Vec<String> =
+                redis::from_redis_value(&parse_redis_value(cmd).unwrap()).unwrap();
+            match &args[0][..] {
+                "SCAN" => {
+                    let keys = match port {
+                        6379 => vec!["bar", "baz"],
+                        _ => vec!["foo", "qux", "{foo}1"],
+                    };
+                    Err(Ok(Value::Bulk(vec![
+                        Value::Data(b"0".to_vec()),
+                        Value::Bulk(
+                            keys.into_iter()
+                                .map(|key| Value::Data(key.as_bytes().to_vec()))
+                                .collect(),
+                        ),
+                    ])))
+                }
+                "UNLINK" => {
+                    unlinked.lock().unwrap().push((port, args[1..].join(" ")));
+                    Err(Ok(Value::Int(args.len() as i64 - 1)))
+                }
+                _ => panic!("Unexpected command {:?}", args),
+            }
+        }
+    });
+
+    assert_eq!(
+        runtime.block_on(connection.delete_pattern("*", None)),
+        Ok(5)
+    );
+    let mut unlinked = unlinked.lock().unwrap().drain(..).collect::<Vec<_>>();
+    unlinked.sort();
+    assert_eq!(
+        unlinked,
+        [
+            (6379, "bar".to_string()),
+            (6379, "baz".to_string()),
+            (6380, "foo {foo}1".to_string()),
+            (6380, "qux".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn chunked_values() {
+    use futures::io::{AsyncReadExt, AsyncWriteExt};
+
+    let _ = env_logger::try_init();
+    let name = "chunked_values";
+
+    let values = Arc::new(Mutex::new(HashMap::<Vec<u8>, Vec<u8>>::new()));
+    let commands = Arc::new(atomic::AtomicUsize::new(0));
+    let MockEnv {
+        runtime,
+        connection,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, {
+        let values = values.clone();
+        let commands = commands.clone();
+        move |cmd: &[u8], _| {
+            respond_startup(name, cmd)?;
+            commands.fetch_add(1, atomic::Ordering::SeqCst);
+            let args: Vec<Vec<u8>> =
+                redis::from_redis_value(&parse_redis_value(cmd).unwrap()).unwrap();
+            let mut values = values.lock().unwrap();
+            match &args[0][..] {
+                b"SET" => {
+                    values.insert(args[1].clone(), args[2].clone());
+                    Err(Ok(Value::Okay))
+                }
+                b"APPEND" => {
+                    let value = values.entry(args[1].clone()).or_default();
+                    value.extend_from_slice(&args[2]);
+                    Err(Ok(Value::Int(value.len() as i64)))
+                }
+                b"GETRANGE" => {
+                    let value = values.get(&args[1]).cloned().unwrap_or_default();
+                    let bound =
+                        |arg: &[u8]| std::str::from_utf8(arg).unwrap().parse::<usize>().unwrap();
+                    let start = bound(&args[2]).min(value.len());
+                    let end = (bound(&args[3]) + 1).min(value.len());
+                    Err(Ok(Value::Data(value[start..end].to_vec())))
+                }
+                _ => panic!("Unexpected command {:?}", args),
+            }
+        }
+    });
+
+    let blob = (0..250u32).map(|i| i as u8).collect::<Vec<_>>();
+    runtime.block_on(async {
+        let mut writer = connection.value_writer("blob").chunk_size(100);
+        writer.write_all(&blob[..30]).await.unwrap();
+        writer.write_all(&blob[30..]).await.unwrap();
+        writer.close().await.unwrap();
+    });
+    assert_eq!(values.lock().unwrap()[&b"blob"[..]], blob);
+    // One `SET` and two `APPEND`
+    assert_eq!(commands.swap(0, atomic::Ordering::SeqCst), 3);
+
+    let read = runtime.block_on(async {
+        let mut read = Vec::new();
+        let mut reader = connection.value_reader("blob").chunk_size(100);
+        reader.read_to_end(&mut read).await.unwrap();
+        read
+    });
+    assert_eq!(read, blob);
+    assert_eq!(commands.swap(0, atomic::Ordering::SeqCst), 3);
+
+    // Closing a writer with nothing written stores an empty value
+    runtime.block_on(async {
+        connection.value_writer("blob").close().await.unwrap();
+    });
+    assert!(values.lock().unwrap()[&b"blob"[..]].is_empty());
+}
+
+#[test]
+fn dual_write() {
+    let _ = env_logger::try_init();
+    let old_name = "dual_write_old";
+    let new_name = "dual_write_new";
+
+    let writes = Arc::new(Mutex::new(Vec::new()));
+    let handler = |name: &'static str, writes: Arc<Mutex<Vec<&'static str>>>| {
+        move |cmd: &[u8], _| {
+            respond_startup(name, cmd)?;
+            if contains_slice(cmd, b"SET") {
+                writes.lock().unwrap().push(name);
+                if name == old_name && contains_slice(cmd, b"bad") {
+                    return Err(parse_redis_value(b"-ERR failed\r\n"));
+                }
+                return Err(Ok(Value::Okay));
+            }
+            if name == old_name && contains_slice(cmd, b"migrated") {
+                return Err(Ok(Value::Nil));
+            }
+            Err(Ok(Value::Data(name.as_bytes().to_vec())))
+        }
+    };
+    let MockEnv {
+        runtime,
+        connection: old,
+        handler: _handler,
+        ..
+    } = MockEnv::new(old_name, handler(old_name, writes.clone()));
+    let new_env = MockEnv::new(new_name, handler(new_name, writes.clone()));
+    // Drive both connections on the same runtime
+    let new = runtime
+        .block_on(new_env.client.get_generic_connection::<MockConnection>())
+        .unwrap();
+
+    let mut connection = DualWriteConnection::new(old.clone(), new.clone());
+    let set = |connection: &mut DualWriteConnection<MockConnection>, key| {
+        runtime.block_on(
+            cmd("SET")
+                .arg(key)
+                .arg("value")
+                .query_async::<_, ()>(connection),
+        )
+    };
+    assert_eq!(set(&mut connection, "test"), Ok(()));
+    let mut written = writes.lock().unwrap().drain(..).collect::<Vec<_>>();
+    written.sort();
+    assert_eq!(written, [new_name, old_name]);
+    assert!(set(&mut connection, "bad").is_err());
+
+    let mut connection = connection
+        .read_from(Cluster::New)
+        .write_errors(WriteErrors::ReadCluster);
+    assert_eq!(set(&mut connection, "bad"), Ok(()));
+
+    // Reads fall back to the other cluster when the key is missing
+    let mut connection = DualWriteConnection::new(old, new);
+    let mut get = |key| {
+        runtime.block_on(
+            cmd("GET")
+                .arg(key)
+                .query_async::<_, String>(&mut connection),
+        )
+    };
+    assert_eq!(get("test"), Ok(old_name.to_string()));
+    assert_eq!(get("migrated"), Ok(new_name.to_string()));
+}
+
+#[test]
+fn mirroring() {
+    let _ = env_logger::try_init();
+    let name = "mirroring";
+    let shadow_name = "mirroring_shadow";
+
+    let mirrored = Arc::new(Mutex::new(Vec::new()));
+    let shadow = MockEnv::new(shadow_name, {
+        let mirrored = mirrored.clone();
+        move |cmd: &[u8], _| {
+            respond_startup(shadow_name, cmd)?;
+            mirrored.lock().unwrap().push(cmd.to_vec());
+            // The replies of the shadow cluster are ignored
+            Err(parse_redis_value(b"-ERR shadow\r\n"))
+        }
+    });
+    let MockEnv {
+        runtime,
+        mut client,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], _| {
+        respond_startup(name, cmd)?;
+        Err(Ok(Value::Okay))
+    });
+
+    client.set_mirror(shadow.client.clone(), 1.0);
+    let mut connection = runtime
+        .block_on(client.get_generic_connection::<MockConnection>())
+        .unwrap();
+    let result = runtime.block_on(async {
+        let result = cmd("SET")
+            .arg("test")
+            .arg("value")
+            .query_async::<_, ()>(&mut connection)
+            .await;
+        // Let the mirrored command reach the shadow cluster
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        result
+    });
+    assert_eq!(result, Ok(()));
+    assert_eq!(
+        *mirrored.lock().unwrap(),
+        [cmd("SET").arg("test").arg("value").get_packed_command()]
+    );
+
+    // Commands for a given node are not mirrored, nor any command with a fraction of 0
+    mirrored.lock().unwrap().clear();
+    runtime
+        .block_on(connection.send_raw(&format!("{}:6379", name), b"*1\r\n$3\r\nGET\r\n"))
+        .unwrap();
+    client.set_mirror(shadow.client.clone(), 0.0);
+    let mut connection = runtime
+        .block_on(client.get_generic_connection::<MockConnection>())
+        .unwrap();
+    runtime.block_on(async {
+        cmd("SET")
+            .arg("test")
+            .arg("value")
+            .query_async::<_, ()>(&mut connection)
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    });
+    assert!(mirrored.lock().unwrap().is_empty());
+}
+
+#[test]
+fn sticky_reads() {
+    let _ = env_logger::try_init();
+    let name = "sticky_reads";
+
+    let fail_port = Arc::new(Mutex::new(None));
+    let MockEnv {
+        runtime,
+        client,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, {
+        let fail_port = fail_port.clone();
+        move |cmd: &[u8], port| {
+            if contains_slice(cmd, b"SLOTS") {
+                return Err(Ok(slots_reply(name, &[(0, 16383, 6379, &[6380, 6381])])));
+            }
+            respond_startup(name, cmd)?;
+            if contains_slice(cmd, b"READONLY") {
+                return Err(Ok(Value::Okay));
+            }
+            if fail_port
+                .lock()
+                .unwrap()
+                .take_if(|fail| *fail == port)
+                .is_some()
+            {
+                return Err(parse_redis_value(b"-ERR failed\r\n"));
+            }
+            Err(Ok(Value::Int(port.into())))
+        }
+    });
+    let connection = runtime
+        .block_on(client.get_generic_connection_with::<MockConnection>(
+            ConnectionConfig::new().read_from_replicas(true),
+        ))
+        .unwrap();
+    let mut sticky = connection.sticky();
+    let mut reads = |count| {
+        let mut ports = (0..count)
+            .map(|_| {
+                runtime
+                    .block_on(cmd("GET").arg("foo").query_async::<_, u16>(&mut sticky))
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        ports.dedup();
+        ports
+    };
+
+    let pinned = reads(20);
+    assert_eq!(pinned.len(), 1);
+
+    // A failure on the pinned node moves the slot to another node, where it stays
+    *fail_port.lock().unwrap() = Some(pinned[0]);
+    let moved = reads(1);
+    assert_ne!(moved, pinned);
+    assert_eq!(reads(20).len(), 1);
+}
+
+#[test]
+fn verified_reads() {
+    let _ = env_logger::try_init();
+    let name = "verified_reads";
+
+    let MockEnv {
+        runtime,
+        connection,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], port| {
+        if contains_slice(cmd, b"SLOTS") {
+            return Err(Ok(slots_reply(name, &[(0, 16383, 6379, &[6380])])));
+        }
+        respond_startup(name, cmd)?;
+        if contains_slice(cmd, b"READONLY") {
+            return Err(Ok(Value::Okay));
+        }
+        match (port, contains_slice(cmd, b"lagging")) {
+            (6380, true) => Err(Ok(Value::Data(b"old".to_vec()))),
+            _ => Err(Ok(Value::Data(b"new".to_vec()))),
+        }
+    });
+
+    let divergences = Arc::new(Mutex::new(Vec::new()));
+    let verification = ReadVerification::new({
+        let divergences = divergences.clone();
+        move |divergence| divergences.lock().unwrap().push(divergence.clone())
+    });
+
+    let value = runtime
+        .block_on(connection.verified_query::<String>(cmd("GET").arg("synced"), &verification));
+    assert_eq!(value, Ok("new".to_string()));
+    assert!(divergences.lock().unwrap().is_empty());
+
+    let value = runtime
+        .block_on(connection.verified_query::<String>(cmd("GET").arg("lagging"), &verification));
+    assert_eq!(value, Ok("new".to_string()));
+    assert_eq!(
+        *divergences.lock().unwrap(),
+        [Divergence {
+            command: "GET".into(),
+            slot: key_slot(b"lagging"),
+            first: (format!("{}:6379", name), Value::Data(b"new".to_vec())),
+            second: (format!("{}:6380", name), Value::Data(b"old".to_vec())),
+        }]
+    );
+
+    // Writes are rejected, as are slots without enough replicas
+    let err = runtime
+        .block_on(connection.verified_query::<()>(cmd("SET").arg("lagging").arg(1), &verification))
+        .unwrap_err();
+    assert_eq!(err.kind(), redis::ErrorKind::ClientError);
+    let err = runtime
+        .block_on(connection.verified_query::<String>(
+            cmd("GET").arg("lagging"),
+            &verification.clone().replicas_only(true),
+        ))
+        .unwrap_err();
+    assert_eq!(err.kind(), redis::ErrorKind::ClientError);
+}
+
+#[test]
+fn read_weights() {
+    let _ = env_logger::try_init();
+    let name = "read_weights";
+
+    let MockEnv {
+        runtime,
+        mut client,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], port| {
+        if contains_slice(cmd, b"SLOTS") {
+            return Err(Ok(slots_reply(name, &[(0, 16383, 6379, &[6380, 6381])])));
+        }
+        respond_startup(name, cmd)?;
+        if contains_slice(cmd, b"READONLY") {
+            return Err(Ok(Value::Okay));
+        }
+        Err(Ok(Value::Int(port.into())))
+    });
+
+    let mut reads = |weights| {
+        client.set_read_weights(Some(weights));
+        let mut connection = runtime
+            .block_on(client.get_generic_connection_with::<MockConnection>(
+                ConnectionConfig::new().read_from_replicas(true),
+            ))
+            .unwrap();
+        let mut ports = (0..20)
+            .map(|_| {
+                runtime
+                    .block_on(cmd("GET").arg("foo").query_async::<_, u16>(&mut connection))
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        ports.sort();
+        ports.dedup();
+        ports
+    };
+
+    assert_eq!(reads(ReadWeights::new().master(1).replica(0)), [6379]);
+    assert_eq!(
+        reads(
+            ReadWeights::new()
+                .node(format!("{}:6381", name), 1)
+                .replica(0)
+        ),
+        [6381]
+    );
+}
+
+#[test]
+fn read_strategies() {
+    let _ = env_logger::try_init();
+    let name = "read_strategies";
+
+    let failing = Arc::new(atomic::AtomicBool::new(false));
+    let MockEnv {
+        runtime,
+        mut client,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, {
+        let failing = failing.clone();
+        move |cmd: &[u8], port| {
+            if contains_slice(cmd, b"SLOTS") {
+                return Err(Ok(slots_reply(name, &[(0, 16383, 6379, &[6380, 6381])])));
+            }
+            respond_startup(name, cmd)?;
+            if contains_slice(cmd, b"READONLY") {
+                return Err(Ok(Value::Okay));
+            }
+            if port != 6379 && failing.load(atomic::Ordering::SeqCst) {
+                return Err(parse_redis_value(b"-ERR replica failed\r\n"));
+            }
+            Err(Ok(Value::Int(port.into())))
+        }
+    });
+
+    let mut reads = |strategy, count| {
+        client.set_read_from_replicas(strategy);
+        let mut connection = runtime
+            .block_on(client.get_generic_connection::<MockConnection>())
+            .unwrap();
+        (0..count)
+            .map(|_| {
+                runtime
+                    .block_on(cmd("GET").arg("foo").query_async::<_, u16>(&mut connection))
+                    .unwrap()
+            })
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        reads(ReadFromReplicaStrategy::MasterOnly, 4),
+        [6379, 6379, 6379, 6379]
+    );
+    let rotated = reads(ReadFromReplicaStrategy::RoundRobin, 4);
+    assert_ne!(rotated[0], rotated[1]);
+    assert_eq!(rotated[0], rotated[2]);
+    assert_eq!(rotated[1], rotated[3]);
+    assert!(rotated.iter().all(|port| *port == 6380 || *port == 6381));
+
+    // Reads failing on a replica are answered by the master
+    failing.store(true, atomic::Ordering::SeqCst);
+    assert_eq!(
+        reads(ReadFromReplicaStrategy::PreferReplica, 2),
+        [6379, 6379]
+    );
+}
+
+#[test]
+fn read_only_transactions() {
+    let _ = env_logger::try_init();
+    let name = "read_only_transactions";
+
+    let MockEnv {
+        runtime,
+        client,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], port| {
+        if contains_slice(cmd, b"SLOTS") {
+            return Err(Ok(slots_reply(name, &[(0, 16383, 6379, &[6380])])));
+        }
+        respond_startup(name, cmd)?;
+        if contains_slice(cmd, b"READONLY") {
+            return Err(Ok(Value::Okay));
+        }
+        Err(Ok(Value::Int(port.into())))
+    });
+    let mut connection = runtime
+        .block_on(client.get_generic_connection_with::<MockConnection>(
+            ConnectionConfig::new().read_from_replicas(true),
+        ))
+        .unwrap();
+
+    let value = runtime.block_on(
+        cmd("EVAL_RO")
+            .arg("return redis.call('GET', KEYS[1])")
+            .arg(1)
+            .arg("foo")
+            .query_async::<_, u16>(&mut connection),
+    );
+    assert_eq!(value, Ok(6380));
+
+    let value = runtime.block_on(
+        redis::pipe()
+            .atomic()
+            .cmd("GET")
+            .arg("{foo}1")
+            .cmd("GET")
+            .arg("{foo}2")
+            .query_async::<_, (u16, u16)>(&mut connection),
+    );
+    assert_eq!(value, Ok((6380, 6380)));
+
+    // A single write sends the whole transaction to the master
+    let value = runtime.block_on(
+        redis::pipe()
+            .atomic()
+            .cmd("GET")
+            .arg("{foo}1")
+            .cmd("INCR")
+            .arg("{foo}2")
+            .query_async::<_, (u16, u16)>(&mut connection),
+    );
+    assert_eq!(value, Ok((6379, 6379)));
+}
+
+#[cfg(feature = "fault-injection")]
+#[test]
+fn response_timeout() {
+    use redis_cluster_async::fault::{self, Fault, FaultRule, FaultyConnection};
+
+    let _ = env_logger::try_init();
+    let name = "response_timeout";
+
+    let MockEnv {
+        runtime,
+        client,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], _| {
+        respond_startup(name, cmd)?;
+        Err(Ok(Value::Okay))
+    });
+    let mut connection = runtime
+        .block_on(
+            client.get_generic_connection_with::<FaultyConnection<MockConnection>>(
+                ConnectionConfig::new().response_timeout(Duration::from_millis(10)),
+            ),
+        )
+        .unwrap();
+
+    fault::inject(
+        FaultRule::new(Fault::Delay(Duration::from_secs(10)))
+            .node(format!("{}:6379", name))
+            .command("SET")
+            .times(1),
+    );
+    let result = runtime.block_on(
+        cmd("SET")
+            .arg("foo")
+            .arg("bar")
+            .query_async::<_, ()>(&mut connection),
+    );
+    assert_eq!(
+        result.map_err(|err| err.kind()),
+        Err(redis::ErrorKind::IoError)
+    );
+}
+
+#[cfg(feature = "failpoints")]
+#[test]
+fn failpoints() {
+    use redis_cluster_async::failpoints;
+
+    let _ = env_logger::try_init();
+    let name = "failpoints";
+
+    let MockEnv {
+        runtime,
+        mut client,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], _| {
+        respond_startup(name, cmd)?;
+        Err(Ok(Value::Data(b"123".to_vec())))
+    });
+
+    let mut connection = runtime
+        .block_on(
+            client
+                .set_retries(Some(1))
+                .get_generic_connection::<MockConnection>(),
+        )
+        .unwrap();
+
+    // Only the nodes of this test are redirected
+    fail::cfg(failpoints::REDIRECT, "return(failpoints:)").unwrap();
+    let err = runtime
+        .block_on(
+            cmd("GET")
+                .arg("test")
+                .query_async::<_, Option<i32>>(&mut connection),
+        )
+        .unwrap_err();
+    assert_eq!(err.kind(), redis::ErrorKind::Moved);
+
+    fail::remove(failpoints::REDIRECT);
+    let value = runtime.block_on(
+        cmd("GET")
+            .arg("test")
+            .query_async::<_, Option<i32>>(&mut connection),
+    );
+    assert_eq!(value, Ok(Some(123)));
+}
+
+#[test]
+fn read_only_connection() {
+    let _ = env_logger::try_init();
+    let name = "read_only_connection";
+
+    let MockEnv {
+        runtime,
+        client,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], port| {
+        if contains_slice(cmd, b"SLOTS") {
+            return Err(Ok(slots_reply(name, &[(0, 16383, 6379, &[6380])])));
+        }
+        respond_startup(name, cmd)?;
+        if contains_slice(cmd, b"SET") {
+            panic!("Writes must not be sent");
+        }
+        Err(Ok(Value::Int(port.into())))
+    });
+    let mut connection =
+        runtime
+            .block_on(client.get_generic_connection_with::<MockConnection>(
+                ConnectionConfig::new().read_only(true),
+            ))
+            .unwrap();
+
+    let value = runtime.block_on(cmd("GET").arg("foo").query_async::<_, u16>(&mut connection));
+    assert_eq!(value, Ok(6380));
+    let err = runtime
+        .block_on(
+            cmd("SET")
+                .arg("foo")
+                .arg("bar")
+                .query_async::<_, ()>(&mut connection),
+        )
+        .unwrap_err();
+    assert_eq!(err.kind(), redis::ErrorKind::ClientError);
+}
+
+#[test]
+fn blocking_commands_use_own_connection() {
+    let _ = env_logger::try_init();
+    let name = "blocking_commands_use_own_connection";
+
+    let pings = Arc::new(atomic::AtomicUsize::new(0));
+    let MockEnv {
+        runtime,
+        mut connection,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, {
+        let pings = pings.clone();
+        move |cmd: &[u8], _| {
+            if contains_slice(cmd, b"PING") {
+                pings.fetch_add(1, atomic::Ordering::SeqCst);
+            }
+            respond_startup(name, cmd)?;
+            Err(Ok(Value::Nil))
+        }
+    });
+    let connects = || pings.load(atomic::Ordering::SeqCst);
+
+    let before = connects();
+    runtime
+        .block_on(cmd("GET").arg("foo").query_async::<_, ()>(&mut connection))
+        .unwrap();
+    assert_eq!(connects(), before);
+
+    runtime
+        .block_on(
+            cmd("BLPOP")
+                .arg("foo")
+                .arg(1)
+                .query_async::<_, ()>(&mut connection),
+        )
+        .unwrap();
+    assert_eq!(connects(), before + 1);
+}
+
+#[cfg(feature = "tls")]
+#[test]
+fn tls_discovered_nodes() {
+    let _ = env_logger::try_init();
+    let name = "tls_discovered_nodes";
+
+    let MockEnv {
+        runtime,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, move |cmd: &[u8], port| {
+        if contains_slice(cmd, b"SLOTS") {
+            return Err(Ok(slots_reply(
+                name,
+                &[(0, 8191, 6379, &[]), (8192, 16383, 6380, &[])],
+            )));
+        }
+        respond_startup(name, cmd)?;
+        Err(Ok(Value::Data(port.to_string().into_bytes())))
+    });
+
+    let mut client = Client::open(vec![format!("redis://{}", name)]).unwrap();
+    client.set_tls(redis_cluster_async::TlsMode::Insecure);
+    let mut connection = runtime
+        .block_on(client.get_generic_connection::<MockConnection>())
+        .unwrap();
+    let port = runtime.block_on(cmd("GET").arg("foo").query_async::<_, u16>(&mut connection));
+    assert_eq!(port, Ok(6380));
+
+    let connections = TLS_CONNECTIONS.lock().unwrap();
+    for port in [6379, 6380] {
+        assert!(
+            connections.contains(&(name.to_string(), port, true)),
+            "{:?}",
+            connections
+        );
+    }
+}
+
+#[test]
+fn transaction() {
+    let _ = env_logger::try_init();
+    let name = "transaction";
+
+    let moved = atomic::AtomicBool::new(false);
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let MockEnv {
+        runtime,
+        connection,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, {
+        let sent = sent.clone();
+        move |cmd: &[u8], port| {
+            if contains_slice(cmd, b"SLOTS") && moved.load(atomic::Ordering::SeqCst) {
+                return Err(Ok(slots_reply(
+                    name,
+                    &[(0, 8191, 6379, &[]), (8192, 16383, 6380, &[])],
+                )));
+            }
+            respond_startup(name, cmd)?;
+            if port == 6379 && !moved.swap(true, atomic::Ordering::SeqCst) {
+                return Err(parse_redis_value(
+                    format!("-MOVED 12182 {}:6380\r\n", name).as_bytes(),
+                ));
+            }
+            let args: Vec<String> =
+                redis::from_redis_value(&parse_redis_value(cmd).unwrap()).unwrap();
+            sent.lock().unwrap().push((port, args.join(" ")));
+            if args[0] == "INCRBY" {
+                Err(Ok(Value::Int(2)))
+            } else {
+                Err(Ok(Value::Okay))
+            }
+        }
+    });
+
+    let mut pipeline = redis::pipe();
+    pipeline
+        .set("{foo}:name", "ada")
+        .ignore()
+        .incr("{foo}:visits", 1);
+    let value = runtime.block_on(connection.transaction::<(i64,)>(&pipeline));
+    assert_eq!(value, Ok((2,)));
+    assert_eq!(
+        *sent.lock().unwrap(),
+        [
+            (6380, "SET {foo}:name ada".to_string()),
+            (6380, "INCRBY {foo}:visits 1".to_string())
+        ]
+    );
+
+    let mut pipeline = redis::pipe();
+    pipeline.set("foo", 1).set("bar", 2);
+    let err = runtime
+        .block_on(connection.transaction::<()>(&pipeline))
+        .unwrap_err();
+    assert_eq!(err.kind(), redis::ErrorKind::ClientError);
+    assert_eq!(sent.lock().unwrap().len(), 2);
+}
+
+#[test]
+fn ask_redirection() {
+    let _ = env_logger::try_init();
+    let name = "ask_redirection";
+
+    let refreshes = Arc::new(atomic::AtomicUsize::new(0));
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let MockEnv {
+        runtime,
+        connection,
+        handler: _handler,
+        ..
+    } = MockEnv::new(name, {
+        let (refreshes, sent) = (refreshes.clone(), sent.clone());
+        move |cmd: &[u8], port| {
+            if contains_slice(cmd, b"SLOTS") {
+                refreshes.fetch_add(1, atomic::Ordering::SeqCst);
+            }
+            respond_startup(name, cmd)?;
+            // The slot of `foo` is being migrated from 6379 to 6380, without any key left on 6379
+            if port == 6379 {
+                return Err(parse_redis_value(
+                    format!("-ASK 12182 {}:6380\r\n", name).as_bytes(),
+                ));
+            }
+            let args: Vec<String> =
+                redis::from_redis_value(&parse_redis_value(cmd).unwrap()).unwrap();
+            sent.lock().unwrap().push(args.join(" "));
+            match &args[0][..] {
+                "GET" => Err(Ok(Value::Data(b"1".to_vec()))),
+                "INCRBY" => Err(Ok(Value::Int(2))),
+                "EXEC" => Err(Ok(Value::Bulk(vec![Value::Okay, Value::Int(2)]))),
+                _ => Err(Ok(Value::Okay)),
+            }
+        }
+    });
+    let startup_refreshes = refreshes.load(atomic::Ordering::SeqCst);
+
+    let value = runtime.block_on(
+        cmd("GET")
+            .arg("foo")
+            .query_async::<_, String>(&mut connection.clone()),
+    );
+    assert_eq!(value, Ok("1".to_string()));
+
+    let mut pipeline = redis::pipe();
+    pipeline.set("foo", 1).ignore().incr("foo", 1);
+    let value = runtime.block_on(pipeline.query_async::<_, (i64,)>(&mut connection.clone()));
+    assert_eq!(value, Ok((2,)));
+
+    let mut pipeline = redis::pipe();
+    pipeline
+        .set("{foo}:name", "ada")
+        .ignore()
+        .incr("{foo}:visits", 1);
+    let value = runtime.block_on(connection.transaction::<(i64,)>(&pipeline));
+    assert_eq!(value, Ok((2,)));
+
+    assert_eq!(
+        *sent.lock().unwrap(),
+        [
+            "ASKING",
+            "GET foo",
+            "ASKING",
+            "SET foo 1",
+            "ASKING",
+            "INCRBY foo 1",
+            "ASKING",
+            "MULTI",
+            "SET {foo}:name ada",
+            "INCRBY {foo}:visits 1",
+            "EXEC",
+        ]
+    );
+    // The slot still belongs to 6379 until the migration ends
+    assert_eq!(refreshes.load(atomic::Ordering::SeqCst), startup_refreshes);
+}
+
+#[test]
+fn scripts() {
+    let _ = env_logger::try_init();
+    let name = "scripts";
+
+    let script = redis::Script::new("return redis.call('GET', KEYS[1])");
+    let hash = script.get_hash().to_string();
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let MockEnv {
+        runtime,
+        mut client,
+        connection,
+        handler: _handler,
+    } = MockEnv::new(name, {
+        let (sent, hash) = (sent.clone(), hash.clone());
+        move |cmd: &[u8], port| {
+            if contains_slice(cmd, b"SLOTS") {
+                return Err(Ok(slots_reply(
+                    name,
+                    &[(0, 8191, 6379, &[]), (8192, 16383, 6380, &[])],
+                )));
+            }
+            respond_startup(name, cmd)?;
+            let args: Vec<String> =
+                redis::from_redis_value(&parse_redis_value(cmd).unwrap()).unwrap();
+            sent.lock().unwrap().push((port, args[..2].join(" ")));
+            match &args[0][..] {
+                "SCRIPT" => Err(Ok(Value::Data(hash.as_bytes().to_vec()))),
+                // The script was never loaded on the master of `foo`
+                "EVALSHA" => Err(parse_redis_value(
+                    b"-NOSCRIPT No matching script. Please use EVAL.\r\n",
+                )),
+                _ => Err(Ok(Value::Data(b"bar".to_vec()))),
+            }
+        }
+    });
+    // The commands sent to `port`, once `SCRIPT LOAD` went to a random node
+    let sent_to = |port| {
+        let mut sent = sent.lock().unwrap();
+        let loads = sent.iter().filter(|(_, cmd)| cmd == "SCRIPT LOAD").count();
+        assert_eq!(loads, 1);
+        sent.drain(..)
+            .filter(|(to, cmd)| *to == port && cmd != "SCRIPT LOAD")
+            .map(|(_, cmd)| cmd)
+            .collect::<Vec<_>>()
+    };
+
+    let value = runtime.block_on(
+        script
+            .key("foo")
+            .invoke_async::<_, String>(&mut connection.clone()),
+    );
+    assert_eq!(value, Ok("bar".to_string()));
+    let evalsha = format!("EVALSHA {}", hash);
+    let eval = "EVAL return redis.call('GET', KEYS[1])".to_string();
+    assert_eq!(sent_to(6380), [evalsha.clone(), evalsha.clone(), eval]);
+
+    let err = runtime
+        .block_on(
+            script
+                .key("foo")
+                .key("bar")
+                .invoke_async::<_, String>(&mut connection.clone()),
+        )
+        .unwrap_err();
+    assert_eq!(err.kind(), redis::ErrorKind::ClientError);
+    assert!(sent.lock().unwrap().is_empty());
+
+    client.set_preload_scripts(true);
+    let mut connection = runtime
+        .block_on(client.get_generic_connection::<MockConnection>())
+        .unwrap();
+    let loaded = runtime.block_on(script.prepare_invoke().load_async(&mut connection));
+    assert_eq!(loaded, Ok(hash));
+    let mut loaded_on = sent.lock().unwrap().drain(..).collect::<Vec<_>>();
+    loaded_on.sort();
+    assert_eq!(
+        loaded_on,
+        [
+            (6379, "SCRIPT LOAD".to_string()),
+            (6380, "SCRIPT LOAD".to_string())
+        ]
+    );
+}
+
+#[test]
+fn refresh_slots() {
+    let _ = env_logger::try_init();
+    let name = "refresh_slots";
+
+    let resharded = Arc::new(atomic::AtomicBool::new(false));
+    let refreshes = Arc::new(atomic::AtomicUsize::new(0));
+    let MockEnv {
+        runtime,
+        mut client,
+        connection,
+        handler: _handler,
+    } = MockEnv::new(name, {
+        let (resharded, refreshes) = (resharded.clone(), refreshes.clone());
+        move |cmd: &[u8], port| {
+            if contains_slice(cmd, b"SLOTS") {
+                refreshes.fetch_add(1, atomic::Ordering::SeqCst);
+            }
+            if contains_slice(cmd, b"SLOTS") && resharded.load(atomic::Ordering::SeqCst) {
+                return Err(Ok(slots_reply(
+                    name,
+                    &[(0, 8191, 6379, &[]), (8192, 16383, 6380, &[])],
+                )));
+            }
+            respond_startup(name, cmd)?;
+            if port == 6379 && contains_slice(cmd, b"foo") {
+                return Err(parse_redis_value(
+                    format!("-MOVED 12182 {}:6380\r\n", name).as_bytes(),
+                ));
+            }
+            Err(Ok(Value::Data(port.to_string().into_bytes())))
+        }
+    });
+
+    // The new owner of the slot is known before any command is redirected
+    resharded.store(true, atomic::Ordering::SeqCst);
+    runtime.block_on(connection.refresh_slots()).unwrap();
+    let mut routed = connection.with_routing_key("foo");
+    let port = runtime.block_on(cmd("GET").arg("foo").query_async::<_, u16>(&mut routed));
+    assert_eq!(port, Ok(6380));
+
+    client.set_slots_refresh_interval(Some(Duration::from_millis(10)));
+    let _connection = runtime
+        .block_on(client.get_generic_connection::<MockConnection>())
+        .unwrap();
+    let before = refreshes.load(atomic::Ordering::SeqCst);
+    runtime.block_on(async { tokio::time::sleep(Duration::from_millis(100)).await });
+    assert!(refreshes.load(atomic::Ordering::SeqCst) >= before + 2);
+}